@@ -1,36 +1,50 @@
 pub mod query;
+pub mod registry;
 pub mod types;
 
-use fred::prelude::RedisValue;
+use protocol::db::DatabaseRequests;
 use query::insert_trade;
-use serde_json::from_str;
+use redis::{MessageBus, RedisQueues};
 use sqlx::{Pool, Postgres};
-use types::DatabaseRequests;
+use std::time::Duration;
 
-pub async fn handle_db_updates(data: Vec<RedisValue>, pg_pool: &Pool<Postgres>) {
-    let data_to_process = &data[0];
+pub const BATCH_SIZE: u64 = 100;
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
 
-    // Convert the RedisValue to a string
-    let db_data = match data_to_process {
-        RedisValue::String(s) => s.to_string(),
-        // BYTES TYPE - NOT NEEDED FOR NOW - check if this can make it faster
-        RedisValue::Bytes(b) => String::from_utf8(b.to_vec()).unwrap_or_else(|_| "".to_string()),
-        _ => {
-            println!("Unexpected Redis value type");
-            return;
-        }
-    };
-
-    // Now you can deserialize it using serde_json
-    match from_str::<DatabaseRequests>(&db_data) {
+// An error leaves the message pending, so it is retried and eventually dead-lettered
+pub async fn handle_db_updates(db_data: &str, pg_pool: &Pool<Postgres>) -> Result<(), String> {
+    // Decode it, rejecting messages from other protocol versions
+    match protocol::decode::<DatabaseRequests>(db_data) {
         Ok(db_data) => match db_data {
             DatabaseRequests::InsertTrade(db_data) => {
                 println!("Received Trade {:?}", db_data);
-                let _ = insert_trade(pg_pool, db_data).await;
+                insert_trade(pg_pool, db_data)
+                    .await
+                    .map_err(|e| format!("Failed to insert trade - {}", e))
             }
         },
-        Err(err) => {
-            println!("Failed to deserialize db request: {:?}", err);
+        Err(err) => Err(format!("Failed to decode db request: {}", err)),
+    }
+}
+
+pub async fn consume_db_updates(bus: &dyn MessageBus, pg_pool: &Pool<Postgres>) {
+    let queue = RedisQueues::DATABASE;
+
+    loop {
+        match bus.pop(queue, BATCH_SIZE).await {
+            Ok(messages) => {
+                for message in messages {
+                    // Only acknowledged once written, failed messages are redelivered later
+                    match handle_db_updates(&message.data, pg_pool).await {
+                        Ok(()) => bus.ack(queue, &message).await,
+                        Err(e) => bus.fail(queue, &message, &e).await,
+                    }
+                }
+            }
+            Err(error) => {
+                println!("Error reading from {} queue: {:?}", queue, error);
+                tokio::time::sleep(ERROR_BACKOFF).await; // redis is down, don't spin
+            }
         }
     }
 }
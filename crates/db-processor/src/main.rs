@@ -1,5 +1,6 @@
-use db_processor::handle_db_updates;
-use redis::{RedisManager, RedisQueues};
+use db_processor::consume_db_updates;
+use db_processor::query::backfill_candles;
+use redis::RedisManager;
 use sqlx_postgres::PostgresDb;
 pub mod query;
 pub mod seed;
@@ -7,31 +8,31 @@ pub mod types;
 
 #[tokio::main]
 async fn main() {
-    let redis_connection = RedisManager::new().await.unwrap();
-    println!("Redis connected!");
-
     let postgres = PostgresDb::new().await.unwrap();
     let pg_pool = postgres.get_pg_connection().unwrap();
     println!("Postgres connection pool ready!");
 
+    // `db-processor backfill [market]` rebuilds the candles from the trades table and exits
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("backfill") {
+        let market = args.get(2).map(String::as_str);
+        match backfill_candles(&pg_pool, market).await {
+            Ok(candles) => println!("Rebuilt {} candles", candles),
+            Err(e) => {
+                eprintln!("Error rebuilding candles: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let redis_connection = RedisManager::new().await.unwrap();
+    println!("Redis connected!");
+
     // // update DATABASE_URL in sqlx_postgres to run the script
     // if let Err(e) = generate_random_trades(&pg_pool, 100000).await {
     //     println!("Error generating trades: {:?}", e);
     // }
 
-    loop {
-        match redis_connection
-            .pop(RedisQueues::DATABASE.to_string().as_str(), Some(1))
-            .await
-        {
-            Ok(data) => {
-                if data.len() > 0 {
-                    handle_db_updates(data, &pg_pool).await;
-                }
-            }
-            Err(error) => {
-                println!("Error popping from Redis: {:?}", error);
-            }
-        }
-    }
+    consume_db_updates(&redis_connection, &pg_pool).await;
 }
@@ -1,26 +1,128 @@
-use crate::types::{DbTrade, KlineData, TickerData};
+use crate::types::{
+    DbApiKey, DbAsset, DbKline, DbMarket, DbRefreshToken, DbUser, KlineData, TickerData,
+};
 use chrono::{DateTime, Duration, Utc};
+use protocol::db::DbTrade;
+use protocol::klines::KlineInterval;
 use rust_decimal::Decimal;
 use sqlx::{Pool, Postgres};
 
+// The trade and its candles are written together, so a redelivered trade fails on its market
+// and id instead of being counted twice
+// A redelivered trade is already stored and counted in its candles, so it changes nothing
 pub async fn insert_trade(pool: &Pool<Postgres>, trade: DbTrade) -> Result<(), sqlx::Error> {
-    sqlx::query(
+    let mut tx = pool.begin().await?;
+
+    let inserted = sqlx::query(
         "INSERT INTO trades(
           trade_id, market, price, quantity, user_id, other_user_id, order_id, timestamp
-      ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+      ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+      ON CONFLICT (market, trade_id) DO NOTHING",
     )
     .bind(trade.trade_id)
-    .bind(trade.market)
+    .bind(&trade.market)
     .bind(trade.price)
     .bind(trade.quantity)
-    .bind(trade.user_id)
-    .bind(trade.other_user_id)
-    .bind(trade.order_id)
+    .bind(&trade.user_id)
+    .bind(&trade.other_user_id)
+    .bind(&trade.order_id)
     .bind(trade.timestamp)
-    .execute(pool)
-    .await?;
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
 
-    Ok(())
+    if inserted == 0 {
+        return tx.commit().await;
+    }
+
+    for interval in KlineInterval::BASE {
+        // The candle opens and closes on its lowest and highest trade id, whatever order they arrive in
+        sqlx::query(
+            "INSERT INTO candles(
+              market, period, open_time, open, high, low, close, volume, quote_volume, trades,
+              first_trade_id, last_trade_id
+          ) VALUES ($1, $2, $3, $4, $4, $4, $4, $5, $4 * $5, 1, $6, $6)
+          ON CONFLICT (market, period, open_time) DO UPDATE SET
+              open = CASE WHEN EXCLUDED.first_trade_id < candles.first_trade_id
+                  THEN EXCLUDED.open ELSE candles.open END,
+              close = CASE WHEN EXCLUDED.last_trade_id > candles.last_trade_id
+                  THEN EXCLUDED.close ELSE candles.close END,
+              high = GREATEST(candles.high, EXCLUDED.high),
+              low = LEAST(candles.low, EXCLUDED.low),
+              volume = candles.volume + EXCLUDED.volume,
+              quote_volume = candles.quote_volume + EXCLUDED.quote_volume,
+              trades = candles.trades + 1,
+              first_trade_id = LEAST(candles.first_trade_id, EXCLUDED.first_trade_id),
+              last_trade_id = GREATEST(candles.last_trade_id, EXCLUDED.last_trade_id)",
+        )
+        .bind(&trade.market)
+        .bind(interval.code())
+        .bind(interval.bucket_start(trade.timestamp))
+        .bind(trade.price)
+        .bind(trade.quantity)
+        .bind(trade.trade_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+// Rebuilds the candles of one market, or of every market, from the trades table
+pub async fn backfill_candles(
+    pool: &Pool<Postgres>,
+    market: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Trades inserted meanwhile wait for the rebuild, then add to the rebuilt candles
+    sqlx::query("LOCK TABLE candles IN EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM candles WHERE $1::VARCHAR IS NULL OR market = $1")
+        .bind(market)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut candles = 0;
+    for interval in KlineInterval::BASE {
+        // Base intervals are fixed lengths aligned to the epoch
+        let (length, _) = interval.fixed_bucket().unwrap_or_default();
+
+        let result = sqlx::query(
+            "INSERT INTO candles(
+              market, period, open_time, open, high, low, close, volume, quote_volume, trades,
+              first_trade_id, last_trade_id
+          )
+          SELECT
+              market,
+              $1,
+              timestamp - MOD(timestamp, $2) AS open_time,
+              (ARRAY_AGG(price ORDER BY trade_id ASC))[1],
+              MAX(price),
+              MIN(price),
+              (ARRAY_AGG(price ORDER BY trade_id DESC))[1],
+              SUM(quantity),
+              SUM(price * quantity),
+              COUNT(*),
+              MIN(trade_id),
+              MAX(trade_id)
+          FROM trades
+          WHERE $3::VARCHAR IS NULL OR market = $3
+          GROUP BY market, open_time",
+        )
+        .bind(interval.code())
+        .bind(length)
+        .bind(market)
+        .execute(&mut *tx)
+        .await?;
+        candles += result.rows_affected();
+    }
+
+    tx.commit().await?;
+
+    Ok(candles)
 }
 
 pub async fn get_trades_from_db(
@@ -37,7 +139,7 @@ pub async fn get_trades_from_db(
     let trades_vec: Vec<DbTrade> = trades
         .iter()
         .map(|trade| DbTrade {
-            trade_id: trade.trade_id.clone(),
+            trade_id: trade.trade_id,
             market: trade.market.clone(),
             price: trade.price.to_string().parse::<Decimal>().unwrap(),
             quantity: trade.quantity.to_string().parse::<Decimal>().unwrap(),
@@ -51,99 +153,238 @@ pub async fn get_trades_from_db(
     Ok(trades_vec)
 }
 
-fn parse_custom_date(date_str: &str) -> String {
-    // https://stackoverflow.com/questions/67774426/convert-postgres-timestamp-to-rust-chrono
-    let simplified_date_str = date_str.replace("+00:00:00", "+00:00"); // as %:z expects +00:00, not +00:00:00
-
-    let fixed_offset = DateTime::parse_from_str(&simplified_date_str, "%Y-%m-%d %H:%M:%S%.f %:z")
-        .expect("Failed to parse date with timezone");
-
-    fixed_offset.with_timezone(&Utc).to_string() // store as UTC, convert to relevant timezone on client side
+// (price, quantity, timestamp) of a market's trades from `since` on, oldest first
+pub async fn get_trades_since(
+    pool: &Pool<Postgres>,
+    market: &str,
+    since: i64,
+) -> Result<Vec<(Decimal, Decimal, i64)>, sqlx::Error> {
+    sqlx::query_as::<_, (Decimal, Decimal, i64)>(
+        "SELECT price, quantity, timestamp FROM trades
+        WHERE market = $1 AND timestamp >= $2 ORDER BY timestamp ASC, trade_id ASC",
+    )
+    .bind(market)
+    .bind(since)
+    .fetch_all(pool)
+    .await
 }
 
+// Candles of `interval` whose open time is between `start_time` and `end_time`, oldest first.
+// Without a start time the most recent `limit` candles are returned.
+// They are rolled up from the stored candles of the interval's base interval. With `fill_gaps`,
+// buckets without trades are returned as flat candles at the previous close
 pub async fn get_klines_timeseries_data(
     pool: &Pool<Postgres>,
     market: String,
-    interval: String,
-    start_time: String,
+    interval: KlineInterval,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    limit: usize,
+    fill_gaps: bool,
 ) -> Result<Vec<KlineData>, sqlx::Error> {
-    let start_time_int = start_time.parse::<i64>().unwrap();
+    // Whole buckets only - the first one opening at or after the start, the last one opening by the end
+    let from = start_time.map_or(0, |start_time| {
+        let bucket = interval.bucket_start(start_time);
+        if bucket < start_time {
+            interval.next_bucket_start(bucket)
+        } else {
+            bucket
+        }
+    });
+    let to = end_time.map_or(i64::MAX, |end_time| {
+        interval.next_bucket_start(interval.bucket_start(end_time))
+    });
+    // Months are truncated by Postgres, every other interval is a fixed length from an offset
+    let (length, offset) = interval.fixed_bucket().unwrap_or((0, 0));
+    let order = if start_time.is_some() { "ASC" } else { "DESC" };
 
-    let klines = sqlx::query!(
-        // The SQL query for generating kline data
+    let mut klines = sqlx::query_as::<_, DbKline>(&format!(
         "
-        WITH timeseries_data AS (
+        WITH bucketed AS (
             SELECT
-                date_trunc($1, to_timestamp(timestamp / 1000)) AS bucket,
-                price,
-                quantity,
-                trade_id,
-                to_timestamp(timestamp / 1000) AS trade_time,
-                ROW_NUMBER() OVER (PARTITION BY date_trunc($1, to_timestamp(timestamp / 1000)) ORDER BY timestamp ASC) AS row_num_asc,
-                ROW_NUMBER() OVER (PARTITION BY date_trunc($1, to_timestamp(timestamp / 1000)) ORDER BY timestamp DESC) AS row_num_desc
-            FROM trades
-            WHERE timestamp >= $2
-              AND market = $3
-        )
-        , aggregated_data AS (
-            SELECT
-                bucket,
-                MAX(price) AS high,
-                MIN(price) AS low,
-                SUM(quantity) AS volume,
-                COUNT(trade_id) AS trades,
-                SUM(price * quantity) AS quote_volume,
-                MIN(trade_time) AS start_time,
-                MAX(trade_time) AS end_time,
-                MAX(CASE WHEN row_num_asc = 1 THEN price END) AS open,
-                MAX(CASE WHEN row_num_desc = 1 THEN price END) AS close
-            FROM timeseries_data
-            GROUP BY bucket
+                CASE WHEN $4 = 0
+                    THEN (EXTRACT(EPOCH FROM date_trunc('month', to_timestamp(open_time / 1000.0) AT TIME ZONE 'UTC')) * 1000)::BIGINT
+                    ELSE open_time - MOD(open_time - $5, $4)
+                END AS bucket,
+                open,
+                high,
+                low,
+                close,
+                volume,
+                quote_volume,
+                trades,
+                first_trade_id,
+                last_trade_id
+            FROM candles
+            WHERE market = $1
+              AND period = $7
+              AND open_time >= $2
+              AND open_time < $3
         )
         SELECT
-            open,
-            bucket AS end,
-            high,
-            low,
-            close,
-            quote_volume,
-            start_time AS start,
-            trades,
-            volume
-        FROM aggregated_data
-        ORDER BY bucket ASC
+            bucket,
+            (ARRAY_AGG(open ORDER BY first_trade_id ASC))[1] AS open,
+            MAX(high) AS high,
+            MIN(low) AS low,
+            (ARRAY_AGG(close ORDER BY last_trade_id DESC))[1] AS close,
+            SUM(volume) AS volume,
+            SUM(quote_volume) AS quote_volume,
+            SUM(trades)::BIGINT AS trades
+        FROM bucketed
+        GROUP BY bucket
+        ORDER BY bucket {}
+        LIMIT $6
         ",
-        interval,      // $1: interval (e.g., 'day', 'week', 'month')
-        start_time_int,    // $2: start time for filtering trades
-        market         // $3: the market identifier
-    )
+        order
+    ))
+    .bind(&market)
+    .bind(from)
+    .bind(to)
+    .bind(length)
+    .bind(offset)
+    .bind(limit as i64)
+    .bind(interval.base_interval().code())
     .fetch_all(pool)
     .await?;
 
+    if start_time.is_none() {
+        klines.reverse();
+    }
+
+    if fill_gaps {
+        // The series starts at startTime, or at the first candle returned, and ends at endTime,
+        // or at the last candle returned
+        let first = match start_time {
+            Some(_) => Some(from),
+            None => klines.first().map(|kline| kline.bucket),
+        };
+        let last = end_time
+            .map(|end_time| interval.bucket_start(end_time))
+            .or(klines.last().map(|kline| kline.bucket));
+
+        if let (Some(first), Some(last)) = (first, last) {
+            // Empty buckets before the first candle continue from the trade before the series
+            let previous_close = match start_time {
+                Some(_) => get_close_before(pool, &market, interval.base_interval(), from).await?,
+                None => None,
+            };
+            klines = fill_kline_gaps(
+                klines,
+                interval,
+                first,
+                last,
+                limit,
+                start_time.is_some(),
+                previous_close,
+            );
+        }
+    }
+
     // Map the result set into a vector of KlineData structs
     let kline_data_vec: Vec<KlineData> = klines
-        .iter()
-        .map(|kline| {
-            let start_time = parse_custom_date(kline.start.clone().unwrap().to_string().as_str());
-            let end_time = parse_custom_date(kline.end.clone().unwrap().to_string().as_str());
-
-            KlineData {
-                open: kline.open.clone().unwrap().to_string(),
-                high: kline.high.clone().unwrap().to_string(),
-                low: kline.low.clone().unwrap().to_string(),
-                close: kline.close.clone().unwrap().to_string(),
-                quote_volume: kline.quote_volume.clone().unwrap().to_string(),
-                start: start_time,
-                end: end_time,
-                trades: kline.trades.clone().unwrap().to_string(),
-                volume: kline.volume.clone().unwrap().to_string(),
-            }
+        .into_iter()
+        .map(|kline| KlineData {
+            open: kline.open.to_string(),
+            high: kline.high.to_string(),
+            low: kline.low.to_string(),
+            close: kline.close.to_string(),
+            quote_volume: kline.quote_volume.to_string(),
+            start: format_timestamp(kline.bucket),
+            end: format_timestamp(interval.next_bucket_start(kline.bucket) - 1),
+            trades: kline.trades.to_string(),
+            volume: kline.volume.to_string(),
         })
         .collect();
 
     Ok(kline_data_vec)
 }
 
+// Close of the last stored candle opening before `before`
+async fn get_close_before(
+    pool: &Pool<Postgres>,
+    market: &str,
+    base_interval: KlineInterval,
+    before: i64,
+) -> Result<Option<Decimal>, sqlx::Error> {
+    sqlx::query_scalar::<_, Decimal>(
+        "SELECT close FROM candles
+        WHERE market = $1 AND period = $2 AND open_time < $3
+        ORDER BY open_time DESC LIMIT 1",
+    )
+    .bind(market)
+    .bind(base_interval.code())
+    .bind(before)
+    .fetch_optional(pool)
+    .await
+}
+
+// Every bucket from `first` through `last`, at most `limit` of them counted from `first` or, for the
+// most recent candles, back from `last`. Empty buckets before any trade are left out
+fn fill_kline_gaps(
+    klines: Vec<DbKline>,
+    interval: KlineInterval,
+    first: i64,
+    last: i64,
+    limit: usize,
+    from_first: bool,
+    mut previous_close: Option<Decimal>,
+) -> Vec<DbKline> {
+    let mut bucket = first;
+    if !from_first {
+        // Walk back from the last bucket rather than generate buckets that are cut anyway
+        let mut start = last;
+        for _ in 1..limit {
+            if start <= first {
+                break;
+            }
+            start = interval.bucket_start(start - 1);
+        }
+        bucket = start.max(first);
+    }
+
+    let mut klines = klines.into_iter().peekable();
+    let mut filled = Vec::new();
+
+    while bucket <= last && filled.len() < limit {
+        // Candles before the window only carry their close forward
+        while let Some(kline) = klines.next_if(|kline| kline.bucket < bucket) {
+            previous_close = Some(kline.close);
+        }
+
+        match klines.next_if(|kline| kline.bucket == bucket) {
+            Some(kline) => {
+                previous_close = Some(kline.close);
+                filled.push(kline);
+            }
+            None => {
+                if let Some(close) = previous_close {
+                    filled.push(DbKline {
+                        bucket,
+                        open: close,
+                        high: close,
+                        low: close,
+                        close,
+                        volume: Decimal::ZERO,
+                        quote_volume: Decimal::ZERO,
+                        trades: 0,
+                    });
+                }
+            }
+        }
+
+        bucket = interval.next_bucket_start(bucket);
+    }
+
+    filled
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    // store as UTC, convert to relevant timezone on client side
+    DateTime::from_timestamp_millis(timestamp)
+        .unwrap_or_default()
+        .to_string()
+}
+
 pub async fn get_tickers_from_db(pool: &Pool<Postgres>) -> Result<Vec<TickerData>, sqlx::Error> {
     let now = Utc::now();
     let start_time_24h_ago = now - Duration::hours(24);
@@ -206,7 +447,7 @@ pub async fn get_tickers_from_db(pool: &Pool<Postgres>) -> Result<Vec<TickerData
             price_change: row.price_change.clone().unwrap().to_string(),
             price_change_percent: row.price_change_percent.clone().unwrap().to_string(),
             quote_volume: row.quote_volume.clone().unwrap().to_string(),
-            trades: row.trades.clone().unwrap().to_string(),
+            trades: row.trades.unwrap().to_string(),
             volume: row.volume.clone().unwrap().to_string(),
         })
         .collect();
@@ -232,3 +473,236 @@ pub async fn get_latest_trade_id_from_db(
 
     Ok(trade_id)
 }
+
+pub async fn get_assets_from_db(pool: &Pool<Postgres>) -> Result<Vec<DbAsset>, sqlx::Error> {
+    sqlx::query_as::<_, DbAsset>(
+        "SELECT symbol, precision, withdrawal_fee, enabled FROM assets ORDER BY symbol ASC",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_markets_from_db(pool: &Pool<Postgres>) -> Result<Vec<DbMarket>, sqlx::Error> {
+    sqlx::query_as::<_, DbMarket>(
+        "SELECT symbol, base_asset, quote_asset, enabled FROM markets ORDER BY symbol ASC",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn insert_api_key(pool: &Pool<Postgres>, api_key: &DbApiKey) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO api_keys(key_id, user_id, secret_encrypted, created_at, revoked)
+        VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(&api_key.key_id)
+    .bind(&api_key.user_id)
+    .bind(&api_key.secret_encrypted)
+    .bind(api_key.created_at)
+    .bind(api_key.revoked)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_api_key_from_db(
+    pool: &Pool<Postgres>,
+    key_id: &str,
+) -> Result<Option<DbApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, DbApiKey>(
+        "SELECT key_id, user_id, secret_encrypted, created_at, revoked FROM api_keys
+        WHERE key_id = $1",
+    )
+    .bind(key_id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn insert_user(pool: &Pool<Postgres>, user: &DbUser) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO users(user_id, email, password_hash, created_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&user.user_id)
+    .bind(&user.email)
+    .bind(&user.password_hash)
+    .bind(user.created_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Only used to undo a registration the engine did not complete
+pub async fn delete_user(pool: &Pool<Postgres>, user_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM users WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_user_by_email(
+    pool: &Pool<Postgres>,
+    email: &str,
+) -> Result<Option<DbUser>, sqlx::Error> {
+    sqlx::query_as::<_, DbUser>(
+        "SELECT user_id, email, password_hash, created_at, totp_secret, totp_enabled
+        FROM users WHERE email = $1",
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn get_user_by_id(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+) -> Result<Option<DbUser>, sqlx::Error> {
+    sqlx::query_as::<_, DbUser>(
+        "SELECT user_id, email, password_hash, created_at, totp_secret, totp_enabled
+        FROM users WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn update_password_hash(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    password_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET password_hash = $2 WHERE user_id = $1")
+        .bind(user_id)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// A None secret removes 2FA from the account
+pub async fn update_totp(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    totp_secret: Option<&str>,
+    totp_enabled: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET totp_secret = $2, totp_enabled = $3 WHERE user_id = $1")
+        .bind(user_id)
+        .bind(totp_secret)
+        .bind(totp_enabled)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// Returns false unless the last step the user passed is below step_bound
+pub async fn use_totp_step(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    step: i64,
+    step_bound: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET totp_last_step = $2
+        WHERE user_id = $1 AND (totp_last_step IS NULL OR totp_last_step < $3)",
+    )
+    .bind(user_id)
+    .bind(step)
+    .bind(step_bound)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// Replaces any earlier recovery codes of the user
+pub async fn replace_recovery_codes(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    code_hashes: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    for code_hash in code_hashes {
+        sqlx::query("INSERT INTO recovery_codes(code_hash, user_id, used) VALUES ($1, $2, FALSE)")
+            .bind(code_hash)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    transaction.commit().await
+}
+
+// Returns false if the code does not belong to the user or was already used
+pub async fn use_recovery_code(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    code_hash: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE recovery_codes SET used = TRUE
+        WHERE user_id = $1 AND code_hash = $2 AND used = FALSE",
+    )
+    .bind(user_id)
+    .bind(code_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn insert_refresh_token(
+    pool: &Pool<Postgres>,
+    refresh_token: &DbRefreshToken,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO refresh_tokens(token_hash, user_id, expires_at, revoked)
+        VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&refresh_token.token_hash)
+    .bind(&refresh_token.user_id)
+    .bind(refresh_token.expires_at)
+    .bind(refresh_token.revoked)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Marks the token as used and returns it, so a refresh token can only be redeemed once
+pub async fn revoke_refresh_token(
+    pool: &Pool<Postgres>,
+    token_hash: &str,
+) -> Result<Option<DbRefreshToken>, sqlx::Error> {
+    sqlx::query_as::<_, DbRefreshToken>(
+        "UPDATE refresh_tokens SET revoked = TRUE
+        WHERE token_hash = $1 AND revoked = FALSE
+        RETURNING token_hash, user_id, expires_at, revoked",
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn revoke_user_refresh_tokens(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE refresh_tokens SET revoked = TRUE WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
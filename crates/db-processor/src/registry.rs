@@ -0,0 +1,121 @@
+use crate::query::{get_assets_from_db, get_markets_from_db};
+use crate::types::{DbAsset, DbMarket};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    UnknownAsset(String),
+    UnknownMarket(String),
+    AssetDisabled(String),
+    MarketDisabled(String),
+}
+
+impl RegistryError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RegistryError::UnknownAsset(_) => "Unsupported asset",
+            RegistryError::UnknownMarket(_) => "Unsupported market",
+            RegistryError::AssetDisabled(_) => "Asset is disabled",
+            RegistryError::MarketDisabled(_) => "Market is disabled",
+        }
+    }
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::UnknownAsset(symbol)
+            | RegistryError::UnknownMarket(symbol)
+            | RegistryError::AssetDisabled(symbol)
+            | RegistryError::MarketDisabled(symbol) => write!(f, "{} - {}", self.reason(), symbol),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+// Listed assets and markets, keyed by symbol - replaces the hardcoded asset enums
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AssetRegistry {
+    assets: HashMap<String, DbAsset>,
+    markets: HashMap<String, DbMarket>,
+}
+
+impl AssetRegistry {
+    pub fn new(assets: Vec<DbAsset>, markets: Vec<DbMarket>) -> AssetRegistry {
+        AssetRegistry {
+            assets: assets
+                .into_iter()
+                .map(|asset| (asset.symbol.clone(), asset))
+                .collect(),
+            markets: markets
+                .into_iter()
+                .map(|market| (market.symbol.clone(), market))
+                .collect(),
+        }
+    }
+
+    pub async fn load(pool: &Pool<Postgres>) -> Result<AssetRegistry, sqlx::Error> {
+        let assets = get_assets_from_db(pool).await?;
+        let markets = get_markets_from_db(pool).await?;
+
+        Ok(AssetRegistry::new(assets, markets))
+    }
+
+    pub fn asset(&self, symbol: &str) -> Result<&DbAsset, RegistryError> {
+        let asset = self
+            .assets
+            .get(symbol)
+            .ok_or_else(|| RegistryError::UnknownAsset(symbol.to_string()))?;
+
+        if !asset.enabled {
+            return Err(RegistryError::AssetDisabled(symbol.to_string()));
+        }
+
+        Ok(asset)
+    }
+
+    // A market is only tradable if both of its assets are listed and enabled
+    pub fn market(&self, symbol: &str) -> Result<&DbMarket, RegistryError> {
+        let market = self
+            .markets
+            .get(symbol)
+            .ok_or_else(|| RegistryError::UnknownMarket(symbol.to_string()))?;
+
+        if !market.enabled {
+            return Err(RegistryError::MarketDisabled(symbol.to_string()));
+        }
+
+        self.asset(&market.base_asset)?;
+        self.asset(&market.quote_asset)?;
+
+        Ok(market)
+    }
+
+    // Every listed asset, enabled or not - a disabled asset can still be held
+    pub fn asset_symbols(&self) -> Vec<&str> {
+        self.assets.keys().map(String::as_str).collect()
+    }
+
+    // Listings after start replace an earlier listing of the same symbol
+    pub fn add_asset(&mut self, asset: DbAsset) {
+        self.assets.insert(asset.symbol.clone(), asset);
+    }
+
+    pub fn add_market(&mut self, market: DbMarket) {
+        self.markets.insert(market.symbol.clone(), market);
+    }
+
+    pub fn markets(&self) -> Vec<&DbMarket> {
+        let mut markets: Vec<&DbMarket> = self
+            .markets
+            .values()
+            .filter(|market| self.market(&market.symbol).is_ok())
+            .collect();
+        markets.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+        markets
+    }
+}
@@ -5,16 +5,13 @@ use rust_decimal::Decimal;
 use sqlx::postgres::PgPool;
 use uuid::Uuid;
 
-use db_processor::{query::insert_trade, types::DbTrade};
+use db_processor::query::insert_trade;
+use protocol::db::DbTrade;
 
 pub async fn generate_random_trades(pool: &PgPool, num_trades: i32) -> Result<(), sqlx::Error> {
     let mut rng = rand::thread_rng();
-    let mut trade_id = 50207;
-
     // Simulate distinct high/low prices by day
-    for _ in 0..num_trades {
-        trade_id += 1;
-
+    for trade_id in (50208..).take(num_trades as usize) {
         // Introduce a daily trend that causes the price to fluctuate across days
         let trend = rng.gen_range(-1.0..1.0); // Trend for the day
         let days_back = rng.gen_range(0..30); // Random number of days back
@@ -1,23 +1,6 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum DatabaseRequests {
-    InsertTrade(DbTrade),
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DbTrade {
-    pub trade_id: i64,
-    pub market: String,
-    pub price: Decimal,
-    pub quantity: Decimal,
-    pub user_id: String,
-    pub other_user_id: String,
-    pub order_id: String,
-    pub timestamp: i64,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KlineData {
     pub open: String,
@@ -31,6 +14,19 @@ pub struct KlineData {
     pub volume: String,
 }
 
+// One aggregated candle, `bucket` is its open time in ms
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DbKline {
+    pub bucket: i64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    pub quote_volume: Decimal,
+    pub trades: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TickerData {
@@ -45,3 +41,46 @@ pub struct TickerData {
     pub trades: String,
     pub volume: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DbAsset {
+    pub symbol: String,
+    pub precision: i32,
+    pub withdrawal_fee: Decimal,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DbMarket {
+    pub symbol: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DbApiKey {
+    pub key_id: String,
+    pub user_id: String,
+    pub secret_encrypted: String,
+    pub created_at: i64,
+    pub revoked: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DbUser {
+    pub user_id: String,
+    pub email: String,
+    pub password_hash: String,
+    pub created_at: i64,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DbRefreshToken {
+    pub token_hash: String,
+    pub user_id: String,
+    pub expires_at: i64,
+    pub revoked: bool,
+}
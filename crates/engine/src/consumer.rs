@@ -0,0 +1,101 @@
+use crate::engine::ws_stream::publish_ws_tickers;
+use crate::{order::handle_order, user::handle_user, Engine};
+use redis::{MessageBus, RedisQueues};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+// Messages read from a queue at once - the engine is locked once per batch
+pub const BATCH_SIZE: u64 = 100;
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+// How often countdowns are checked - a countdown fires at most this late
+pub const COUNTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// Cadence of the ticker streams
+pub const TICKER_INTERVAL: Duration = Duration::from_secs(1);
+// How often candles are checked - a candle's final message is at most this late
+pub const KLINE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub async fn consume_orders(bus: Arc<dyn MessageBus>, engine: Arc<Mutex<Engine>>) {
+    consume(RedisQueues::ORDERS, bus, engine).await
+}
+
+pub async fn consume_users(bus: Arc<dyn MessageBus>, engine: Arc<Mutex<Engine>>) {
+    consume(RedisQueues::USERS, bus, engine).await
+}
+
+// Cancels the orders of users whose countdown cancel ran out
+pub async fn expire_countdowns(bus: Arc<dyn MessageBus>, engine: Arc<Mutex<Engine>>) {
+    let mut interval = tokio::time::interval(COUNTDOWN_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let mut engine = engine.lock().await;
+        if !engine.countdowns.is_empty() {
+            let now = chrono::Utc::now().timestamp_millis();
+            engine.expire_countdowns(now, bus.as_ref()).await;
+        }
+    }
+}
+
+// Publishes the rolling 24h statistics of every market, whether or not it traded since the last time
+pub async fn publish_tickers(bus: Arc<dyn MessageBus>, engine: Arc<Mutex<Engine>>) {
+    let mut interval = tokio::time::interval(TICKER_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let tickers = {
+            let mut engine = engine.lock().await;
+            engine.ticker_updates(chrono::Utc::now().timestamp_millis())
+        };
+        publish_ws_tickers(&tickers, bus.as_ref()).await;
+    }
+}
+
+// Closes the candles of markets that did not trade since their bucket ended
+pub async fn close_klines(bus: Arc<dyn MessageBus>, engine: Arc<Mutex<Engine>>) {
+    let mut interval = tokio::time::interval(KLINE_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let mut engine = engine.lock().await;
+        let now = chrono::Utc::now().timestamp_millis();
+        engine.close_klines(now, bus.as_ref()).await;
+    }
+}
+
+async fn consume(queue: RedisQueues, bus: Arc<dyn MessageBus>, engine: Arc<Mutex<Engine>>) {
+    loop {
+        match bus.pop(queue, BATCH_SIZE).await {
+            Ok(messages) => {
+                if messages.is_empty() {
+                    continue;
+                }
+
+                let mut engine = engine.lock().await;
+                for message in messages {
+                    let result = match queue {
+                        RedisQueues::ORDERS => {
+                            handle_order(&message.data, bus.as_ref(), &mut engine).await
+                        }
+                        RedisQueues::USERS => {
+                            handle_user(&message.data, bus.as_ref(), &mut engine).await
+                        }
+                        RedisQueues::DATABASE => unreachable!("the db-processor owns this queue"),
+                    };
+                    // Acknowledged only once processed, a crash before this redelivers the message
+                    match result {
+                        Ok(()) => bus.ack(queue, &message).await,
+                        Err(e) => bus.fail(queue, &message, &e).await,
+                    }
+                }
+            }
+            Err(error) => {
+                println!("Error reading from {} queue: {:?}", queue, error);
+                tokio::time::sleep(ERROR_BACKOFF).await; // redis is down, don't spin
+            }
+        }
+    }
+}
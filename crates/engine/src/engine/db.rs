@@ -1,12 +1,10 @@
 use super::engine::Engine;
-use crate::types::{
-    db::{DatabaseRequests, DbTrade},
-    engine::{Fill, Order},
-};
+use crate::types::engine::Fill;
 use async_trait::async_trait;
-use redis::{RedisManager, RedisQueues};
+use protocol::db::{DatabaseRequests, DbTrade};
+use protocol::orders::Order;
+use redis::{MessageBus, RedisQueues};
 use rust_decimal::Decimal;
-use serde_json::to_string;
 
 #[async_trait]
 pub trait DbUpdates {
@@ -14,15 +12,16 @@ pub trait DbUpdates {
         &self,
         order: Order,
         executed_quantity: Decimal,
-        fills: &Vec<Fill>,
-        redis_conn: &RedisManager,
+        fills: &[Fill],
+        bus: &dyn MessageBus,
     );
     async fn create_db_trades(
         &self,
         user_id: String,
         market: String,
-        fills: &Vec<Fill>,
-        redis_conn: &RedisManager,
+        fills: &[Fill],
+        timestamp: i64,
+        bus: &dyn MessageBus,
     );
 }
 
@@ -32,19 +31,21 @@ impl DbUpdates for Engine {
         &self,
         order: Order,
         executed_quantity: Decimal,
-        fills: &Vec<Fill>,
-        redis_conn: &RedisManager,
+        fills: &[Fill],
+        bus: &dyn MessageBus,
     ) {
-        let _ = (order, executed_quantity, fills, redis_conn);
+        let _ = (order, executed_quantity, fills, bus);
     }
 
     async fn create_db_trades(
         &self,
         user_id: String,
         market: String,
-        fills: &Vec<Fill>,
-        redis_conn: &RedisManager,
+        fills: &[Fill],
+        timestamp: i64,
+        bus: &dyn MessageBus,
     ) {
+        // The order's timestamp, like the trade stream, so stored trades land in the same candles
         for fill in fills.iter() {
             let db_trade = DbTrade {
                 trade_id: fill.trade_id,
@@ -54,16 +55,13 @@ impl DbUpdates for Engine {
                 user_id: user_id.clone(),
                 other_user_id: fill.other_user_id.clone(),
                 order_id: fill.order_id.clone(),
-                timestamp: chrono::Utc::now().timestamp_millis(),
+                timestamp,
             };
 
             let create_db_trade_request = DatabaseRequests::InsertTrade(db_trade);
-            let create_db_trade_data = to_string(&create_db_trade_request).unwrap();
-            let _ = redis_conn
-                .push(
-                    RedisQueues::DATABASE.to_string().as_str(),
-                    create_db_trade_data,
-                )
+            let create_db_trade_data = protocol::encode(&create_db_trade_request);
+            let _ = bus
+                .push(RedisQueues::DATABASE, create_db_trade_data)
                 .await
                 .map_err(|e| {
                     println!("Couldn't push into database queue - {}", e);
@@ -1,12 +1,24 @@
 use crate::engine::db::DbUpdates;
+use crate::engine::error::EngineError;
+use crate::engine::kline::LiveKlines;
 use crate::engine::orderbook::OrderBook;
+use crate::engine::ticker::{RollingTicker, TICKER_WINDOW_MS};
 use crate::engine::ws_stream::WsStreamUpdates;
-use crate::types::engine::{
-    Asset, AssetPair, CancelAllOrders, CancelOrder, CreateOrder, GetDepth, GetOpenOrder,
-    GetOpenOrders, Order, OrderSide, OrderStatus, OrderType, ProcessOrderResult,
+use crate::types::engine::{Asset, AssetPair, ProcessOrderResult};
+use db_processor::query::{get_latest_trade_id_from_db, get_trades_since};
+use db_processor::registry::{AssetRegistry, RegistryError};
+use db_processor::types::{DbAsset, DbMarket};
+use protocol::klines::KlineInterval;
+use protocol::orders::{
+    BatchCancelOrders, BatchCreateOrders, BatchMode, BatchResult, BookTickerResponse,
+    CancelAllOrders, CancelOrder, CancelOrderResponse, CountdownSource, CreateOrder,
+    CreateOrderResponse, DepthResponse, DepthSubscription, FillReport, GetBookTicker, GetDepth,
+    GetL3Depth, GetOpenOrder, GetOpenOrders, L3DepthResponse, Liquidity, Order, OrderExecution,
+    OrderSide, OrderStatus, OrderType, DEFAULT_DEPTH_LIMIT, DEPTH_LIMITS,
+    DEPTH_SUBSCRIPTION_TTL_MS, MAX_BATCH_ORDERS, MIN_COUNTDOWN_MS,
 };
-use db_processor::query::get_latest_trade_id_from_db;
-use redis::RedisManager;
+use protocol::ws_stream::{Kline, TickerUpdate};
+use redis::MessageBus;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::{Deserialize, Serialize};
@@ -19,68 +31,116 @@ pub enum AmountType {
     LOCKED,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Amount {
-    available: Decimal,
-    locked: Decimal,
+    pub available: Decimal,
+    pub locked: Decimal,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserBalances {
-    user_id: String,
-    balance: HashMap<Asset, Amount>,
+    pub user_id: String,
+    pub balance: HashMap<Asset, Amount>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Engine {
     pub orderbooks: Vec<OrderBook>,
     pub balances: HashMap<String, Mutex<UserBalances>>,
+    pub registry: AssetRegistry,
+    // user_id -> when each of the user's countdowns cancels all of the user's orders, in ms
+    pub countdowns: HashMap<String, HashMap<CountdownSource, i64>>,
+    #[serde(skip)] // re-registered by ws-stream - when each registration expires, in ms
+    pub depth_subscriptions: HashMap<DepthSubscription, i64>,
+    #[serde(skip)] // rebuilt from the trades table on start
+    pub tickers: HashMap<String, RollingTicker>,
+    #[serde(skip)] // rebuilt from the trades table on start
+    pub klines: HashMap<String, LiveKlines>,
 }
 
 impl Engine {
-    pub fn new() -> Engine {
+    pub fn new(registry: AssetRegistry) -> Engine {
         Engine {
             orderbooks: vec![],
             balances: HashMap::new(),
+            registry,
+            countdowns: HashMap::new(),
+            depth_subscriptions: HashMap::new(),
+            tickers: HashMap::new(),
+            klines: HashMap::new(),
         }
     }
 
     pub async fn init_engine(&mut self, pool: &Pool<Postgres>) {
-        let market = "SOL_USDC".to_string();
-        let trade_id: i64 = get_latest_trade_id_from_db(pool, market).await.unwrap();
-
-        let orderbook = OrderBook::new(
-            AssetPair {
-                base: Asset::SOL,
-                quote: Asset::USDC,
-            },
-            trade_id + 1,
-        );
+        let asset_pairs: Vec<(String, AssetPair)> = self
+            .registry
+            .markets()
+            .into_iter()
+            .map(|market| {
+                (
+                    market.symbol.clone(),
+                    AssetPair {
+                        base: Asset::new(&market.base_asset),
+                        quote: Asset::new(&market.quote_asset),
+                    },
+                )
+            })
+            .collect();
+
+        for (market, asset_pair) in asset_pairs {
+            // Replay recent trades so the ticker and the open candles do not start from zero -
+            // the last 24h, or back to where the longest open candle starts
+            let now = chrono::Utc::now().timestamp_millis();
+            let since = KlineInterval::ALL
+                .iter()
+                .map(|interval| interval.bucket_start(now))
+                .fold(now - TICKER_WINDOW_MS, i64::min);
+            let trades = get_trades_since(pool, &market, since).await.unwrap();
+            let mut ticker = RollingTicker::new();
+            let mut klines = LiveKlines::new();
+            for (price, quantity, timestamp) in trades {
+                ticker.record_trade(price, quantity, timestamp);
+                klines.record_trade(price, quantity, timestamp);
+            }
+            self.tickers.insert(market.clone(), ticker);
+            self.klines.insert(market.clone(), klines);
 
-        self.orderbooks.push(orderbook);
-    }
+            let trade_id: i64 = get_latest_trade_id_from_db(pool, market).await.unwrap();
+            let orderbook = OrderBook::new(asset_pair, trade_id + 1);
 
-    pub fn init_user_balance(&mut self, user_id: &str) {
-        let initial_balances = UserBalances {
-            user_id: user_id.to_string(),
-            balance: HashMap::new(),
-        };
+            self.orderbooks.push(orderbook);
+        }
+    }
 
-        // Add dummy values for USDC and SOL
-        let usdc_balance = Amount {
-            available: Decimal::new(1000000, 0), // Dummy value: 1000000 USDC
-            locked: Decimal::new(0, 0),          // 0 locked
-        };
+    // Resolve a market symbol like "SOL_USDC" into its base and quote assets
+    pub fn market_assets(&self, market: &str) -> Result<(Asset, Asset), RegistryError> {
+        let market = self.registry.market(market)?;
 
-        let sol_balance = Amount {
-            available: Decimal::new(10000, 0), // Dummy value: 10000 SOL
-            locked: Decimal::new(0, 0),        // 0 locked
-        };
+        Ok((
+            Asset::new(&market.base_asset),
+            Asset::new(&market.quote_asset),
+        ))
+    }
 
-        // Initialize the balance HashMap for the user
-        let mut balances_map = initial_balances.balance;
-        balances_map.insert(Asset::USDC, usdc_balance);
-        balances_map.insert(Asset::SOL, sol_balance);
+    // Every listed asset starts at zero - except for dummy USDC and SOL funds until deposits work
+    pub fn init_user_balance(&mut self, user_id: &str) {
+        let balances_map: HashMap<Asset, Amount> = self
+            .registry
+            .asset_symbols()
+            .into_iter()
+            .map(|symbol| {
+                let available = match symbol {
+                    "USDC" => dec!(1000000),
+                    "SOL" => dec!(10000),
+                    _ => dec!(0),
+                };
+                let amount = Amount {
+                    available,
+                    locked: dec!(0),
+                };
+                (Asset::new(symbol), amount)
+            })
+            .collect();
 
         // Add the initialized UserBalances to the Engine's balances map
         self.balances.insert(
@@ -92,42 +152,78 @@ impl Engine {
         );
     }
 
-    pub async fn create_order(
+    // Lists a market after start, users get a zero balance of the assets they don't hold yet
+    pub fn add_market(
         &mut self,
-        input_order: CreateOrder,
-        redis_conn: &RedisManager,
-    ) -> Result<String, &str> {
-        let funds_check = self.check_and_lock_funds(&input_order);
+        assets: Vec<DbAsset>,
+        market: DbMarket,
+    ) -> Result<(), RegistryError> {
+        for asset in assets {
+            self.registry.add_asset(asset);
+        }
+        let symbol = market.symbol.clone();
+        self.registry.add_market(market);
+        let (base_asset, quote_asset) = self.market_assets(&symbol)?;
 
-        if funds_check.is_err() {
-            return Err("Funds check failed");
+        for user_balances in self.balances.values_mut() {
+            let Ok(user_balances) = user_balances.get_mut() else {
+                continue;
+            };
+            for asset in [&base_asset, &quote_asset] {
+                user_balances.balance.entry(asset.clone()).or_default();
+            }
         }
 
-        let orderbook = match self
+        if !self
             .orderbooks
-            .iter_mut()
-            .find(|orderbook| orderbook.ticker() == input_order.market)
+            .iter()
+            .any(|orderbook| orderbook.ticker() == symbol)
         {
-            Some(ob) => ob,
+            let asset_pair = AssetPair {
+                base: base_asset,
+                quote: quote_asset,
+            };
+            self.orderbooks.push(OrderBook::new(asset_pair, 1));
+        }
+
+        Ok(())
+    }
+
+    pub async fn create_order(
+        &mut self,
+        input_order: CreateOrder,
+        bus: &dyn MessageBus,
+    ) -> Result<CreateOrderResponse, EngineError> {
+        let (base_asset, quote_asset) = self.market_assets(&input_order.market)?;
+        self.validate_order(&input_order)?;
+
+        let orderbook_index = match self
+            .orderbooks
+            .iter()
+            .position(|orderbook| orderbook.ticker() == input_order.market)
+        {
+            Some(index) => index,
             None => {
                 eprintln!(
                     "No matching orderbook found for market: {}",
                     input_order.market
                 );
-                return Err("No matching orderbook found");
+                return Err(EngineError::UnknownMarket(input_order.market));
             }
         };
 
-        let assets: Vec<&str> = input_order.market.split('_').collect();
-        let base_asset = Asset::from_str(assets[0]).unwrap();
-        let quote_asset = Asset::from_str(assets[1]).unwrap();
+        // Lock funds only once the order is known to be valid, so a rejected order never holds any
+        self.check_and_lock_funds(&input_order)?;
+
+        let orderbook = &mut self.orderbooks[orderbook_index];
+
         let order_id = uuid::Uuid::new_v4().to_string();
 
         let order = Order {
             price: input_order.price,
             quantity: input_order.quantity,
             filled_quantity: dec!(0),
-            order_id: order_id.clone(),
+            order_id,
             user_id: input_order.user_id.clone(),
             side: input_order.side,
             order_type: OrderType::MARKET,
@@ -136,16 +232,27 @@ impl Engine {
         };
 
         let order_result: ProcessOrderResult = orderbook.process_order(order.clone());
-        println!("Current orderbook bids {:?}", orderbook.bids);
-        println!("Current orderbook asks {:?}", orderbook.asks);
 
-        let _ = self.update_user_balance(base_asset, quote_asset, order.clone(), &order_result);
+        let report = Self::execution_report(&order, &order_result, &base_asset, &quote_asset);
+
+        // The order already matched, so it is reported as placed either way
+        if let Err(e) = self.update_user_balance(
+            base_asset.clone(),
+            quote_asset.clone(),
+            order.clone(),
+            &order_result,
+        ) {
+            eprintln!(
+                "Failed to settle the fills of order {} - {}",
+                order.order_id, e
+            );
+        }
         let _ = self
             .update_db_orders(
                 order.clone(),
                 order_result.executed_quantity,
                 &order_result.fills,
-                redis_conn,
+                bus,
             )
             .await;
 
@@ -154,34 +261,106 @@ impl Engine {
                 input_order.user_id.clone(),
                 input_order.market.clone(),
                 &order_result.fills,
-                redis_conn,
+                order.timestamp,
+                bus,
             )
             .await;
 
+        let ticker = self.tickers.entry(input_order.market.clone()).or_default();
+        let live_klines = self.klines.entry(input_order.market.clone()).or_default();
+        let mut klines = Vec::new();
+        for fill in order_result.fills.iter() {
+            ticker.record_trade(fill.price, fill.quantity, order.timestamp);
+            klines.extend(live_klines.record_trade(fill.price, fill.quantity, order.timestamp));
+        }
+        if !order_result.fills.is_empty() {
+            klines.extend(live_klines.open_klines());
+        }
+
         let _ = self
             .publish_ws_trades(
                 input_order.market.clone(),
                 input_order.user_id.clone(),
                 &order_result.fills,
                 order.timestamp,
-                redis_conn,
+                bus,
             )
             .await;
 
-        let _ = self
-            .publish_ws_depth_updates(
-                input_order.market.clone(),
-                order.price,
-                order.side,
-                &order_result.fills,
-                redis_conn,
-            )
+        self.publish_ws_klines(&input_order.market, &klines, bus)
             .await;
 
-        Ok(order_id)
+        self.publish_ws_order_updates(
+            input_order.market.clone(),
+            &order,
+            &order_result.fills,
+            bus,
+        )
+        .await;
+
+        let mut affected_users = vec![order.user_id.clone()];
+        for fill in order_result.fills.iter() {
+            if !affected_users.contains(&fill.other_user_id) {
+                affected_users.push(fill.other_user_id.clone());
+            }
+        }
+        self.publish_ws_balance_updates(
+            &affected_users,
+            &[base_asset, quote_asset],
+            order.timestamp,
+            bus,
+        )
+        .await;
+
+        self.publish_ws_depth_updates(&input_order.market, bus)
+            .await;
+        self.publish_ws_l3_updates(&input_order.market, bus).await;
+        self.publish_ws_book_ticker(&input_order.market, bus).await;
+
+        Ok(report)
+    }
+
+    // Full execution report for a freshly processed order, trimmed later to the requested response type
+    pub fn execution_report(
+        order: &Order,
+        order_result: &ProcessOrderResult,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+    ) -> CreateOrderResponse {
+        // Fees are charged in the asset the user receives - no fee schedule exists yet, so they are zero
+        let fee_asset = match order.side {
+            OrderSide::BUY => base_asset,
+            OrderSide::SELL => quote_asset,
+        };
+
+        // The incoming order is always the aggressor, so each of its fills is a taker fill
+        let fills = order_result
+            .fills
+            .iter()
+            .map(|fill| FillReport {
+                trade_id: fill.trade_id,
+                price: fill.price,
+                quantity: fill.quantity,
+                fee: dec!(0),
+                fee_asset: fee_asset.to_string(),
+                liquidity: Liquidity::TAKER,
+            })
+            .collect();
+
+        CreateOrderResponse {
+            status: "Created Order".to_string(),
+            order_id: order.order_id.clone(),
+            execution: Some(OrderExecution {
+                executed_quantity: order_result.executed_quantity,
+                avg_price: order_result.avg_price(),
+                remaining_quantity: order.quantity - order_result.executed_quantity,
+                order_status: order_result.order_status(order.quantity),
+            }),
+            fills: Some(fills),
+        }
     }
 
-    pub fn get_open_order(&mut self, open_order: GetOpenOrder) -> Result<&Order, ()> {
+    pub fn get_open_order(&mut self, open_order: GetOpenOrder) -> Result<&Order, EngineError> {
         let orderbook = match self
             .orderbooks
             .iter_mut()
@@ -193,16 +372,22 @@ impl Engine {
                     "No matching orderbook found for market: {}",
                     open_order.market
                 );
-                return Err(());
+                return Err(EngineError::UnknownMarket(open_order.market));
             }
         };
 
-        let open_order = orderbook.get_open_order(open_order.user_id, open_order.order_id);
-
-        open_order
+        orderbook
+            .get_open_order(open_order.user_id, open_order.order_id.clone())
+            .ok_or(EngineError::UnknownOrder(open_order.order_id))
     }
 
-    pub fn cancel_order(&mut self, cancel_order: CancelOrder) -> Result<String, &str> {
+    pub async fn cancel_order(
+        &mut self,
+        cancel_order: CancelOrder,
+        bus: &dyn MessageBus,
+    ) -> Result<String, EngineError> {
+        let (base_asset, quote_asset) = self.market_assets(&cancel_order.market)?;
+
         let orderbook = match self
             .orderbooks
             .iter_mut()
@@ -214,21 +399,17 @@ impl Engine {
                     "No matching orderbook found for market: {}",
                     cancel_order.market
                 );
-                return Err("No matching orderbook found");
+                return Err(EngineError::UnknownMarket(cancel_order.market));
             }
         };
 
-        let assets: Vec<&str> = cancel_order.market.split('_').collect();
-        let base_asset_str = assets[0];
-        let quote_asset_str = assets[1];
-        let base_asset = Asset::from_str(base_asset_str)?;
-        let quote_asset = Asset::from_str(quote_asset_str)?;
         let cancel_order_id = cancel_order.order_id.clone();
+        let cancel_order_market = cancel_order.market.clone();
 
         let result = orderbook.cancel_order(cancel_order);
 
         match result {
-            Ok(order) => {
+            Some(order) => {
                 let quantity = match order.side {
                     OrderSide::BUY => (order.quantity - order.filled_quantity) * order.price,
                     OrderSide::SELL => order.quantity - order.filled_quantity,
@@ -268,17 +449,38 @@ impl Engine {
                     }
                 }
 
-                return Ok(cancel_order_id);
+                self.publish_ws_cancelled_orders(
+                    cancel_order_market.clone(),
+                    std::slice::from_ref(&order),
+                    bus,
+                )
+                .await;
+                self.publish_ws_depth_updates(&cancel_order_market, bus)
+                    .await;
+                self.publish_ws_l3_updates(&cancel_order_market, bus).await;
+                self.publish_ws_book_ticker(&cancel_order_market, bus).await;
+                self.publish_ws_balance_updates(
+                    std::slice::from_ref(&order.user_id),
+                    &[base_asset, quote_asset],
+                    chrono::Utc::now().timestamp_millis(),
+                    bus,
+                )
+                .await;
+
+                Ok(cancel_order_id)
             }
 
-            Err(()) => {
+            None => {
                 println!("Failed to cancel order");
-                return Err("Failed to cancel order");
+                Err(EngineError::UnknownOrder(cancel_order_id))
             }
         }
     }
 
-    pub fn get_open_orders(&mut self, open_orders: GetOpenOrders) -> Vec<&Order> {
+    pub fn get_open_orders(
+        &mut self,
+        open_orders: GetOpenOrders,
+    ) -> Result<Vec<&Order>, EngineError> {
         let orderbook = match self
             .orderbooks
             .iter_mut()
@@ -290,19 +492,20 @@ impl Engine {
                     "No matching orderbook found for market: {}",
                     open_orders.market
                 );
-                return Vec::new();
+                return Err(EngineError::UnknownMarket(open_orders.market));
             }
         };
 
-        let open_orders: Vec<&Order> = orderbook.get_open_orders(open_orders.user_id);
-
-        open_orders
+        Ok(orderbook.get_open_orders(open_orders.user_id))
     }
 
-    pub fn cancel_all_orders(
+    pub async fn cancel_all_orders(
         &mut self,
         cancel_all_orders: CancelAllOrders,
-    ) -> Result<String, &str> {
+        bus: &dyn MessageBus,
+    ) -> Result<String, EngineError> {
+        let (base_asset, quote_asset) = self.market_assets(&cancel_all_orders.market)?;
+
         let orderbook = match self
             .orderbooks
             .iter_mut()
@@ -314,21 +517,15 @@ impl Engine {
                     "No matching orderbook found for market: {}",
                     cancel_all_orders.market
                 );
-                return Err("No matching orderbook found");
+                return Err(EngineError::UnknownMarket(cancel_all_orders.market));
             }
         };
 
-        let assets: Vec<&str> = cancel_all_orders.market.split('_').collect();
-        let base_asset_str = assets[0];
-        let quote_asset_str = assets[1];
-        let base_asset = Asset::from_str(base_asset_str)?;
-        let quote_asset = Asset::from_str(quote_asset_str)?;
-
-        let open_orders = orderbook.cancel_all_orders(cancel_all_orders.user_id.clone());
+        let cancelled_orders = orderbook.cancel_all_orders(cancel_all_orders.user_id.clone());
 
         let mut balance_updates: Vec<(String, Asset, Decimal, AmountType)> = Vec::new();
 
-        for order in open_orders {
+        for order in cancelled_orders.iter() {
             let quantity = match order.side {
                 OrderSide::BUY => (order.quantity - order.filled_quantity) * order.price,
                 OrderSide::SELL => order.quantity - order.filled_quantity,
@@ -372,6 +569,23 @@ impl Engine {
             self.update_balance_with_lock(user_id, asset, amount, amount_type)?;
         }
 
+        self.publish_ws_cancelled_orders(
+            cancel_all_orders.market.clone(),
+            &cancelled_orders,
+            bus,
+        )
+        .await;
+        self.publish_ws_depth_updates(&cancel_all_orders.market, bus).await;
+        self.publish_ws_l3_updates(&cancel_all_orders.market, bus).await;
+        self.publish_ws_book_ticker(&cancel_all_orders.market, bus).await;
+        self.publish_ws_balance_updates(
+            std::slice::from_ref(&cancel_all_orders.user_id),
+            &[base_asset, quote_asset],
+            chrono::Utc::now().timestamp_millis(),
+            bus,
+        )
+        .await;
+
         // Return a success message after cancelling all orders
         Ok(format!(
             "All orders for user {} cancelled successfully",
@@ -379,7 +593,276 @@ impl Engine {
         ))
     }
 
-    pub fn get_depth(&self, depth: GetDepth) -> (Vec<(Decimal, Decimal)>, Vec<(Decimal, Decimal)>) {
+    // Cancels the user's open orders in every market, returns how many were cancelled
+    async fn cancel_all_user_orders(
+        &mut self,
+        user_id: &str,
+        bus: &dyn MessageBus,
+    ) -> Result<usize, EngineError> {
+        let markets: Vec<(String, usize)> = self
+            .orderbooks
+            .iter_mut()
+            .map(|orderbook| {
+                let open_orders = orderbook.get_open_orders(user_id.to_string()).len();
+                (orderbook.ticker(), open_orders)
+            })
+            .filter(|(_, open_orders)| *open_orders > 0)
+            .collect();
+
+        let mut cancelled_orders = 0;
+        for (market, open_orders) in markets {
+            self.cancel_all_orders(
+                CancelAllOrders {
+                    user_id: user_id.to_string(),
+                    market,
+                    pubsub_id: None,
+                },
+                bus,
+            )
+            .await?;
+            cancelled_orders += open_orders;
+        }
+
+        Ok(cancelled_orders)
+    }
+
+    // Starts, refreshes or (with 0) stops one of the user's countdowns, returns when it will fire
+    pub fn set_countdown(
+        &mut self,
+        user_id: &str,
+        source: CountdownSource,
+        countdown_time: u64,
+        now: i64,
+    ) -> Result<Option<i64>, EngineError> {
+        if countdown_time == 0 {
+            if let Some(countdowns) = self.countdowns.get_mut(user_id) {
+                countdowns.remove(&source);
+                if countdowns.is_empty() {
+                    self.countdowns.remove(user_id);
+                }
+            }
+            return Ok(None);
+        }
+
+        if countdown_time < MIN_COUNTDOWN_MS {
+            return Err(EngineError::InvalidCountdown(countdown_time));
+        }
+
+        let trigger_time = now + countdown_time as i64;
+        self.countdowns
+            .entry(user_id.to_string())
+            .or_default()
+            .insert(source, trigger_time);
+
+        Ok(Some(trigger_time))
+    }
+
+    // Cancels the orders of every user with a countdown that ran out without being refreshed,
+    // the user's other countdowns keep running
+    pub async fn expire_countdowns(&mut self, now: i64, bus: &dyn MessageBus) {
+        let mut expired = vec![];
+        for (user_id, countdowns) in self.countdowns.iter_mut() {
+            let before = countdowns.len();
+            countdowns.retain(|_, trigger_time| *trigger_time > now);
+            if countdowns.len() < before {
+                expired.push(user_id.clone());
+            }
+        }
+        self.countdowns
+            .retain(|_, countdowns| !countdowns.is_empty());
+
+        for user_id in expired {
+            match self.cancel_all_user_orders(&user_id, bus).await {
+                Ok(cancelled) => println!(
+                    "Countdown expired for user {}, cancelled {} orders",
+                    user_id, cancelled
+                ),
+                Err(e) => println!(
+                    "Countdown expired for user {}, failed to cancel orders - {}",
+                    user_id, e
+                ),
+            }
+        }
+    }
+
+    // Places every order of the batch under the one engine lock, results are in request order
+    pub async fn batch_create_orders(
+        &mut self,
+        batch: BatchCreateOrders,
+        bus: &dyn MessageBus,
+    ) -> Result<Vec<BatchResult<CreateOrderResponse>>, EngineError> {
+        Self::check_batch_size(batch.orders.len())?;
+
+        let orders: Vec<CreateOrder> = batch
+            .orders
+            .into_iter()
+            .map(|order| CreateOrder {
+                user_id: batch.user_id.clone(),
+                pubsub_id: None,
+                ..order
+            })
+            .collect();
+
+        let all_or_nothing = batch.mode == BatchMode::AllOrNothing;
+        if all_or_nothing {
+            let rejections = self.validate_batch(&orders);
+
+            if rejections.iter().any(Option::is_some) {
+                return Ok(Self::reject_batch(rejections));
+            }
+        }
+
+        let batch_size = orders.len();
+        let mut placed = Vec::with_capacity(batch_size);
+        let mut results = Vec::with_capacity(batch_size);
+        for (index, order) in orders.into_iter().enumerate() {
+            let response_type = order.response_type;
+            let mut cancel_order = CancelOrder {
+                order_id: String::new(),
+                user_id: order.user_id.clone(),
+                price: order.price,
+                side: order.side.clone(),
+                market: order.market.clone(),
+                pubsub_id: None,
+            };
+
+            match self.create_order(order, bus).await {
+                Ok(report) => {
+                    cancel_order.order_id = report.order_id.clone();
+                    placed.push(cancel_order);
+                    results.push(BatchResult::Ok(report.with_response_type(response_type)));
+                }
+                // Validated above, so this is not expected - the orders placed so far are taken
+                // off the book again, fills they already made can't be undone
+                Err(e) if all_or_nothing => {
+                    println!("Batch order {} failed, cancelling the batch - {}", index, e);
+                    for cancel_order in placed {
+                        let order_id = cancel_order.order_id.clone();
+                        if let Err(e) = self.cancel_order(cancel_order, bus).await {
+                            println!("Failed to cancel batch order {} - {}", order_id, e);
+                        }
+                    }
+
+                    let mut rejections: Vec<Option<EngineError>> =
+                        (0..batch_size).map(|_| None).collect();
+                    rejections[index] = Some(e);
+                    return Ok(Self::reject_batch(rejections));
+                }
+                Err(e) => results.push(BatchResult::Err(e.to_response())),
+            }
+        }
+
+        Ok(results)
+    }
+
+    // Orders with an error of their own report it, the others are rejected with the batch
+    fn reject_batch(rejections: Vec<Option<EngineError>>) -> Vec<BatchResult<CreateOrderResponse>> {
+        rejections
+            .into_iter()
+            .map(|rejection| {
+                let error = rejection.unwrap_or(EngineError::BatchRejected);
+                BatchResult::Err(error.to_response())
+            })
+            .collect()
+    }
+
+    pub async fn batch_cancel_orders(
+        &mut self,
+        batch: BatchCancelOrders,
+        bus: &dyn MessageBus,
+    ) -> Result<Vec<BatchResult<CancelOrderResponse>>, EngineError> {
+        Self::check_batch_size(batch.orders.len())?;
+
+        let mut results = Vec::with_capacity(batch.orders.len());
+        for cancel_order in batch.orders {
+            let cancel_order = CancelOrder {
+                user_id: batch.user_id.clone(),
+                pubsub_id: None,
+                ..cancel_order
+            };
+
+            let result = self
+                .cancel_order(cancel_order, bus)
+                .await
+                .map(|order_id| CancelOrderResponse {
+                    status: "Cancelled Order".to_string(),
+                    order_id,
+                })
+                .map_err(|e| e.to_response());
+
+            results.push(result.into());
+        }
+
+        Ok(results)
+    }
+
+    fn check_batch_size(size: usize) -> Result<(), EngineError> {
+        if size == 0 || size > MAX_BATCH_ORDERS {
+            return Err(EngineError::InvalidBatchSize(size));
+        }
+
+        Ok(())
+    }
+
+    // The reason each order would be rejected, with funds checked against what the whole batch needs
+    pub fn validate_batch(&self, orders: &[CreateOrder]) -> Vec<Option<EngineError>> {
+        let mut required: HashMap<Asset, Decimal> = HashMap::new();
+
+        orders
+            .iter()
+            .map(|order| self.validate_batch_order(order, &mut required).err())
+            .collect()
+    }
+
+    fn validate_batch_order(
+        &self,
+        order: &CreateOrder,
+        required: &mut HashMap<Asset, Decimal>,
+    ) -> Result<(), EngineError> {
+        let (base_asset, quote_asset) = self.market_assets(&order.market)?;
+        self.validate_order(order)?;
+
+        if !self
+            .orderbooks
+            .iter()
+            .any(|orderbook| orderbook.ticker() == order.market)
+        {
+            return Err(EngineError::UnknownMarket(order.market.clone()));
+        }
+
+        let (asset, amount) = match order.side {
+            OrderSide::BUY => (quote_asset, order.price * order.quantity),
+            OrderSide::SELL => (base_asset, order.quantity),
+        };
+
+        let total = required.get(&asset).copied().unwrap_or_default() + amount;
+        if total > self.available_balance(&order.user_id, &asset)? {
+            return Err(EngineError::InsufficientFunds);
+        }
+        required.insert(asset, total);
+
+        Ok(())
+    }
+
+    fn available_balance(&self, user_id: &str, asset: &Asset) -> Result<Decimal, EngineError> {
+        let user_balance = self
+            .balances
+            .get(user_id)
+            .ok_or_else(|| EngineError::UnknownUser(user_id.to_string()))?
+            .lock()
+            .map_err(|_| EngineError::LockPoisoned)?;
+
+        user_balance
+            .balance
+            .get(asset)
+            .map(|amount| amount.available)
+            .ok_or_else(|| EngineError::NoBalanceForAsset(asset.to_string()))
+    }
+
+    pub fn get_depth(&self, depth: GetDepth) -> Result<DepthResponse, EngineError> {
+        let limit = depth.limit.unwrap_or(DEFAULT_DEPTH_LIMIT);
+        self.validate_depth(&depth.symbol, limit, depth.step)?;
+
         let orderbook = match self
             .orderbooks
             .iter()
@@ -388,47 +871,209 @@ impl Engine {
             Some(ob) => ob,
             None => {
                 eprintln!("No matching orderbook found for market: {}", depth.symbol);
-                return (Vec::new(), Vec::new());
+                return Err(EngineError::UnknownMarket(depth.symbol));
             }
         };
 
-        let depth = orderbook.get_depth();
+        let (bids, asks) = orderbook.get_depth(limit, depth.step);
 
-        depth
+        Ok(DepthResponse {
+            last_update_id: orderbook.last_update_id(),
+            bids,
+            asks,
+        })
     }
 
-    pub fn check_and_lock_funds(&mut self, order: &CreateOrder) -> Result<(), &str> {
-        let assets: Vec<&str> = order.market.split('_').collect();
-        let base_asset_str = assets[0];
-        let quote_asset_str = assets[1];
+    // Sends the final message of every candle whose bucket is over
+    pub async fn close_klines(&mut self, now: i64, bus: &dyn MessageBus) {
+        let closed: Vec<(String, Vec<Kline>)> = self
+            .klines
+            .iter_mut()
+            .map(|(market, klines)| (market.clone(), klines.close_due(now)))
+            .filter(|(_, klines)| !klines.is_empty())
+            .collect();
+
+        for (market, klines) in closed {
+            self.publish_ws_klines(&market, &klines, bus).await;
+        }
+    }
+
+    // Rolling 24h statistics of every market that has traded, sorted by market
+    pub fn ticker_updates(&mut self, now: i64) -> Vec<TickerUpdate> {
+        let mut updates: Vec<TickerUpdate> = self
+            .tickers
+            .iter_mut()
+            .filter_map(|(market, ticker)| ticker.snapshot(market, now))
+            .collect();
+        updates.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        updates
+    }
+
+    pub fn get_book_ticker(
+        &self,
+        book_ticker: GetBookTicker,
+    ) -> Result<BookTickerResponse, EngineError> {
+        let orderbook = match self
+            .orderbooks
+            .iter()
+            .find(|orderbook| orderbook.ticker() == book_ticker.symbol)
+        {
+            Some(ob) => ob,
+            None => {
+                eprintln!(
+                    "No matching orderbook found for market: {}",
+                    book_ticker.symbol
+                );
+                return Err(EngineError::UnknownMarket(book_ticker.symbol));
+            }
+        };
+
+        let (bid, ask) = (orderbook.best_bid(), orderbook.best_ask());
+
+        Ok(BookTickerResponse {
+            symbol: book_ticker.symbol,
+            last_update_id: orderbook.last_update_id(),
+            bid_price: bid.map(|(price, _)| price),
+            bid_quantity: bid.map(|(_, quantity)| quantity),
+            ask_price: ask.map(|(price, _)| price),
+            ask_quantity: ask.map(|(_, quantity)| quantity),
+        })
+    }
+
+    pub fn get_l3_depth(&self, depth: GetL3Depth) -> Result<L3DepthResponse, EngineError> {
+        let orderbook = match self
+            .orderbooks
+            .iter()
+            .find(|orderbook| orderbook.ticker() == depth.symbol)
+        {
+            Some(ob) => ob,
+            None => {
+                eprintln!("No matching orderbook found for market: {}", depth.symbol);
+                return Err(EngineError::UnknownMarket(depth.symbol));
+            }
+        };
+
+        let (bids, asks) = orderbook.get_l3_depth();
+
+        Ok(L3DepthResponse {
+            last_update_id: orderbook.last_update_id(),
+            bids,
+            asks,
+        })
+    }
+
+    // Limits come from DEPTH_LIMITS, steps must be positive and fit the quote asset's precision
+    pub fn validate_depth(
+        &self,
+        symbol: &str,
+        limit: usize,
+        step: Option<Decimal>,
+    ) -> Result<(), EngineError> {
+        let market = self.registry.market(symbol)?;
+        let quote_asset = self.registry.asset(&market.quote_asset)?;
+
+        if !DEPTH_LIMITS.contains(&limit) {
+            return Err(EngineError::InvalidDepthLimit(limit));
+        }
+
+        if let Some(step) = step {
+            let price_precision = quote_asset.precision.max(0) as u32;
+            if step <= Decimal::ZERO || step.normalize().scale() > price_precision {
+                return Err(EngineError::InvalidDepthStep(step));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Registers or refreshes a partial book depth stream, ws-stream keeps asking while it has
+    // subscribers so the streams come back after a restart and die with a lost ws-stream
+    pub async fn subscribe_depth(
+        &mut self,
+        subscription: DepthSubscription,
+        now: i64,
+        bus: &dyn MessageBus,
+    ) -> Result<(), EngineError> {
+        self.validate_depth(&subscription.symbol, subscription.limit, subscription.step)?;
+
+        self.depth_subscriptions
+            .retain(|_, expires_at| *expires_at > now);
+
+        let symbol = subscription.symbol.clone();
+        self.depth_subscriptions
+            .insert(subscription, now + DEPTH_SUBSCRIPTION_TTL_MS);
+
+        // Give the new subscribers the current book instead of making them wait for a change
+        self.publish_ws_partial_depth(&symbol, bus).await;
+
+        Ok(())
+    }
+
+    pub fn unsubscribe_depth(&mut self, subscription: &DepthSubscription) {
+        self.depth_subscriptions.remove(subscription);
+    }
+
+    // Rejects orders with non-positive amounts or more decimals than the registry allows
+    pub fn validate_order(&self, order: &CreateOrder) -> Result<(), EngineError> {
+        let market = self.registry.market(&order.market)?;
+        let base_asset = self.registry.asset(&market.base_asset)?;
+        let quote_asset = self.registry.asset(&market.quote_asset)?;
+
+        if order.quantity <= Decimal::ZERO {
+            return Err(EngineError::InvalidQuantity);
+        }
+
+        if order.price <= Decimal::ZERO {
+            return Err(EngineError::InvalidPrice);
+        }
+
+        let quantity_precision = base_asset.precision.max(0) as u32;
+        if order.quantity.normalize().scale() > quantity_precision {
+            return Err(EngineError::InvalidPrecision {
+                field: "quantity",
+                precision: quantity_precision,
+            });
+        }
+
+        let price_precision = quote_asset.precision.max(0) as u32;
+        if order.price.normalize().scale() > price_precision {
+            return Err(EngineError::InvalidPrecision {
+                field: "price",
+                precision: price_precision,
+            });
+        }
+
+        Ok(())
+    }
 
-        // Convert string assets to Asset enum
-        let base_asset = Asset::from_str(base_asset_str)?;
-        let quote_asset = Asset::from_str(quote_asset_str)?;
+    pub fn check_and_lock_funds(&mut self, order: &CreateOrder) -> Result<(), EngineError> {
+        let (base_asset, quote_asset) = self.market_assets(&order.market)?;
 
         let user_id = &order.user_id;
 
         let user_balance_mutex = self
             .balances
             .get_mut(user_id)
-            .ok_or("No matching user found")?;
+            .ok_or_else(|| EngineError::UnknownUser(user_id.clone()))?;
 
         // Lock the Mutex to safely access the user's balances
-        let mut user_balance = user_balance_mutex.lock().map_err(|_| "Mutex lock failed")?;
+        let user_balance = user_balance_mutex
+            .get_mut()
+            .map_err(|_| EngineError::LockPoisoned)?;
 
         match order.side {
             OrderSide::BUY => {
                 let balance = user_balance
                     .balance
                     .get_mut(&quote_asset)
-                    .ok_or("No balance for asset found")?;
+                    .ok_or_else(|| EngineError::NoBalanceForAsset(quote_asset.to_string()))?;
 
                 let total_cost = order.price * order.quantity;
                 if balance.available >= total_cost {
                     balance.available -= total_cost;
                     balance.locked += total_cost;
                 } else {
-                    return Err("Insufficient funds");
+                    return Err(EngineError::InsufficientFunds);
                 }
             }
 
@@ -437,13 +1082,13 @@ impl Engine {
                 let balance = user_balance
                     .balance
                     .get_mut(&base_asset)
-                    .ok_or("No balance for asset found")?;
+                    .ok_or_else(|| EngineError::NoBalanceForAsset(base_asset.to_string()))?;
 
                 if balance.available >= order.quantity {
                     balance.available -= order.quantity;
                     balance.locked += order.quantity;
                 } else {
-                    return Err("Insufficient asset quantity");
+                    return Err(EngineError::InsufficientFunds);
                 }
             }
         }
@@ -457,7 +1102,7 @@ impl Engine {
         quote_asset: Asset,
         order: Order,
         order_result: &ProcessOrderResult,
-    ) -> Result<(), &str> {
+    ) -> Result<(), EngineError> {
         match order.side {
             OrderSide::BUY => {
                 for fill in &order_result.fills {
@@ -526,24 +1171,28 @@ impl Engine {
     }
 
     // Helper function to update balance with lock
-    fn update_balance_with_lock(
+    pub fn update_balance_with_lock(
         &self,
         user_id: String,
         asset: Asset,
         amount: Decimal,
         amount_type: AmountType,
-    ) -> Result<(), &str> {
+    ) -> Result<(), EngineError> {
         // Access the user's balance via the Mutex
         let balances = &self.balances;
-        let user_balance_mutex = balances.get(&user_id).ok_or("No matching user found")?;
+        let user_balance_mutex = balances
+            .get(&user_id)
+            .ok_or_else(|| EngineError::UnknownUser(user_id.clone()))?;
 
         // Lock the Mutex to safely access the user's balances
-        let mut user_balance = user_balance_mutex.lock().map_err(|_| "Mutex lock failed")?;
+        let mut user_balance = user_balance_mutex
+            .lock()
+            .map_err(|_| EngineError::LockPoisoned)?;
 
         let balance = user_balance
             .balance
             .get_mut(&asset)
-            .ok_or("No balance for asset found")?;
+            .ok_or_else(|| EngineError::NoBalanceForAsset(asset.to_string()))?;
 
         match amount_type {
             AmountType::AVAILABLE => balance.available += amount,
@@ -1,29 +1,117 @@
-// use super::Asset;
+use db_processor::registry::RegistryError;
+use protocol::errors::{ErrorCode, ErrorResponse};
+use protocol::orders::{DEPTH_LIMITS, MAX_BATCH_ORDERS, MIN_COUNTDOWN_MS};
+use rust_decimal::Decimal;
 
-// #[derive(Debug)]
-// pub enum AssetError {
-//     UnsupportedAsset,
-// }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineError {
+    UnknownMarket(String),
+    UnknownAsset(String),
+    MarketDisabled(String),
+    AssetDisabled(String),
+    InvalidPrecision { field: &'static str, precision: u32 },
+    InvalidQuantity,
+    InvalidPrice,
+    InvalidBatchSize(usize),
+    BatchRejected,
+    InvalidCountdown(u64),
+    InvalidDepthLimit(usize),
+    InvalidDepthStep(Decimal),
+    UnknownUser(String),
+    NoBalanceForAsset(String),
+    InsufficientFunds,
+    UnknownOrder(String),
+    LockPoisoned,
+}
 
-// impl std::fmt::Display for AssetError {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         match self {
-//             AssetError::UnsupportedAsset => write!(f, "Unsupported asset"),
-//         }
-//     }
-// }
+impl EngineError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            EngineError::UnknownMarket(_) => ErrorCode::UnknownMarket,
+            EngineError::UnknownAsset(_) => ErrorCode::UnknownAsset,
+            EngineError::MarketDisabled(_) => ErrorCode::MarketDisabled,
+            EngineError::AssetDisabled(_) => ErrorCode::AssetDisabled,
+            EngineError::InvalidPrecision { .. } => ErrorCode::InvalidPrecision,
+            EngineError::InvalidQuantity => ErrorCode::InvalidQuantity,
+            EngineError::InvalidPrice => ErrorCode::InvalidPrice,
+            EngineError::InvalidBatchSize(_) => ErrorCode::InvalidBatch,
+            EngineError::BatchRejected => ErrorCode::BatchRejected,
+            EngineError::InvalidCountdown(_) => ErrorCode::InvalidCountdown,
+            EngineError::InvalidDepthLimit(_) | EngineError::InvalidDepthStep(_) => {
+                ErrorCode::InvalidDepth
+            }
+            EngineError::UnknownUser(_) => ErrorCode::UnknownUser,
+            EngineError::NoBalanceForAsset(_) | EngineError::InsufficientFunds => {
+                ErrorCode::InsufficientFunds
+            }
+            EngineError::UnknownOrder(_) => ErrorCode::UnknownOrder,
+            EngineError::LockPoisoned => ErrorCode::Internal,
+        }
+    }
 
-// impl std::error::Error for AssetError {}
+    pub fn to_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            code: self.code(),
+            msg: self.to_string(),
+        }
+    }
+}
 
-// impl Asset {
-//     pub fn from_str(asset_str: &str) -> Result<Asset, AssetError> {
-//         match asset_str {
-//             "USDC" => Ok(Asset::USDC),
-//             "USDT" => Ok(Asset::USDT),
-//             "BTC" => Ok(Asset::BTC),
-//             "ETH" => Ok(Asset::ETH),
-//             "SOL" => Ok(Asset::SOL),
-//             _ => Err(AssetError::UnsupportedAsset),
-//         }
-//     }
-// }
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::UnknownMarket(market) => write!(f, "Unknown market {}", market),
+            EngineError::UnknownAsset(asset) => write!(f, "Unknown asset {}", asset),
+            EngineError::MarketDisabled(market) => write!(f, "Market {} is disabled", market),
+            EngineError::AssetDisabled(asset) => write!(f, "Asset {} is disabled", asset),
+            EngineError::InvalidPrecision { field, precision } => write!(
+                f,
+                "Order {} has more than {} decimal places",
+                field, precision
+            ),
+            EngineError::InvalidQuantity => write!(f, "Order quantity must be positive"),
+            EngineError::InvalidPrice => write!(f, "Order price must be positive"),
+            EngineError::InvalidBatchSize(size) => write!(
+                f,
+                "Batch has {} orders, it must have between 1 and {}",
+                size, MAX_BATCH_ORDERS
+            ),
+            EngineError::BatchRejected => {
+                write!(f, "Not placed, another order in the batch was rejected")
+            }
+            EngineError::InvalidCountdown(countdown_time) => write!(
+                f,
+                "Countdown of {} ms is too short, it must be 0 or at least {} ms",
+                countdown_time, MIN_COUNTDOWN_MS
+            ),
+            EngineError::InvalidDepthLimit(limit) => write!(
+                f,
+                "Depth limit {} is not supported, it must be one of {:?}",
+                limit, DEPTH_LIMITS
+            ),
+            EngineError::InvalidDepthStep(step) => write!(
+                f,
+                "Depth step {} must be positive and within the market's price precision",
+                step
+            ),
+            EngineError::UnknownUser(user_id) => write!(f, "Unknown user {}", user_id),
+            EngineError::NoBalanceForAsset(asset) => write!(f, "No balance for asset {}", asset),
+            EngineError::InsufficientFunds => write!(f, "Insufficient funds"),
+            EngineError::UnknownOrder(order_id) => write!(f, "Unknown order {}", order_id),
+            EngineError::LockPoisoned => write!(f, "Mutex lock failed"),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<RegistryError> for EngineError {
+    fn from(error: RegistryError) -> EngineError {
+        match error {
+            RegistryError::UnknownAsset(asset) => EngineError::UnknownAsset(asset),
+            RegistryError::UnknownMarket(market) => EngineError::UnknownMarket(market),
+            RegistryError::AssetDisabled(asset) => EngineError::AssetDisabled(asset),
+            RegistryError::MarketDisabled(market) => EngineError::MarketDisabled(market),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use protocol::klines::KlineInterval;
+use protocol::ws_stream::Kline;
+use rust_decimal::Decimal;
+
+// The open candle of every interval for one market, built from the engine's own fills so it
+// lands in the same buckets as the trades table
+#[derive(Debug, Clone)]
+pub struct LiveKlines {
+    open: Vec<(KlineInterval, Option<Kline>)>,
+}
+
+impl Default for LiveKlines {
+    fn default() -> LiveKlines {
+        LiveKlines {
+            open: KlineInterval::ALL
+                .into_iter()
+                .map(|interval| (interval, None))
+                .collect(),
+        }
+    }
+}
+
+impl LiveKlines {
+    pub fn new() -> LiveKlines {
+        LiveKlines::default()
+    }
+
+    // Returns the candles the trade closed, a late trade counts towards the open candle
+    pub fn record_trade(
+        &mut self,
+        price: Decimal,
+        quantity: Decimal,
+        timestamp: i64,
+    ) -> Vec<Kline> {
+        let mut closed = Vec::new();
+
+        for (interval, open) in self.open.iter_mut() {
+            if let Some(kline) = open.as_ref().filter(|kline| timestamp > kline.close_time) {
+                closed.push(Kline {
+                    closed: true,
+                    ..kline.clone()
+                });
+                *open = None;
+            }
+
+            match open {
+                Some(kline) => {
+                    kline.high = kline.high.max(price);
+                    kline.low = kline.low.min(price);
+                    kline.close = price;
+                    kline.volume += quantity;
+                    kline.quote_volume += price * quantity;
+                    kline.trades += 1;
+                }
+                None => {
+                    let open_time = interval.bucket_start(timestamp);
+                    *open = Some(Kline {
+                        open_time,
+                        close_time: interval.next_bucket_start(open_time) - 1,
+                        interval: *interval,
+                        open: price,
+                        high: price,
+                        low: price,
+                        close: price,
+                        volume: quantity,
+                        quote_volume: price * quantity,
+                        trades: 1,
+                        closed: false,
+                    });
+                }
+            }
+        }
+
+        closed
+    }
+
+    // Candles whose bucket ended by `now` without a trade after it
+    pub fn close_due(&mut self, now: i64) -> Vec<Kline> {
+        let mut closed = Vec::new();
+
+        for (_, open) in self.open.iter_mut() {
+            if let Some(kline) = open.take_if(|kline| kline.close_time < now) {
+                closed.push(Kline {
+                    closed: true,
+                    ..kline
+                });
+            }
+        }
+
+        closed
+    }
+
+    pub fn open_klines(&self) -> Vec<Kline> {
+        self.open
+            .iter()
+            .filter_map(|(_, open)| open.clone())
+            .collect()
+    }
+}
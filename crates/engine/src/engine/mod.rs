@@ -1,5 +1,8 @@
+#[allow(clippy::module_inception)]
 pub mod engine;
 pub mod error;
+pub mod kline;
 pub mod orderbook;
+pub mod ticker;
 pub mod db;
 pub mod ws_stream;
\ No newline at end of file
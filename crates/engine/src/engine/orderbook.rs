@@ -1,9 +1,27 @@
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
 
-use crate::types::engine::{AssetPair, CancelOrder, Fill, Order, OrderSide, ProcessOrderResult};
+use crate::types::engine::{AssetPair, Fill, ProcessOrderResult};
+use protocol::orders::{CancelOrder, L3Level, L3Order, Order, OrderSide, OrderStatus, PriceLevel};
+use protocol::ws_stream::{BookTickerUpdate, DepthUpdate, L3Action, L3Event, L3Update};
+
+// Price levels changed since the last depth diff was taken
+#[derive(Debug, Clone, Default)]
+struct PendingDiff {
+    bids: BTreeSet<Decimal>,
+    asks: BTreeSet<Decimal>,
+    first_update_id: Option<i64>,
+}
+
+// Individual order changes since the last L3 update was taken
+#[derive(Debug, Clone, Default)]
+struct PendingL3 {
+    events: Vec<L3Event>,
+    first_update_id: Option<i64>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBook {
@@ -12,6 +30,13 @@ pub struct OrderBook {
     pub asset_pair: AssetPair,
     pub trade_id: i64,
     last_update_id: i64,
+    #[serde(skip)]
+    pending_diff: PendingDiff,
+    #[serde(skip)]
+    pending_l3: PendingL3,
+    // Best bid and ask as of the last book ticker taken
+    #[serde(skip)]
+    published_top: (Option<PriceLevel>, Option<PriceLevel>),
 }
 
 impl OrderBook {
@@ -22,20 +47,170 @@ impl OrderBook {
             asset_pair,
             trade_id,
             last_update_id: 0,
+            pending_diff: PendingDiff::default(),
+            pending_l3: PendingL3::default(),
+            published_top: (None, None),
         }
     }
 
     pub fn ticker(&self) -> String {
-        format!("{:?}_{:?}", self.asset_pair.base, self.asset_pair.quote)
+        format!("{}_{}", self.asset_pair.base, self.asset_pair.quote)
+    }
+
+    // Id of the latest change to the book - depth snapshots and diffs carry it, so clients
+    // can line a snapshot up with the diff stream and notice missed diffs
+    pub fn last_update_id(&self) -> i64 {
+        self.last_update_id
+    }
+
+    fn bump_update_id(&mut self) {
+        self.last_update_id += 1;
+        self.pending_diff
+            .first_update_id
+            .get_or_insert(self.last_update_id);
+        self.pending_l3
+            .first_update_id
+            .get_or_insert(self.last_update_id);
+    }
+
+    fn record_l3(
+        &mut self,
+        action: L3Action,
+        order_id: &str,
+        side: &OrderSide,
+        price: Decimal,
+        quantity: Decimal,
+        timestamp: Option<i64>,
+    ) {
+        self.pending_l3.events.push(L3Event {
+            action,
+            order_id: public_order_id(order_id),
+            side: side.clone(),
+            price,
+            quantity,
+            timestamp,
+        });
+    }
+
+    fn touch_level(&mut self, side: &OrderSide, price: Decimal) {
+        match side {
+            OrderSide::BUY => self.pending_diff.bids.insert(price),
+            OrderSide::SELL => self.pending_diff.asks.insert(price),
+        };
+    }
+
+    // The new quantity of every level changed since the last diff, 0 for levels that are gone
+    pub fn take_depth_diff(&mut self) -> Option<DepthUpdate> {
+        let pending = std::mem::take(&mut self.pending_diff);
+        let first_update_id = pending.first_update_id?;
+
+        let levels = |book: &BTreeMap<Decimal, Vec<Order>>, prices: BTreeSet<Decimal>| {
+            prices
+                .into_iter()
+                .map(|price| {
+                    let quantity = book
+                        .get(&price)
+                        .map_or(Decimal::ZERO, |orders| level_quantity(orders));
+                    (price, quantity)
+                })
+                .collect::<Vec<PriceLevel>>()
+        };
+
+        Some(DepthUpdate {
+            event: "depth".to_string(),
+            symbol: self.ticker(),
+            first_update_id,
+            last_update_id: self.last_update_id,
+            bids: levels(&self.bids, pending.bids),
+            asks: levels(&self.asks, pending.asks),
+        })
+    }
+
+    // Every order change since the last update, in the order they happened
+    pub fn take_l3_update(&mut self) -> Option<L3Update> {
+        let pending = std::mem::take(&mut self.pending_l3);
+        let first_update_id = pending.first_update_id?;
+
+        Some(L3Update {
+            event: "l3".to_string(),
+            symbol: self.ticker(),
+            first_update_id,
+            last_update_id: self.last_update_id,
+            orders: pending.events,
+        })
+    }
+
+    pub fn best_bid(&self) -> Option<PriceLevel> {
+        self.bids
+            .iter()
+            .next_back()
+            .map(|(price, orders)| (*price, level_quantity(orders)))
+    }
+
+    pub fn best_ask(&self) -> Option<PriceLevel> {
+        self.asks
+            .iter()
+            .next()
+            .map(|(price, orders)| (*price, level_quantity(orders)))
+    }
+
+    // The top of the book, but only when its price or quantity changed since the last one taken
+    pub fn take_book_ticker(&mut self) -> Option<BookTickerUpdate> {
+        let top = (self.best_bid(), self.best_ask());
+        if top == self.published_top {
+            return None;
+        }
+        self.published_top = top;
+
+        let (bid, ask) = top;
+        Some(BookTickerUpdate {
+            event: "bookTicker".to_string(),
+            symbol: self.ticker(),
+            last_update_id: self.last_update_id,
+            bid_price: bid.map(|(price, _)| price),
+            bid_quantity: bid.map(|(_, quantity)| quantity),
+            ask_price: ask.map(|(price, _)| price),
+            ask_quantity: ask.map(|(_, quantity)| quantity),
+        })
+    }
+
+    // Every resting order per level, bids best-first
+    pub fn get_l3_depth(&self) -> (Vec<L3Level>, Vec<L3Level>) {
+        let level = |(price, orders): (&Decimal, &Vec<Order>)| L3Level {
+            price: *price,
+            orders: orders
+                .iter()
+                .enumerate()
+                .map(|(index, order)| L3Order {
+                    order_id: public_order_id(&order.order_id),
+                    quantity: order.quantity - order.filled_quantity,
+                    timestamp: order.timestamp,
+                    queue_position: index + 1,
+                })
+                .collect(),
+        };
+
+        (
+            self.bids.iter().rev().map(level).collect(),
+            self.asks.iter().map(level).collect(),
+        )
     }
 
     pub fn process_order(&mut self, mut order: Order) -> ProcessOrderResult {
-        let order_result: ProcessOrderResult;
+        // A valid order always changes the book - it trades, rests or both
+        self.bump_update_id();
+
+        let resting_side = order.side.clone();
+        let resting_price = order.price;
+        let resting_quantity = order.quantity;
+        let resting_order_id = order.order_id.clone();
+        let resting_timestamp = order.timestamp;
 
-        match order.side {
+        let order_result = match order.side {
             OrderSide::BUY => {
-                order_result = self.match_asks(&order);
+                let order_result = self.match_asks(&order);
                 order.filled_quantity = order_result.executed_quantity;
+                order.order_status = order_result.order_status(order.quantity);
                 if order_result.executed_quantity < order.quantity {
                     self.bids
                         .entry(order.price)
@@ -45,8 +220,9 @@ impl OrderBook {
                 order_result
             }
             OrderSide::SELL => {
-                order_result = self.match_bids(&order);
-
+                let order_result = self.match_bids(&order);
+                order.filled_quantity = order_result.executed_quantity;
+                order.order_status = order_result.order_status(order.quantity);
                 if order_result.executed_quantity < order.quantity {
                     self.asks
                         .entry(order.price)
@@ -55,7 +231,44 @@ impl OrderBook {
                 }
                 order_result
             }
+        };
+
+        // Makers sit on the other side of the book at the fill prices
+        let maker_side = match resting_side {
+            OrderSide::BUY => OrderSide::SELL,
+            OrderSide::SELL => OrderSide::BUY,
+        };
+        for fill in order_result.fills.iter() {
+            self.touch_level(&maker_side, fill.price);
+
+            let remaining = fill.other_quantity - fill.other_filled_quantity;
+            let action = if remaining > Decimal::ZERO {
+                L3Action::Modify
+            } else {
+                L3Action::Delete
+            };
+            self.record_l3(
+                action,
+                &fill.order_id,
+                &maker_side,
+                fill.price,
+                remaining,
+                None,
+            );
         }
+        if order_result.executed_quantity < resting_quantity {
+            self.touch_level(&resting_side, resting_price);
+            self.record_l3(
+                L3Action::Add,
+                &resting_order_id,
+                &resting_side,
+                resting_price,
+                resting_quantity - order_result.executed_quantity,
+                Some(resting_timestamp),
+            );
+        }
+
+        order_result
     }
 
     pub fn match_asks(&mut self, order: &Order) -> ProcessOrderResult {
@@ -65,12 +278,20 @@ impl OrderBook {
         for (_price, asks) in self.asks.iter_mut() {
             for ask in asks.iter_mut() {
                 if order.price >= ask.price && executed_quantity < order.quantity {
-                    let filled_quantity =
-                        std::cmp::min(ask.quantity - executed_quantity, ask.quantity);
+                    // Bounded by what is left of both the incoming and the resting order
+                    let filled_quantity = std::cmp::min(
+                        order.quantity - executed_quantity,
+                        ask.quantity - ask.filled_quantity,
+                    );
                     self.trade_id += 1;
 
                     executed_quantity += filled_quantity;
                     ask.filled_quantity += filled_quantity;
+                    ask.order_status = if ask.filled_quantity < ask.quantity {
+                        OrderStatus::PartiallyFilled
+                    } else {
+                        OrderStatus::Filled
+                    };
 
                     fills.push(Fill {
                         price: ask.price,
@@ -78,6 +299,8 @@ impl OrderBook {
                         trade_id: self.trade_id,
                         other_user_id: ask.user_id.clone(),
                         order_id: ask.order_id.clone(),
+                        other_quantity: ask.quantity,
+                        other_filled_quantity: ask.filled_quantity,
                     })
                 }
             }
@@ -85,6 +308,7 @@ impl OrderBook {
             // Remove asks that have been completely filled
             asks.retain(|ask| ask.filled_quantity < ask.quantity);
         }
+        self.asks.retain(|_, asks| !asks.is_empty());
 
         ProcessOrderResult {
             fills,
@@ -99,12 +323,20 @@ impl OrderBook {
         for (_price, bids) in self.bids.iter_mut().rev() {
             for bid in bids.iter_mut() {
                 if order.price <= bid.price && executed_quantity < order.quantity {
-                    let filled_quantity =
-                        std::cmp::min(bid.quantity - executed_quantity, bid.quantity);
+                    // Bounded by what is left of both the incoming and the resting order
+                    let filled_quantity = std::cmp::min(
+                        order.quantity - executed_quantity,
+                        bid.quantity - bid.filled_quantity,
+                    );
                     self.trade_id += 1;
 
                     executed_quantity += filled_quantity;
                     bid.filled_quantity += filled_quantity;
+                    bid.order_status = if bid.filled_quantity < bid.quantity {
+                        OrderStatus::PartiallyFilled
+                    } else {
+                        OrderStatus::Filled
+                    };
 
                     fills.push(Fill {
                         price: bid.price,
@@ -112,6 +344,8 @@ impl OrderBook {
                         trade_id: self.trade_id,
                         other_user_id: bid.user_id.clone(),
                         order_id: bid.order_id.clone(),
+                        other_quantity: bid.quantity,
+                        other_filled_quantity: bid.filled_quantity,
                     })
                 }
             }
@@ -119,6 +353,7 @@ impl OrderBook {
             // Remove bids that have been completely filled
             bids.retain(|bid| bid.filled_quantity < bid.quantity);
         }
+        self.bids.retain(|_, bids| !bids.is_empty());
 
         ProcessOrderResult {
             fills,
@@ -126,18 +361,12 @@ impl OrderBook {
         }
     }
 
-    pub fn get_open_order(&self, user_id: String, order_id: String) -> Result<&Order, ()> {
-        let order = self
-            .bids
+    pub fn get_open_order(&self, user_id: String, order_id: String) -> Option<&Order> {
+        self.bids
             .values()
             .chain(self.asks.values()) // Combine bids and asks
             .flat_map(|orders| orders.iter()) // Flatten the Vec<Order> for each price level
-            .find(|order| order.user_id == user_id && order.order_id == order_id);
-
-        match order {
-            Some(order) => Ok(order),
-            None => Err(()),
-        }
+            .find(|order| order.user_id == user_id && order.order_id == order_id)
     }
 
     pub fn get_open_orders(&mut self, user_id: String) -> Vec<&Order> {
@@ -149,62 +378,127 @@ impl OrderBook {
             .collect()
     }
 
-    pub fn cancel_order(&mut self, cancel_order: CancelOrder) -> Result<Order, ()> {
+    pub fn cancel_order(&mut self, cancel_order: CancelOrder) -> Option<Order> {
+        // Someone else's order is treated as unknown, without telling whether it exists
         let cancel = |orders_map: &mut BTreeMap<Decimal, Vec<Order>>| {
-            if let Some(orders) = orders_map.get_mut(&cancel_order.price) {
-                if let Some(index) = orders
-                    .iter()
-                    .position(|order| order.order_id == cancel_order.order_id)
-                {
-                    let order = orders.get(index).unwrap().clone();
-                    orders.remove(index);
-                    Ok(order)
-                } else {
-                    Err(())
-                }
-            } else {
-                Err(())
+            let orders = orders_map.get_mut(&cancel_order.price)?;
+            let index = orders.iter().position(|order| {
+                order.order_id == cancel_order.order_id && order.user_id == cancel_order.user_id
+            })?;
+
+            let order = orders.remove(index);
+            if orders.is_empty() {
+                orders_map.remove(&cancel_order.price);
             }
+            Some(order)
         };
 
-        match cancel_order.side {
+        let cancelled = match cancel_order.side {
             OrderSide::BUY => cancel(&mut self.bids),
             OrderSide::SELL => cancel(&mut self.asks),
+        };
+
+        if let Some(order) = &cancelled {
+            self.bump_update_id();
+            self.touch_level(&cancel_order.side, cancel_order.price);
+            self.record_l3(
+                L3Action::Delete,
+                &order.order_id,
+                &order.side,
+                order.price,
+                Decimal::ZERO,
+                None,
+            );
         }
+        cancelled
     }
 
-    pub fn cancel_all_orders(&mut self, user_id: String) -> Vec<&Order> {
-        self.bids.values_mut().for_each(|orders| {
-            orders.retain(|order| order.user_id != user_id);
-        });
+    // Returns the removed orders so their locked funds can be released
+    pub fn cancel_all_orders(&mut self, user_id: String) -> Vec<Order> {
+        let mut cancelled_orders: Vec<Order> = Vec::new();
 
-        self.asks.values_mut().for_each(|orders| {
-            orders.retain(|order| order.user_id != user_id);
-        });
+        for orders in self.bids.values_mut().chain(self.asks.values_mut()) {
+            let (cancelled, open): (Vec<Order>, Vec<Order>) =
+                orders.drain(..).partition(|order| order.user_id == user_id);
+
+            cancelled_orders.extend(cancelled);
+            *orders = open;
+        }
+        self.bids.retain(|_, bids| !bids.is_empty());
+        self.asks.retain(|_, asks| !asks.is_empty());
 
-        self.get_open_orders(user_id)
+        if !cancelled_orders.is_empty() {
+            self.bump_update_id();
+        }
+        for order in cancelled_orders.iter() {
+            self.touch_level(&order.side, order.price);
+            self.record_l3(
+                L3Action::Delete,
+                &order.order_id,
+                &order.side,
+                order.price,
+                Decimal::ZERO,
+                None,
+            );
+        }
+        cancelled_orders
     }
 
-    pub fn get_depth(&self) -> (Vec<(Decimal, Decimal)>, Vec<(Decimal, Decimal)>) {
-        let mut bids_depth: Vec<(Decimal, Decimal)> = Vec::new();
-        let mut asks_depth: Vec<(Decimal, Decimal)> = Vec::new();
+    // The best `limit` levels per side, bids best-first - with a step, levels are merged into
+    // buckets first, bids rounding down and asks rounding up so a bucket never looks better
+    pub fn get_depth(
+        &self,
+        limit: usize,
+        step: Option<Decimal>,
+    ) -> (Vec<PriceLevel>, Vec<PriceLevel>) {
+        let bids = self
+            .bids
+            .iter()
+            .rev()
+            .map(|(price, orders)| (bucket_price(*price, step, false), level_quantity(orders)));
+        let asks = self
+            .asks
+            .iter()
+            .map(|(price, orders)| (bucket_price(*price, step, true), level_quantity(orders)));
 
-        // Aggregate quantities for each price level in bids
-        for (price, orders) in self.bids.iter() {
-            let total_quantity = orders
-                .iter()
-                .fold(Decimal::ZERO, |acc, order| acc + order.quantity);
-            bids_depth.push((*price, total_quantity));
-        }
+        (group_levels(bids, limit), group_levels(asks, limit))
+    }
+}
 
-        // Aggregate quantities for each price level in asks
-        for (price, orders) in self.asks.iter() {
-            let total_quantity = orders
-                .iter()
-                .fold(Decimal::ZERO, |acc, order| acc + order.quantity);
-            asks_depth.push((*price, total_quantity));
-        }
+fn bucket_price(price: Decimal, step: Option<Decimal>, round_up: bool) -> Decimal {
+    match step {
+        Some(step) if round_up => (price / step).ceil() * step,
+        Some(step) => (price / step).floor() * step,
+        None => price,
+    }
+}
+
+// Levels arrive sorted, so a bucket's levels are always next to each other
+fn group_levels(levels: impl Iterator<Item = PriceLevel>, limit: usize) -> Vec<PriceLevel> {
+    let mut grouped: Vec<PriceLevel> = Vec::new();
 
-        (bids_depth, asks_depth)
+    for (price, quantity) in levels {
+        if let Some(last) = grouped.last_mut().filter(|last| last.0 == price) {
+            last.1 += quantity;
+        } else if grouped.len() == limit {
+            break;
+        } else {
+            grouped.push((price, quantity));
+        }
     }
+
+    grouped
+}
+
+// Stable for the life of the order but not reversible, so L3 data cannot be used to cancel or look up
+// someone else's order
+pub fn public_order_id(order_id: &str) -> String {
+    hex::encode(&Sha256::digest(order_id.as_bytes())[..8])
+}
+
+// What is left to trade at a price level - partially filled orders only count their remainder
+fn level_quantity(orders: &[Order]) -> Decimal {
+    orders.iter().fold(Decimal::ZERO, |acc, order| {
+        acc + order.quantity - order.filled_quantity
+    })
 }
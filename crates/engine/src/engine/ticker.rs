@@ -0,0 +1,107 @@
+use protocol::ws_stream::TickerUpdate;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+// Rolling window of the ticker statistics
+pub const TICKER_WINDOW_MS: i64 = 24 * 60 * 60 * 1000;
+// Trades are kept per minute, so the window is exact to the minute
+const BUCKET_MS: i64 = 60 * 1000;
+
+#[derive(Debug, Clone)]
+struct TickerBucket {
+    start: i64,
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    volume: Decimal,
+    quote_volume: Decimal,
+    trades: u64,
+}
+
+// 24h statistics of a market kept in memory, without storing every trade
+#[derive(Debug, Clone, Default)]
+pub struct RollingTicker {
+    buckets: VecDeque<TickerBucket>,
+    // Outlives the window, a quiet market still reports where it last traded
+    last_price: Option<Decimal>,
+}
+
+impl RollingTicker {
+    pub fn new() -> RollingTicker {
+        RollingTicker::default()
+    }
+
+    // Trades are expected in time order - a late one counts towards the newest minute
+    pub fn record_trade(&mut self, price: Decimal, quantity: Decimal, timestamp: i64) {
+        let start = timestamp - timestamp.rem_euclid(BUCKET_MS);
+        self.last_price = Some(price);
+
+        match self.buckets.back_mut() {
+            Some(bucket) if bucket.start >= start => {
+                bucket.high = bucket.high.max(price);
+                bucket.low = bucket.low.min(price);
+                bucket.volume += quantity;
+                bucket.quote_volume += price * quantity;
+                bucket.trades += 1;
+            }
+            _ => self.buckets.push_back(TickerBucket {
+                start,
+                open: price,
+                high: price,
+                low: price,
+                volume: quantity,
+                quote_volume: price * quantity,
+                trades: 1,
+            }),
+        }
+    }
+
+    // Drops the minutes that ended before the window
+    fn expire(&mut self, now: i64) {
+        while let Some(bucket) = self.buckets.front() {
+            if bucket.start + BUCKET_MS > now - TICKER_WINDOW_MS {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    // None until the market has traded at least once
+    pub fn snapshot(&mut self, symbol: &str, now: i64) -> Option<TickerUpdate> {
+        self.expire(now);
+        let last_price = self.last_price?;
+
+        let open = self
+            .buckets
+            .front()
+            .map_or(last_price, |bucket| bucket.open);
+        let (high, low) = self
+            .buckets
+            .iter()
+            .fold((last_price, last_price), |(high, low), bucket| {
+                (high.max(bucket.high), low.min(bucket.low))
+            });
+
+        let price_change = last_price - open;
+        let price_change_percent = if open.is_zero() {
+            Decimal::ZERO
+        } else {
+            (price_change / open * Decimal::ONE_HUNDRED).round_dp(2)
+        };
+
+        Some(TickerUpdate {
+            event: "24hrTicker".to_string(),
+            symbol: symbol.to_string(),
+            open,
+            high,
+            low,
+            last_price,
+            price_change,
+            price_change_percent,
+            volume: self.buckets.iter().map(|bucket| bucket.volume).sum(),
+            quote_volume: self.buckets.iter().map(|bucket| bucket.quote_volume).sum(),
+            trades: self.buckets.iter().map(|bucket| bucket.trades).sum(),
+            timestamp: now,
+        })
+    }
+}
@@ -1,11 +1,16 @@
 use super::engine::Engine;
-use crate::types::{
-    engine::{Fill, OrderSide},
-    ws_stream::WsResponse,
-};
+use crate::types::engine::{Asset, Fill};
 use async_trait::async_trait;
-use redis::RedisManager;
+use protocol::orders::{Order, OrderSide, OrderStatus};
+use protocol::ws_stream::{
+    kline_stream, partial_depth_stream, user_stream, AssetBalance, BalanceUpdate, Kline,
+    KlineUpdate, OrderUpdate, PartialDepthUpdate, TickerUpdate, TradeUpdate, WsResponse,
+    ALL_TICKERS_STREAM,
+};
+use redis::MessageBus;
 use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
 
 #[async_trait]
 pub trait WsStreamUpdates {
@@ -13,52 +18,124 @@ pub trait WsStreamUpdates {
         &self,
         market: String,
         user_id: String,
-        fills: &Vec<Fill>,
+        fills: &[Fill],
         timestamp: i64,
-        redis_conn: &RedisManager,
+        bus: &dyn MessageBus,
+    );
+
+    async fn publish_ws_klines(&self, market: &str, klines: &[Kline], bus: &dyn MessageBus);
+
+    async fn publish_ws_depth_updates(&mut self, market: &str, bus: &dyn MessageBus);
+
+    async fn publish_ws_partial_depth(&self, market: &str, bus: &dyn MessageBus);
+
+    async fn publish_ws_l3_updates(&mut self, market: &str, bus: &dyn MessageBus);
+
+    async fn publish_ws_book_ticker(&mut self, market: &str, bus: &dyn MessageBus);
+
+    async fn publish_ws_order_updates(
+        &self,
+        market: String,
+        order: &Order,
+        fills: &[Fill],
+        bus: &dyn MessageBus,
     );
 
-    async fn publish_ws_depth_updates(
-        &mut self,
+    async fn publish_ws_cancelled_orders(
+        &self,
         market: String,
-        price: Decimal,
-        side: OrderSide,
-        fills: &Vec<Fill>,
-        redis_conn: &RedisManager,
+        orders: &[Order],
+        bus: &dyn MessageBus,
+    );
+
+    async fn publish_ws_balance_updates(
+        &self,
+        user_ids: &[String],
+        assets: &[Asset],
+        timestamp: i64,
+        bus: &dyn MessageBus,
     );
 }
 
+// Private events go to the `user.<user_id>` channel of the order or balance owner
+async fn publish_user_event<T: Serialize>(user_id: &str, data: T, bus: &dyn MessageBus) {
+    let stream = user_stream(user_id);
+    let ws_response = WsResponse {
+        stream: stream.clone(),
+        data,
+    };
+    let ws_response_string = protocol::encode(&ws_response);
+
+    let result = bus.publish(stream.as_str(), ws_response_string).await;
+
+    if let Err(e) = result {
+        eprintln!("Error publishing to redis: {}", e);
+    }
+}
+
+// One message per market on `ticker.<market>`, and all of them together on `!ticker`
+pub async fn publish_ws_tickers(tickers: &[TickerUpdate], bus: &dyn MessageBus) {
+    for ticker in tickers.iter() {
+        let stream = format!("ticker.{}", ticker.symbol);
+        let ws_response = WsResponse {
+            stream: stream.clone(),
+            data: ticker,
+        };
+        let ws_response_string = protocol::encode(&ws_response);
+
+        if let Err(e) = bus.publish(stream.as_str(), ws_response_string).await {
+            eprintln!("Error publishing to redis: {}", e);
+        }
+    }
+
+    let ws_response = WsResponse {
+        stream: ALL_TICKERS_STREAM.to_string(),
+        data: tickers,
+    };
+    let ws_response_string = protocol::encode(&ws_response);
+
+    if let Err(e) = bus.publish(ALL_TICKERS_STREAM, ws_response_string).await {
+        eprintln!("Error publishing to redis: {}", e);
+    }
+}
+
+fn fill_status(quantity: Decimal, filled_quantity: Decimal) -> OrderStatus {
+    if filled_quantity < quantity {
+        OrderStatus::PartiallyFilled
+    } else {
+        OrderStatus::Filled
+    }
+}
+
 #[async_trait]
 impl WsStreamUpdates for Engine {
     async fn publish_ws_trades(
         &self,
         market: String,
         user_id: String,
-        fills: &Vec<Fill>,
+        fills: &[Fill],
         timestamp: i64,
-        redis_conn: &RedisManager,
+        bus: &dyn MessageBus,
     ) {
         for fill in fills.iter() {
             let stream = format!("trade.{}", market);
-            let data = serde_json::json!({
-                "e": "trade",
-                "t": fill.trade_id,
-                "m": fill.other_user_id == user_id, // check this
-                "p": fill.price,
-                "q": fill.quantity,
-                "s": market,
-                "T": timestamp,
-            });
+            let data = TradeUpdate {
+                event: "trade".to_string(),
+                trade_id: fill.trade_id,
+                is_buyer_maker: fill.other_user_id == user_id, // check this
+                price: fill.price,
+                quantity: fill.quantity,
+                symbol: market.clone(),
+                timestamp,
+            };
 
             let ws_response = WsResponse {
                 stream: stream.clone(),
                 data,
             };
-            let ws_response_string = serde_json::to_string(&ws_response).unwrap();
+            let ws_response_string = protocol::encode(&ws_response);
 
-            let result = redis_conn
-                .publish(stream.as_str(), ws_response_string)
-                .await;
+            let result = bus.publish(stream.as_str(), ws_response_string).await;
 
             if let Err(e) = result {
                 eprintln!("Error publishing to redis: {}", e);
@@ -66,101 +143,272 @@ impl WsStreamUpdates for Engine {
         }
     }
 
-    async fn publish_ws_depth_updates(
-        &mut self,
-        market: String,
-        price: Decimal,
-        side: OrderSide,
-        fills: &Vec<Fill>,
-        redis_conn: &RedisManager,
-    ) {
+    // Publishes the levels the last change touched, nothing if the book did not change
+    async fn publish_ws_depth_updates(&mut self, market: &str, bus: &dyn MessageBus) {
+        let depth_update = match self
+            .orderbooks
+            .iter_mut()
+            .find(|orderbook| orderbook.ticker() == market)
+        {
+            Some(orderbook) => orderbook.take_depth_diff(),
+            None => {
+                eprintln!("No matching orderbook found for market: {}", market);
+                return;
+            }
+        };
+
+        if let Some(data) = depth_update {
+            let stream = format!("depth.{}", market);
+            let ws_response = WsResponse {
+                stream: stream.clone(),
+                data,
+            };
+            let ws_response_string = protocol::encode(&ws_response);
+
+            if let Err(e) = bus.publish(stream.as_str(), ws_response_string).await {
+                eprintln!("Error publishing to redis: {}", e);
+            }
+
+            self.publish_ws_partial_depth(market, bus).await;
+        }
+    }
+
+    // Publishes the top of the book to every partial book depth stream registered for the market
+    async fn publish_ws_partial_depth(&self, market: &str, bus: &dyn MessageBus) {
         let orderbook = match self
+            .orderbooks
+            .iter()
+            .find(|orderbook| orderbook.ticker() == market)
+        {
+            Some(orderbook) => orderbook,
+            None => return,
+        };
+
+        // Expired registrations are only removed on the next subscribe, they are skipped here
+        let now = chrono::Utc::now().timestamp_millis();
+        for (subscription, expires_at) in self.depth_subscriptions.iter() {
+            if subscription.symbol != market || *expires_at <= now {
+                continue;
+            }
+
+            let (bids, asks) = orderbook.get_depth(subscription.limit, subscription.step);
+            let stream = partial_depth_stream(market, subscription.limit, subscription.step);
+            let ws_response = WsResponse {
+                stream: stream.clone(),
+                data: PartialDepthUpdate {
+                    event: "partialDepth".to_string(),
+                    symbol: market.to_string(),
+                    last_update_id: orderbook.last_update_id(),
+                    bids,
+                    asks,
+                },
+            };
+            let ws_response_string = protocol::encode(&ws_response);
+
+            if let Err(e) = bus.publish(stream.as_str(), ws_response_string).await {
+                eprintln!("Error publishing to redis: {}", e);
+            }
+        }
+    }
+
+    // Publishes the order changes behind the last book change on `l3.<market>`
+    async fn publish_ws_l3_updates(&mut self, market: &str, bus: &dyn MessageBus) {
+        let l3_update = match self
             .orderbooks
             .iter_mut()
             .find(|orderbook| orderbook.ticker() == market)
         {
-            Some(ob) => ob,
+            Some(orderbook) => orderbook.take_l3_update(),
             None => {
                 eprintln!("No matching orderbook found for market: {}", market);
                 return;
             }
         };
 
-        let depth = orderbook.get_depth();
-        let depth_bids = depth.0;
-        let depth_asks = depth.1;
-
-        match side {
-            OrderSide::BUY => {
-                // if buy, asks would be filled, so we'd need to update quantity for these prices on frontend
-                // for bids, only the order price bid would be filled
-                let updated_asks = depth_asks
-                    .into_iter()
-                    .filter(|ask| fills.iter().any(|fill| fill.price == ask.0))
-                    .collect::<Vec<(Decimal, Decimal)>>();
-                let updated_bids = depth_bids
-                    .into_iter()
-                    .filter(|bid| bid.0 == price)
-                    .collect::<Vec<(Decimal, Decimal)>>();
-
-                let stream = format!("depth.{}", market);
-                let data = serde_json::json!({
-                    "e": "depth",
-                    "s": market,
-                    "b": updated_bids,
-                    "a": updated_asks,
-                });
-
-                let ws_response = WsResponse {
-                    stream: stream.clone(),
-                    data,
-                };
-
-                let ws_response_string = serde_json::to_string(&ws_response).unwrap();
-
-                let result = redis_conn
-                    .publish(stream.as_str(), ws_response_string)
-                    .await;
-
-                if let Err(e) = result {
-                    eprintln!("Error publishing to redis: {}", e);
-                }
+        if let Some(data) = l3_update {
+            let stream = format!("l3.{}", market);
+            let ws_response = WsResponse {
+                stream: stream.clone(),
+                data,
+            };
+            let ws_response_string = protocol::encode(&ws_response);
+
+            if let Err(e) = bus.publish(stream.as_str(), ws_response_string).await {
+                eprintln!("Error publishing to redis: {}", e);
             }
-            OrderSide::SELL => {
-                // if sell, bids would be filled, so we'd need to update quantity for these prices on frontend
-                // for asks, only the order price ask would be filled
-                let updated_bids = depth_bids
-                    .into_iter()
-                    .filter(|bid| fills.iter().any(|fill| fill.price == bid.0))
-                    .collect::<Vec<(Decimal, Decimal)>>();
-                let updated_asks = depth_asks
-                    .into_iter()
-                    .filter(|ask| ask.0 == price)
-                    .collect::<Vec<(Decimal, Decimal)>>();
-
-                let stream = format!("depth.{}", market);
-                let data = serde_json::json!({
-                    "e": "depth",
-                    "s": market,
-                    "b": updated_bids,
-                    "a": updated_asks,
-                });
-
-                let ws_response = WsResponse {
-                    stream: stream.clone(),
-                    data,
-                };
-
-                let ws_response_string = serde_json::to_string(&ws_response).unwrap();
-
-                let result = redis_conn
-                    .publish(stream.as_str(), ws_response_string)
-                    .await;
-
-                if let Err(e) = result {
-                    eprintln!("Error publishing to redis: {}", e);
-                }
+        }
+    }
+
+    // Publishes each candle on `kline_<interval>.<market>`, open ones on every trade and closed ones once
+    async fn publish_ws_klines(&self, market: &str, klines: &[Kline], bus: &dyn MessageBus) {
+        for kline in klines.iter() {
+            let stream = kline_stream(kline.interval, market);
+            let ws_response = WsResponse {
+                stream: stream.clone(),
+                data: KlineUpdate {
+                    event: "kline".to_string(),
+                    symbol: market.to_string(),
+                    kline: kline.clone(),
+                },
+            };
+            let ws_response_string = protocol::encode(&ws_response);
+
+            if let Err(e) = bus.publish(stream.as_str(), ws_response_string).await {
+                eprintln!("Error publishing to redis: {}", e);
             }
         }
     }
+
+    // Publishes the best bid and ask on `bookTicker.<market>` when either of them changed
+    async fn publish_ws_book_ticker(&mut self, market: &str, bus: &dyn MessageBus) {
+        let book_ticker = match self
+            .orderbooks
+            .iter_mut()
+            .find(|orderbook| orderbook.ticker() == market)
+        {
+            Some(orderbook) => orderbook.take_book_ticker(),
+            None => {
+                eprintln!("No matching orderbook found for market: {}", market);
+                return;
+            }
+        };
+
+        if let Some(data) = book_ticker {
+            let stream = format!("bookTicker.{}", market);
+            let ws_response = WsResponse {
+                stream: stream.clone(),
+                data,
+            };
+            let ws_response_string = protocol::encode(&ws_response);
+
+            if let Err(e) = bus.publish(stream.as_str(), ws_response_string).await {
+                eprintln!("Error publishing to redis: {}", e);
+            }
+        }
+    }
+
+    async fn publish_ws_order_updates(
+        &self,
+        market: String,
+        order: &Order,
+        fills: &[Fill],
+        bus: &dyn MessageBus,
+    ) {
+        let new_order = OrderUpdate {
+            event: "orderUpdate".to_string(),
+            symbol: market.clone(),
+            order_id: order.order_id.clone(),
+            side: order.side.clone(),
+            price: order.price,
+            quantity: order.quantity,
+            filled_quantity: dec!(0),
+            status: OrderStatus::Pending,
+            last_filled_quantity: dec!(0),
+            last_filled_price: dec!(0),
+            trade_id: None,
+            timestamp: order.timestamp,
+        };
+        publish_user_event(&order.user_id, new_order, bus).await;
+
+        let maker_side = match order.side {
+            OrderSide::BUY => OrderSide::SELL,
+            OrderSide::SELL => OrderSide::BUY,
+        };
+
+        let mut filled_quantity = dec!(0);
+        for fill in fills.iter() {
+            filled_quantity += fill.quantity;
+
+            let taker_update = OrderUpdate {
+                event: "orderUpdate".to_string(),
+                symbol: market.clone(),
+                order_id: order.order_id.clone(),
+                side: order.side.clone(),
+                price: order.price,
+                quantity: order.quantity,
+                filled_quantity,
+                status: fill_status(order.quantity, filled_quantity),
+                last_filled_quantity: fill.quantity,
+                last_filled_price: fill.price,
+                trade_id: Some(fill.trade_id),
+                timestamp: order.timestamp,
+            };
+            publish_user_event(&order.user_id, taker_update, bus).await;
+
+            let maker_update = OrderUpdate {
+                event: "orderUpdate".to_string(),
+                symbol: market.clone(),
+                order_id: fill.order_id.clone(),
+                side: maker_side.clone(),
+                price: fill.price,
+                quantity: fill.other_quantity,
+                filled_quantity: fill.other_filled_quantity,
+                status: fill_status(fill.other_quantity, fill.other_filled_quantity),
+                last_filled_quantity: fill.quantity,
+                last_filled_price: fill.price,
+                trade_id: Some(fill.trade_id),
+                timestamp: order.timestamp,
+            };
+            publish_user_event(&fill.other_user_id, maker_update, bus).await;
+        }
+    }
+
+    async fn publish_ws_cancelled_orders(
+        &self,
+        market: String,
+        orders: &[Order],
+        bus: &dyn MessageBus,
+    ) {
+        let timestamp = chrono::Utc::now().timestamp_millis();
+
+        for order in orders.iter() {
+            let cancelled = OrderUpdate {
+                event: "orderUpdate".to_string(),
+                symbol: market.clone(),
+                order_id: order.order_id.clone(),
+                side: order.side.clone(),
+                price: order.price,
+                quantity: order.quantity,
+                filled_quantity: order.filled_quantity,
+                status: OrderStatus::Cancelled,
+                last_filled_quantity: dec!(0),
+                last_filled_price: dec!(0),
+                trade_id: None,
+                timestamp,
+            };
+            publish_user_event(&order.user_id, cancelled, bus).await;
+        }
+    }
+
+    async fn publish_ws_balance_updates(
+        &self,
+        user_ids: &[String],
+        assets: &[Asset],
+        timestamp: i64,
+        bus: &dyn MessageBus,
+    ) {
+        for user_id in user_ids.iter() {
+            // Snapshot under the lock, publish after releasing it
+            let balances = match self.balances.get(user_id).map(|balance| balance.lock()) {
+                Some(Ok(user_balances)) => assets
+                    .iter()
+                    .filter_map(|asset| {
+                        user_balances.balance.get(asset).map(|amount| AssetBalance {
+                            asset: asset.to_string(),
+                            available: amount.available,
+                            locked: amount.locked,
+                        })
+                    })
+                    .collect::<Vec<AssetBalance>>(),
+                _ => continue,
+            };
+
+            let balance_update = BalanceUpdate {
+                event: "balanceUpdate".to_string(),
+                balances,
+                timestamp,
+            };
+            publish_user_event(user_id, balance_update, bus).await;
+        }
+    }
 }
@@ -0,0 +1,7 @@
+pub mod consumer;
+pub mod engine;
+pub mod order;
+pub mod types;
+pub mod user;
+
+use engine::engine::Engine;
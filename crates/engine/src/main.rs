@@ -1,81 +1,56 @@
-pub mod engine;
-pub mod order;
-pub mod types;
-pub mod user;
-
-use engine::engine::Engine;
-use order::handle_order;
-use redis::{RedisManager, RedisQueues};
+use db_processor::registry::AssetRegistry;
+use engine::consumer::{
+    close_klines, consume_orders, consume_users, expire_countdowns, publish_tickers,
+};
+use engine::engine::engine::Engine;
+use redis::{MessageBus, RedisManager};
 use sqlx_postgres::PostgresDb;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::task;
-use user::handle_user;
 
 #[tokio::main]
 async fn main() {
-    let redis_connection = Arc::new(RedisManager::new().await.unwrap());
+    let bus: Arc<dyn MessageBus> = Arc::new(RedisManager::new().await.unwrap());
     println!("Redis connected!");
 
     let postgres = PostgresDb::new().await.unwrap();
     let pg_pool = postgres.get_pg_connection().unwrap();
     println!("Postgres connection pool ready!");
 
+    let registry = AssetRegistry::load(&pg_pool).await.unwrap();
+    println!("Asset registry loaded!");
+
     // Use Arc and Mutex to safely share engine across tasks
-    let engine = Arc::new(Mutex::new(Engine::new()));
+    let engine = Arc::new(Mutex::new(Engine::new(registry)));
     engine.lock().await.init_engine(&pg_pool).await;
     engine.lock().await.init_user_balance("test_user");
 
     // Spawn a task to handle orders concurrently
-    let redis_connection_orders = Arc::clone(&redis_connection); // Arc clone to share the same connection
-    let engine_orders = Arc::clone(&engine);
-    let orders_handle = task::spawn(async move {
-        loop {
-            match redis_connection_orders
-                .pop(RedisQueues::ORDERS.to_string().as_str(), Some(1))
-                .await
-            {
-                Ok(data) => {
-                    if data.len() > 0 {
-                        let mut engine = engine_orders.lock().await;
-                        handle_order(data, &redis_connection_orders, &mut engine).await;
-                    }
-                }
-                Err(error) => {
-                    println!("Error popping from orders redis queue: {:?}", error);
-                }
-            }
-        }
-    });
+    let orders_handle = task::spawn(consume_orders(bus.clone(), Arc::clone(&engine)));
 
     // Spawn a task to handle users concurrently
-    let redis_connection_users = Arc::clone(&redis_connection); // Arc clone to share the same connection
-    let engine_users = Arc::clone(&engine);
-    let users_handle = task::spawn(async move {
-        loop {
-            match redis_connection_users
-                .pop(RedisQueues::USERS.to_string().as_str(), Some(1))
-                .await
-            {
-                Ok(data) => {
-                    if data.len() > 0 {
-                        let mut engine = engine_users.lock().await;
-                        handle_user(data, &redis_connection_users, &mut engine).await;
-                    }
-                }
-                Err(error) => {
-                    println!("Error popping from users redis queue: {:?}", error);
-                }
-            }
-        }
-    });
+    let users_handle = task::spawn(consume_users(bus.clone(), Arc::clone(&engine)));
 
-    // Await both tasks to run concurrently
-    if let Err(e) = orders_handle.await {
-        println!("Error in the orders task: {:?}", e);
-    }
+    // Cancels the orders of users whose countdown cancel was not refreshed in time
+    task::spawn(expire_countdowns(bus.clone(), Arc::clone(&engine)));
+
+    // Rolling 24h statistics for the ticker streams
+    task::spawn(publish_tickers(bus.clone(), Arc::clone(&engine)));
+
+    // Final messages of candles that closed without a trade after them
+    task::spawn(close_klines(bus, Arc::clone(&engine)));
+
+    // The consumers never return, so either one stopping leaves the engine deaf to its
+    // queue. Exit and let the supervisor restart the engine from the database.
+    let (task_name, result) = tokio::select! {
+        result = orders_handle => ("orders", result),
+        result = users_handle => ("users", result),
+    };
 
-    if let Err(e) = users_handle.await {
-        println!("Error in the users task: {:?}", e);
+    match result {
+        Ok(()) => eprintln!("The {} task stopped, exiting", task_name),
+        Err(e) => eprintln!("Error in the {} task, exiting: {:?}", task_name, e),
     }
+    std::process::exit(1);
 }
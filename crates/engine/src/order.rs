@@ -1,212 +1,229 @@
-use crate::{types::engine::OrderRequests, Engine};
-use fred::prelude::RedisValue;
-use redis::RedisManager;
-use serde_json::from_str;
-
+use crate::Engine;
+use protocol::errors::{EngineReply, ErrorCode, ErrorResponse};
+use protocol::orders::{
+    CancelAllOrdersResponse, CancelOrderResponse, CountdownCancelAllResponse, OrderRequests,
+};
+use protocol::ProtocolError;
+use redis::MessageBus;
+use serde::Serialize;
+use uuid::Uuid;
+
+// Undecodable requests are an Err, engine and version rejections are replied to and acked
 pub async fn handle_order(
-    data: Vec<RedisValue>,
-    redis_connection: &RedisManager,
+    order_data: &str,
+    bus: &dyn MessageBus,
     engine: &mut Engine,
-) {
-    let order_to_process = &data[0];
-
-    // Convert the RedisValue to a string
-    let order_data = match order_to_process {
-        RedisValue::String(s) => s.to_string(),
-        // BYTES TYPE - NOT NEEDED FOR NOW - check if this can make it faster
-        RedisValue::Bytes(b) => String::from_utf8(b.to_vec()).unwrap_or_else(|_| "".to_string()),
-        _ => {
-            println!("Unexpected Redis value type");
-            return;
-        }
-    };
-
-    // Now you can deserialize it using serde_json
-    match from_str::<OrderRequests>(&order_data) {
+) -> Result<(), String> {
+    // Decode it, rejecting messages from other protocol versions
+    match protocol::decode::<OrderRequests>(order_data) {
         Ok(order) => match order {
             OrderRequests::CreateOrder(order) => {
                 println!("Create Order: {:?}", order);
-                let pubsub_id = order.pubsub_id.unwrap().to_string();
-                let pubsub_id_ref = pubsub_id.as_str();
-
-                let create_order_result = engine.create_order(order, redis_connection).await;
-
-                match create_order_result {
-                    Ok(order_id) => {
-                        let create_order_json = serde_json::json!({
-                            "status": "Created Order",
-                            "order_id": order_id,
-                        });
-
-                        let create_order_string =
-                            serde_json::to_string(&create_order_json).unwrap();
+                let pubsub_id = order.pubsub_id;
+                let response_type = order.response_type;
 
-                        let _ = redis_connection
-                            .publish(pubsub_id_ref, create_order_string)
-                            .await;
+                let create_order_result = engine
+                    .create_order(order, bus)
+                    .await
+                    .map(|report| report.with_response_type(response_type))
+                    .map_err(|e| e.to_response());
 
-                        println!("Successfully placed order!")
-                    }
-                    Err(str) => {
-                        let create_order_json = serde_json::json!({
-                            "status": "Failed to Create Order",
-                        });
-
-                        let create_order_string =
-                            serde_json::to_string(&create_order_json).unwrap();
-
-                        let _ = redis_connection
-                            .publish(pubsub_id_ref, create_order_string)
-                            .await;
-
-                        println!("Order creation failed - {}", str)
-                    }
-                }
+                reply(pubsub_id, create_order_result, bus).await;
             }
 
             OrderRequests::GetOpenOrder(open_order) => {
                 println!("Get Open Order: {:?}", open_order);
-                let pubsub_id = open_order.pubsub_id.unwrap().to_string();
-                let pubsub_id_ref = pubsub_id.as_str();
-
-                let open_order_result = engine.get_open_order(open_order);
-
-                match open_order_result {
-                    Ok(open_order) => {
-                        let open_order_json = serde_json::json!(open_order);
-
-                        let open_order_string = serde_json::to_string(&open_order_json).unwrap();
-
-                        let _ = redis_connection
-                            .publish(pubsub_id_ref, open_order_string)
-                            .await;
-                        println!("Successfully retrieved open order!")
-                    }
-                    Err(()) => {
-                        let open_order_json = serde_json::json!({
-                            "status": "Failed to Retrieve Open Order",
-                        });
-
-                        let open_order_string = serde_json::to_string(&open_order_json).unwrap();
-
-                        let _ = redis_connection
-                            .publish(pubsub_id_ref, open_order_string)
-                            .await;
-                        println!("Order retrieval failed")
-                    }
-                }
+                let pubsub_id = open_order.pubsub_id;
+
+                let open_order_result = engine
+                    .get_open_order(open_order)
+                    .map_err(|e| e.to_response());
+
+                reply(pubsub_id, open_order_result, bus).await;
             }
 
             OrderRequests::CancelOrder(cancel_order) => {
                 println!("Cancel Order: {:?}", cancel_order);
-                let pubsub_id = cancel_order.pubsub_id.unwrap().to_string();
-                let pubsub_id_ref = pubsub_id.as_str();
-
-                let cancel_order_result = engine.cancel_order(cancel_order);
-
-                match cancel_order_result {
-                    Ok(cancel_order_id) => {
-                        let cancel_order_json = serde_json::json!({
-                            "status": "Cancelled Order",
-                            "order_id": cancel_order_id,
-                        });
-
-                        let cancel_order_string =
-                            serde_json::to_string(&cancel_order_json).unwrap();
-
-                        let _ = redis_connection
-                            .publish(pubsub_id_ref, cancel_order_string)
-                            .await;
-                        println!("Successfully cancelled order!")
-                    }
-                    Err(str) => {
-                        let cancel_order_json = serde_json::json!({
-                            "status": "Failed to Cancel Order",
-                        });
-
-                        let cancel_order_string =
-                            serde_json::to_string(&cancel_order_json).unwrap();
-
-                        let _ = redis_connection
-                            .publish(pubsub_id_ref, cancel_order_string)
-                            .await;
-                        println!("Order cancellation failed - {}", str)
-                    }
-                }
+                let pubsub_id = cancel_order.pubsub_id;
+
+                let cancel_order_result = engine
+                    .cancel_order(cancel_order, bus)
+                    .await
+                    .map(|cancel_order_id| CancelOrderResponse {
+                        status: "Cancelled Order".to_string(),
+                        order_id: cancel_order_id,
+                    })
+                    .map_err(|e| e.to_response());
+
+                reply(pubsub_id, cancel_order_result, bus).await;
             }
 
             OrderRequests::GetOpenOrders(open_orders) => {
                 println!("Open Order: {:?}", open_orders);
-                let pubsub_id = open_orders.pubsub_id.unwrap().to_string();
-                let pubsub_id_ref = pubsub_id.as_str();
+                let pubsub_id = open_orders.pubsub_id;
 
-                let open_orders_vec = engine.get_open_orders(open_orders);
-                let open_orders_string = serde_json::to_string(&open_orders_vec).unwrap();
+                let open_orders_result = engine
+                    .get_open_orders(open_orders)
+                    .map_err(|e| e.to_response());
 
-                let _ = redis_connection
-                    .publish(pubsub_id_ref, open_orders_string)
-                    .await;
-                println!("Successfully retrieved open orders!");
+                reply(pubsub_id, open_orders_result, bus).await;
             }
 
             OrderRequests::CancelAllOrders(cancel_all_orders) => {
                 println!("Cancel All Orders: {:?}", cancel_all_orders);
                 let user_id = cancel_all_orders.user_id.clone();
-                let pubsub_id = cancel_all_orders.pubsub_id.unwrap().to_string();
-                let pubsub_id_ref = pubsub_id.as_str();
-
-                let cancel_all_orders_result = engine.cancel_all_orders(cancel_all_orders);
-
-                match cancel_all_orders_result {
-                    Ok(_) => {
-                        let cancel_all_orders_json = serde_json::json!({
-                            "status": "Cancelled All Orders",
-                            "user_id": user_id,
-                        });
-
-                        let cancel_all_orders_string =
-                            serde_json::to_string(&cancel_all_orders_json).unwrap();
-
-                        let _ = redis_connection
-                            .publish(pubsub_id_ref, cancel_all_orders_string)
-                            .await;
-                        println!("Successfully cancelled all orders!")
-                    }
-                    Err(str) => {
-                        let cancel_all_orders_json = serde_json::json!({
-                            "status": "Failed to Cancel All Orders",
-                        });
-
-                        let cancel_all_orders_string =
-                            serde_json::to_string(&cancel_all_orders_json).unwrap();
-
-                        let _ = redis_connection
-                            .publish(pubsub_id_ref, cancel_all_orders_string)
-                            .await;
-                        println!("Order cancellation failed - {}", str)
-                    }
-                }
+                let pubsub_id = cancel_all_orders.pubsub_id;
+
+                let cancel_all_orders_result = engine
+                    .cancel_all_orders(cancel_all_orders, bus)
+                    .await
+                    .map(|_| CancelAllOrdersResponse {
+                        status: "Cancelled All Orders".to_string(),
+                        user_id,
+                    })
+                    .map_err(|e| e.to_response());
+
+                reply(pubsub_id, cancel_all_orders_result, bus).await;
+            }
+
+            OrderRequests::BatchCreate(batch) => {
+                println!("Batch Create Orders: {} orders", batch.orders.len());
+                let pubsub_id = batch.pubsub_id;
+
+                let batch_result = engine
+                    .batch_create_orders(batch, bus)
+                    .await
+                    .map_err(|e| e.to_response());
+
+                reply(pubsub_id, batch_result, bus).await;
+            }
+
+            OrderRequests::BatchCancel(batch) => {
+                println!("Batch Cancel Orders: {} orders", batch.orders.len());
+                let pubsub_id = batch.pubsub_id;
+
+                let batch_result = engine
+                    .batch_cancel_orders(batch, bus)
+                    .await
+                    .map_err(|e| e.to_response());
+
+                reply(pubsub_id, batch_result, bus).await;
+            }
+
+            OrderRequests::CountdownCancelAll(countdown) => {
+                println!("Countdown Cancel All: {:?}", countdown);
+                let pubsub_id = countdown.pubsub_id;
+
+                let countdown_result = engine
+                    .set_countdown(
+                        &countdown.user_id,
+                        countdown.source,
+                        countdown.countdown_time,
+                        chrono::Utc::now().timestamp_millis(),
+                    )
+                    .map(|trigger_time| CountdownCancelAllResponse {
+                        user_id: countdown.user_id,
+                        countdown_time: countdown.countdown_time,
+                        trigger_time,
+                    })
+                    .map_err(|e| e.to_response());
+
+                reply(pubsub_id, countdown_result, bus).await;
             }
 
             OrderRequests::GetDepth(depth) => {
                 println!("Get Depth: {:?}", depth);
-                let pubsub_id = depth.pubsub_id.unwrap().to_string();
-                let pubsub_id_ref = pubsub_id.as_str();
+                let pubsub_id = depth.pubsub_id;
+
+                let depth_result = engine.get_depth(depth).map_err(|e| e.to_response());
 
-                let depth_result = engine.get_depth(depth);
-                let depth_json = serde_json::json!({
-                    "bids": depth_result.0,
-                    "asks": depth_result.1,
-                });
+                reply(pubsub_id, depth_result, bus).await;
+            }
 
-                let depth_string = serde_json::to_string(&depth_json).unwrap();
+            OrderRequests::GetBookTicker(book_ticker) => {
+                println!("Get Book Ticker: {:?}", book_ticker);
+                let pubsub_id = book_ticker.pubsub_id;
 
-                let _ = redis_connection.publish(pubsub_id_ref, depth_string).await;
-                println!("Successfully retrieved depth!");
+                let book_ticker_result = engine
+                    .get_book_ticker(book_ticker)
+                    .map_err(|e| e.to_response());
+
+                reply(pubsub_id, book_ticker_result, bus).await;
+            }
+
+            OrderRequests::GetL3Depth(depth) => {
+                println!("Get L3 Depth: {:?}", depth);
+                let pubsub_id = depth.pubsub_id;
+
+                let depth_result = engine.get_l3_depth(depth).map_err(|e| e.to_response());
+
+                reply(pubsub_id, depth_result, bus).await;
+            }
+
+            // Registered by ws-stream, there is nobody to reply to
+            OrderRequests::SubscribeDepth(subscription) => {
+                println!("Subscribe Depth: {:?}", subscription);
+
+                let now = chrono::Utc::now().timestamp_millis();
+                if let Err(e) = engine.subscribe_depth(subscription, now, bus).await {
+                    println!("Rejected depth subscription - {}", e);
+                }
+            }
+
+            OrderRequests::UnsubscribeDepth(subscription) => {
+                println!("Unsubscribe Depth: {:?}", subscription);
+                engine.unsubscribe_depth(&subscription);
             }
         },
-        Err(err) => {
-            println!("Failed to deserialize order request: {:?}", err);
+        // The caller got its answer, retrying would only reply again
+        Err(err @ ProtocolError::VersionMismatch { .. }) => {
+            eprintln!("Rejected order request: {}", err);
+            reject_request(order_data, err, bus).await;
         }
+        Err(err) => return Err(format!("Failed to deserialize order request: {:?}", err)),
+    }
+
+    Ok(())
+}
+
+// A request without a pubsub_id has nobody waiting for it, the result is only logged
+pub async fn reply<T: Serialize + std::fmt::Debug>(
+    pubsub_id: Option<Uuid>,
+    result: EngineReply<T>,
+    bus: &dyn MessageBus,
+) {
+    match pubsub_id {
+        Some(pubsub_id) => publish_reply(&pubsub_id.to_string(), result, bus).await,
+        None => println!("Request without a reply channel: {:?}", result),
+    }
+}
+
+// Replies go to the request's pubsub_id channel, where the router is waiting for them
+pub async fn publish_reply<T: Serialize>(
+    pubsub_id: &str,
+    reply: EngineReply<T>,
+    bus: &dyn MessageBus,
+) {
+    match &reply {
+        Ok(_) => println!("Request {} succeeded", pubsub_id),
+        Err(e) => println!("Request {} failed - {:?}: {}", pubsub_id, e.code, e.msg),
+    }
+
+    let reply_string = protocol::encode(&reply);
+
+    if let Err(e) = bus.publish(pubsub_id, reply_string).await {
+        eprintln!("Error publishing to redis: {}", e);
+    }
+}
+
+// Let the caller know why its request was dropped instead of leaving it waiting for a reply
+pub async fn reject_request(data: &str, err: ProtocolError, bus: &dyn MessageBus) {
+    if let Some(pubsub_id) = protocol::reply_channel(data) {
+        let rejection: EngineReply<()> = Err(ErrorResponse {
+            code: ErrorCode::ProtocolMismatch,
+            msg: err.to_string(),
+        });
+
+        publish_reply(&pubsub_id.to_string(), rejection, bus).await;
     }
 }
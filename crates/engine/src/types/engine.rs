@@ -1,27 +1,25 @@
+use protocol::orders::OrderStatus;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
+// Asset symbol as listed in the asset registry, e.g. "SOL"
 #[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, Hash)]
-pub enum Asset {
-    USDC,
-    USDT,
-    BTC,
-    ETH,
-    SOL,
-}
+#[serde(transparent)]
+pub struct Asset(String);
 
 impl Asset {
-    pub fn from_str(asset_str: &str) -> Result<Asset, &'static str> {
-        // static lifetime because Err str slice is static
-        match asset_str {
-            "USDC" => Ok(Asset::USDC),
-            "USDT" => Ok(Asset::USDT),
-            "BTC" => Ok(Asset::BTC),
-            "ETH" => Ok(Asset::ETH),
-            "SOL" => Ok(Asset::SOL),
-            _ => Err("Unsupported asset"),
-        }
+    pub fn new(symbol: &str) -> Asset {
+        Asset(symbol.to_string())
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Asset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
@@ -31,39 +29,6 @@ pub struct AssetPair {
     pub quote: Asset,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum OrderSide {
-    BUY,
-    SELL,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum OrderType {
-    LIMIT,
-    MARKET,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum OrderStatus {
-    Pending,
-    Filled,
-    PartiallyFilled,
-    Cancelled,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Order {
-    pub price: Decimal,
-    pub quantity: Decimal,
-    pub filled_quantity: Decimal,
-    pub order_id: String,
-    pub user_id: String,
-    pub side: OrderSide,
-    pub order_type: OrderType,
-    pub order_status: OrderStatus,
-    pub timestamp: i64, // chrono::Utc::now().timestamp_millis();
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Fill {
     pub price: Decimal,
@@ -71,6 +36,9 @@ pub struct Fill {
     pub trade_id: i64,
     pub other_user_id: String,
     pub order_id: String,
+    // State of the resting order after this fill, for its owner's order updates
+    pub other_quantity: Decimal,
+    pub other_filled_quantity: Decimal,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -79,78 +47,30 @@ pub struct ProcessOrderResult {
     pub fills: Vec<Fill>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CreateOrder {
-    pub market: String,
-    pub price: Decimal,
-    pub quantity: Decimal,
-    pub side: OrderSide,
-    pub user_id: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetOpenOrder {
-    pub user_id: String,
-    pub order_id: String,
-    pub market: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CancelOrder {
-    pub order_id: String,
-    pub user_id: String,
-    pub price: Decimal,
-    pub side: OrderSide,
-    pub market: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetOpenOrders {
-    pub user_id: String,
-    pub market: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CancelAllOrders {
-    pub user_id: String,
-    pub market: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetDepth {
-    pub symbol: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
-}
+impl ProcessOrderResult {
+    // Status of the incoming order once matching is done
+    pub fn order_status(&self, quantity: Decimal) -> OrderStatus {
+        if self.executed_quantity.is_zero() {
+            OrderStatus::Pending
+        } else if self.executed_quantity < quantity {
+            OrderStatus::PartiallyFilled
+        } else {
+            OrderStatus::Filled
+        }
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum OrderRequests {
-    CreateOrder(CreateOrder),
-    GetOpenOrder(GetOpenOrder),
-    CancelOrder(CancelOrder),
-    GetOpenOrders(GetOpenOrders),
-    GetDepth(GetDepth),
-    CancelAllOrders(CancelAllOrders),
-}
+    // Volume weighted price over all fills, None when nothing traded
+    pub fn avg_price(&self) -> Option<Decimal> {
+        if self.executed_quantity.is_zero() {
+            return None;
+        }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CreateUserInput {
-    pub user_id: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
-}
+        let notional: Decimal = self
+            .fills
+            .iter()
+            .map(|fill| fill.price * fill.quantity)
+            .sum();
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum UserRequests {
-    CreateUser(CreateUserInput),
+        Some(notional / self.executed_quantity)
+    }
 }
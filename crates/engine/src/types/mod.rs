@@ -1,3 +1 @@
 pub mod engine;
-pub mod db;
-pub mod ws_stream;
\ No newline at end of file
@@ -1,50 +1,42 @@
-use crate::{types::engine::UserRequests, Engine};
-use fred::prelude::RedisValue;
-use redis::RedisManager;
-use serde_json::from_str;
-
+use crate::{
+    order::{reject_request, reply},
+    Engine,
+};
+use protocol::errors::EngineReply;
+use protocol::users::{CreateUserResponse, UserRequests};
+use protocol::ProtocolError;
+use redis::MessageBus;
+
+// Undecodable requests are an Err, engine and version rejections are replied to and acked
 pub async fn handle_user(
-    data: Vec<RedisValue>,
-    redis_connection: &RedisManager,
+    user_data: &str,
+    bus: &dyn MessageBus,
     engine: &mut Engine,
-) {
-    let user_to_process = &data[0];
-
-    // Convert the RedisValue to a string
-    let user_data = match user_to_process {
-        RedisValue::String(s) => s.to_string(),
-        _ => {
-            println!("Unexpected Redis value type");
-            return;
-        }
-    };
-
-    // Now you can deserialize it using serde_json
-    match from_str::<UserRequests>(&user_data) {
+) -> Result<(), String> {
+    // Decode it, rejecting messages from other protocol versions
+    match protocol::decode::<UserRequests>(user_data) {
         Ok(user) => match user {
             UserRequests::CreateUser(user) => {
                 println!("Create User: {:?}", user);
-                let pubsub_id = user.pubsub_id.unwrap().to_string();
-                let pubsub_id_ref = pubsub_id.as_str();
+                let pubsub_id = user.pubsub_id;
 
                 engine.init_user_balance(user.user_id.as_str());
 
-                let create_user_json = serde_json::json!({
-                    "status": "Created User",
-                    "user_id": user.user_id,
+                let create_user_result: EngineReply<CreateUserResponse> = Ok(CreateUserResponse {
+                    status: "Created User".to_string(),
+                    user_id: user.user_id,
                 });
 
-                let create_user_string = serde_json::to_string(&create_user_json).unwrap();
-
-                let _ = redis_connection
-                    .publish(pubsub_id_ref, String::from(create_user_string))
-                    .await;
-
-                println!("Successfully created user!")
+                reply(pubsub_id, create_user_result, bus).await;
             }
         },
-        Err(err) => {
-            println!("Failed to deserialize user request: {:?}", err);
+        // The caller got its answer, retrying would only reply again
+        Err(err @ ProtocolError::VersionMismatch { .. }) => {
+            eprintln!("Rejected user request: {}", err);
+            reject_request(user_data, err, bus).await;
         }
+        Err(err) => return Err(format!("Failed to deserialize user request: {:?}", err)),
     }
+
+    Ok(())
 }
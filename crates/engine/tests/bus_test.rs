@@ -0,0 +1,205 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::test_registry;
+    use engine::consumer::{consume_orders, consume_users};
+    use engine::engine::engine::Engine;
+    use engine::engine::orderbook::OrderBook;
+    use engine::types::engine::{Asset, AssetPair};
+    use protocol::errors::{EngineReply, ErrorCode};
+    use protocol::orders::{
+        CreateOrder, CreateOrderResponse, DepthSubscription, OrderRequests, OrderSide, ResponseType,
+    };
+    use protocol::users::{CreateUserInput, CreateUserResponse, UserRequests};
+    use protocol::ws_stream::{partial_depth_stream, PartialDepthUpdate, WsResponse};
+    use redis::{InMemoryBus, MessageBus, RedisQueues};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    use uuid::Uuid;
+
+    // An engine with an empty SOL_USDC book, consuming from the in-memory bus
+    fn start_engine() -> Arc<InMemoryBus> {
+        let mut engine = Engine::new(test_registry());
+        engine.orderbooks.push(OrderBook::new(
+            AssetPair {
+                base: Asset::new("SOL"),
+                quote: Asset::new("USDC"),
+            },
+            1,
+        ));
+        let engine = Arc::new(Mutex::new(engine));
+
+        let bus = Arc::new(InMemoryBus::new());
+        tokio::spawn(consume_orders(bus.clone(), engine.clone()));
+        tokio::spawn(consume_users(bus.clone(), engine));
+        bus
+    }
+
+    async fn create_user(bus: &dyn MessageBus, user_id: &str) -> EngineReply<CreateUserResponse> {
+        let pubsub_id = Uuid::new_v4();
+        let request = UserRequests::CreateUser(CreateUserInput {
+            user_id: user_id.to_string(),
+            pubsub_id: Some(pubsub_id),
+        });
+
+        let reply = bus
+            .push_and_wait_for_subscriber(RedisQueues::USERS, protocol::encode(&request), pubsub_id)
+            .await
+            .unwrap();
+        protocol::decode(&reply).unwrap()
+    }
+
+    async fn create_order(
+        bus: &dyn MessageBus,
+        user_id: &str,
+        side: OrderSide,
+        price: Decimal,
+        quantity: Decimal,
+    ) -> EngineReply<CreateOrderResponse> {
+        let pubsub_id = Uuid::new_v4();
+        let request = OrderRequests::CreateOrder(CreateOrder {
+            market: "SOL_USDC".to_string(),
+            price,
+            quantity,
+            side,
+            user_id: user_id.to_string(),
+            response_type: ResponseType::ACK,
+            pubsub_id: Some(pubsub_id),
+        });
+
+        let reply = bus
+            .push_and_wait_for_subscriber(
+                RedisQueues::ORDERS,
+                protocol::encode(&request),
+                pubsub_id,
+            )
+            .await
+            .unwrap();
+        protocol::decode(&reply).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_engine_replies_over_in_memory_bus() {
+        let bus = start_engine();
+
+        for user_id in ["seller", "buyer"] {
+            let user = create_user(bus.as_ref(), user_id).await.unwrap();
+            assert_eq!(user.user_id, user_id);
+        }
+
+        let ask = create_order(bus.as_ref(), "seller", OrderSide::SELL, dec!(10), dec!(1))
+            .await
+            .unwrap();
+        assert!(!ask.order_id.is_empty());
+        create_order(bus.as_ref(), "buyer", OrderSide::BUY, dec!(10), dec!(1))
+            .await
+            .unwrap();
+
+        // The trade is handed on to the db-processor
+        let db_updates = bus.pop(RedisQueues::DATABASE, 10).await.unwrap();
+        assert!(!db_updates.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_engine_rejection_over_in_memory_bus() {
+        let bus = start_engine();
+        create_user(bus.as_ref(), "bus_user").await.unwrap();
+
+        let error = create_order(
+            bus.as_ref(),
+            "bus_user",
+            OrderSide::BUY,
+            dec!(1000000),
+            dec!(5),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InsufficientFunds);
+    }
+
+    #[tokio::test]
+    async fn test_partial_depth_stream_over_in_memory_bus() {
+        let bus = start_engine();
+        create_user(bus.as_ref(), "maker").await.unwrap();
+
+        // What ws-stream does for the first subscriber of depth.SOL_USDC.5.1
+        let stream = partial_depth_stream("SOL_USDC", 5, Some(dec!(1)));
+        let mut updates = bus.subscribe(&stream).await.unwrap();
+        let request = OrderRequests::SubscribeDepth(DepthSubscription {
+            symbol: "SOL_USDC".to_string(),
+            limit: 5,
+            step: Some(dec!(1)),
+        });
+        bus.push(RedisQueues::ORDERS, protocol::encode(&request))
+            .await
+            .unwrap();
+
+        let snapshot: WsResponse<PartialDepthUpdate> =
+            protocol::decode(&updates.recv().await.unwrap()).unwrap();
+        assert_eq!(snapshot.stream, "depth.SOL_USDC.5.1");
+        assert!(snapshot.data.bids.is_empty());
+
+        for price in [dec!(9.5), dec!(9.2)] {
+            create_order(bus.as_ref(), "maker", OrderSide::BUY, price, dec!(1))
+                .await
+                .unwrap();
+        }
+
+        let first: WsResponse<PartialDepthUpdate> =
+            protocol::decode(&updates.recv().await.unwrap()).unwrap();
+        assert_eq!(first.data.bids, vec![(dec!(9), dec!(1))]);
+        let second: WsResponse<PartialDepthUpdate> =
+            protocol::decode(&updates.recv().await.unwrap()).unwrap();
+        assert_eq!(second.data.last_update_id, 2);
+        assert_eq!(second.data.bids, vec![(dec!(9), dec!(2))]);
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_requests_are_failed_not_acked() {
+        let bus = start_engine();
+        bus.push(RedisQueues::ORDERS, "not an order request".to_string())
+            .await
+            .unwrap();
+
+        // Handled in queue order, so the garbage was dealt with once this is replied to
+        create_user(bus.as_ref(), "bus_user").await.unwrap();
+        create_order(bus.as_ref(), "bus_user", OrderSide::BUY, dec!(1), dec!(1))
+            .await
+            .unwrap();
+
+        let failed = bus.failed(RedisQueues::ORDERS);
+        assert_eq!(failed.len(), 1);
+        let (message, reason) = &failed[0];
+        assert_eq!(message.data, "not an order request");
+        assert!(reason.starts_with("Failed to deserialize order request"));
+        assert!(bus.failed(RedisQueues::USERS).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_version_mismatches_are_replied_to_and_acked() {
+        let bus = start_engine();
+        create_user(bus.as_ref(), "bus_user").await.unwrap();
+
+        let pubsub_id = Uuid::new_v4();
+        let request = format!(
+            r#"{{"version":2,"payload":{{"GetDepth":{{"symbol":"SOL_USDC","pubsub_id":"{}"}}}}}}"#,
+            pubsub_id
+        );
+
+        let reply = bus
+            .push_and_wait_for_subscriber(RedisQueues::ORDERS, request, pubsub_id)
+            .await
+            .unwrap();
+        let reply: EngineReply<()> = protocol::decode(&reply).unwrap();
+        assert_eq!(reply.unwrap_err().code, ErrorCode::ProtocolMismatch);
+
+        // Handled in queue order - once this is replied to, the mismatch was acked or failed
+        create_order(bus.as_ref(), "bus_user", OrderSide::BUY, dec!(1), dec!(1))
+            .await
+            .unwrap();
+        assert!(bus.failed(RedisQueues::ORDERS).is_empty());
+    }
+}
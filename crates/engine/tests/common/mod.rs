@@ -0,0 +1,23 @@
+use db_processor::registry::AssetRegistry;
+use db_processor::types::{DbAsset, DbMarket};
+use rust_decimal_macros::dec;
+
+// SOL and USDC with the SOL_USDC market, shared by the engine test files
+pub fn test_registry() -> AssetRegistry {
+    let asset = |symbol: &str| DbAsset {
+        symbol: symbol.to_string(),
+        precision: 8,
+        withdrawal_fee: dec!(0),
+        enabled: true,
+    };
+
+    AssetRegistry::new(
+        vec![asset("SOL"), asset("USDC")],
+        vec![DbMarket {
+            symbol: "SOL_USDC".to_string(),
+            base_asset: "SOL".to_string(),
+            quote_asset: "USDC".to_string(),
+            enabled: true,
+        }],
+    )
+}
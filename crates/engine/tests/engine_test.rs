@@ -1,20 +1,52 @@
+mod common;
+
 #[cfg(test)]
 mod tests {
-    use engine::engine::{AmountType, Engine};
-    use engine::types::engine::{Asset, CreateOrder, OrderSide};
+    use crate::common::test_registry;
+    use db_processor::registry::RegistryError;
+    use db_processor::types::{DbAsset, DbMarket};
+    use engine::engine::engine::{AmountType, Engine};
+    use engine::engine::error::EngineError;
+    use engine::engine::kline::LiveKlines;
+    use engine::engine::orderbook::{public_order_id, OrderBook};
+    use engine::engine::ticker::{RollingTicker, TICKER_WINDOW_MS};
+    use engine::types::engine::{Asset, AssetPair};
+    use protocol::errors::ErrorCode;
+    use protocol::klines::KlineInterval;
+    use protocol::orders::{
+        BatchCancelOrders, BatchCreateOrders, BatchMode, BatchResult, CancelOrder, CountdownSource,
+        CreateOrder, DepthSubscription, GetBookTicker, GetDepth, GetL3Depth, Liquidity, Order,
+        OrderSide, OrderStatus, OrderType, ResponseType, DEPTH_SUBSCRIPTION_TTL_MS,
+    };
+    use protocol::ws_stream::L3Action;
+    use redis::InMemoryBus;
     use rust_decimal::Decimal;
     use rust_decimal_macros::dec;
 
+    fn test_order(order_id: &str, side: OrderSide, price: Decimal, quantity: Decimal) -> Order {
+        Order {
+            price,
+            quantity,
+            filled_quantity: dec!(0),
+            order_id: order_id.to_string(),
+            user_id: format!("user_{}", order_id),
+            side,
+            order_type: OrderType::LIMIT,
+            order_status: OrderStatus::Pending,
+            timestamp: 0,
+        }
+    }
+
     #[test]
     fn test_engine_creation() {
-        let engine = Engine::new();
+        let engine = Engine::new(test_registry());
         assert_eq!(engine.orderbooks.len(), 0);
         assert_eq!(engine.balances.len(), 0);
     }
 
     #[test]
     fn test_init_user_balance() {
-        let mut engine = Engine::new();
+        let mut engine = Engine::new(test_registry());
         let user_id = "test_user";
         
         engine.init_user_balance(user_id);
@@ -22,21 +54,21 @@ mod tests {
         assert!(engine.balances.contains_key(user_id));
         let user_balance = engine.balances.get(user_id).unwrap().lock().unwrap();
         assert_eq!(user_balance.user_id, user_id);
-        assert!(user_balance.balance.contains_key(&Asset::USDC));
-        assert!(user_balance.balance.contains_key(&Asset::SOL));
+        assert!(user_balance.balance.contains_key(&Asset::new("USDC")));
+        assert!(user_balance.balance.contains_key(&Asset::new("SOL")));
         
-        let usdc_balance = user_balance.balance.get(&Asset::USDC).unwrap();
+        let usdc_balance = user_balance.balance.get(&Asset::new("USDC")).unwrap();
         assert_eq!(usdc_balance.available, dec!(1000000));
         assert_eq!(usdc_balance.locked, dec!(0));
         
-        let sol_balance = user_balance.balance.get(&Asset::SOL).unwrap();
+        let sol_balance = user_balance.balance.get(&Asset::new("SOL")).unwrap();
         assert_eq!(sol_balance.available, dec!(10000));
         assert_eq!(sol_balance.locked, dec!(0));
     }
 
     #[test]
     fn test_check_and_lock_funds_buy() {
-        let mut engine = Engine::new();
+        let mut engine = Engine::new(test_registry());
         let user_id = "test_user";
         
         // 初始化用户余额
@@ -49,6 +81,7 @@ mod tests {
             quantity: dec!(5),
             side: OrderSide::BUY,
             user_id: user_id.to_string(),
+            response_type: ResponseType::FULL,
             pubsub_id: None,
         };
         
@@ -58,14 +91,14 @@ mod tests {
         
         // 验证资金是否正确锁定
         let user_balance = engine.balances.get(user_id).unwrap().lock().unwrap();
-        let usdc_balance = user_balance.balance.get(&Asset::USDC).unwrap();
+        let usdc_balance = user_balance.balance.get(&Asset::new("USDC")).unwrap();
         assert_eq!(usdc_balance.available, dec!(999500)); // 1000000 - 100*5
         assert_eq!(usdc_balance.locked, dec!(500)); // 100*5
     }
 
     #[test]
     fn test_check_and_lock_funds_sell() {
-        let mut engine = Engine::new();
+        let mut engine = Engine::new(test_registry());
         let user_id = "test_user";
         
         // 初始化用户余额
@@ -78,6 +111,7 @@ mod tests {
             quantity: dec!(5),
             side: OrderSide::SELL,
             user_id: user_id.to_string(),
+            response_type: ResponseType::FULL,
             pubsub_id: None,
         };
         
@@ -87,14 +121,14 @@ mod tests {
         
         // 验证资金是否正确锁定
         let user_balance = engine.balances.get(user_id).unwrap().lock().unwrap();
-        let sol_balance = user_balance.balance.get(&Asset::SOL).unwrap();
+        let sol_balance = user_balance.balance.get(&Asset::new("SOL")).unwrap();
         assert_eq!(sol_balance.available, dec!(9995)); // 10000 - 5
         assert_eq!(sol_balance.locked, dec!(5)); // 5
     }
 
     #[test]
     fn test_check_and_lock_funds_insufficient_funds() {
-        let mut engine = Engine::new();
+        let mut engine = Engine::new(test_registry());
         let user_id = "test_user";
         
         // 初始化用户余额
@@ -107,18 +141,92 @@ mod tests {
             quantity: dec!(5),
             side: OrderSide::BUY,
             user_id: user_id.to_string(),
+            response_type: ResponseType::FULL,
             pubsub_id: None,
         };
         
         // 检查并锁定资金应该失败
         let result = engine.check_and_lock_funds(&order);
         assert!(result.is_err());
-        assert_eq!(result.err().unwrap(), "Insufficient funds");
+        assert_eq!(result.err().unwrap(), EngineError::InsufficientFunds);
+    }
+
+    #[test]
+    fn test_check_and_lock_funds_unknown_market() {
+        let mut engine = Engine::new(test_registry());
+        let user_id = "test_user";
+
+        engine.init_user_balance(user_id);
+
+        let order = CreateOrder {
+            market: "DOGE_USDC".to_string(),
+            price: dec!(1),
+            quantity: dec!(5),
+            side: OrderSide::BUY,
+            user_id: user_id.to_string(),
+            response_type: ResponseType::FULL,
+            pubsub_id: None,
+        };
+
+        let result = engine.check_and_lock_funds(&order);
+        assert_eq!(
+            result.err().unwrap(),
+            EngineError::UnknownMarket("DOGE_USDC".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_order_precision() {
+        let engine = Engine::new(test_registry());
+
+        let mut order = CreateOrder {
+            market: "SOL_USDC".to_string(),
+            price: dec!(100.5),
+            quantity: dec!(0.123456789),
+            side: OrderSide::BUY,
+            user_id: "test_user".to_string(),
+            response_type: ResponseType::FULL,
+            pubsub_id: None,
+        };
+
+        let error = engine.validate_order(&order).err().unwrap();
+        assert_eq!(
+            error,
+            EngineError::InvalidPrecision {
+                field: "quantity",
+                precision: 8
+            }
+        );
+        assert_eq!(error.code(), ErrorCode::InvalidPrecision);
+
+        // Trailing zeros do not count towards the precision
+        order.quantity = dec!(0.12345678000);
+        assert!(engine.validate_order(&order).is_ok());
+
+        order.quantity = dec!(0);
+        assert_eq!(
+            engine.validate_order(&order).err().unwrap(),
+            EngineError::InvalidQuantity
+        );
+    }
+
+    #[test]
+    fn test_market_assets_from_registry() {
+        let engine = Engine::new(test_registry());
+
+        let (base, quote) = engine.market_assets("SOL_USDC").unwrap();
+        assert_eq!(base, Asset::new("SOL"));
+        assert_eq!(quote, Asset::new("USDC"));
+
+        assert_eq!(
+            engine.market_assets("BTC_USDC"),
+            Err(RegistryError::UnknownMarket("BTC_USDC".to_string()))
+        );
     }
 
     #[test]
     fn test_update_balance_with_lock() {
-        let mut engine = Engine::new();
+        let mut engine = Engine::new(test_registry());
         let user_id = "test_user";
         
         // 初始化用户余额
@@ -127,7 +235,7 @@ mod tests {
         // 更新用户余额
         let result = engine.update_balance_with_lock(
             user_id.to_string(),
-            Asset::USDC,
+            Asset::new("USDC"),
             dec!(100),
             AmountType::AVAILABLE,
         );
@@ -136,7 +244,845 @@ mod tests {
         
         // 验证余额是否正确更新
         let user_balance = engine.balances.get(user_id).unwrap().lock().unwrap();
-        let usdc_balance = user_balance.balance.get(&Asset::USDC).unwrap();
+        let usdc_balance = user_balance.balance.get(&Asset::new("USDC")).unwrap();
         assert_eq!(usdc_balance.available, dec!(1000100)); // 1000000 + 100
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_execution_report_partial_fill() {
+        let mut orderbook = OrderBook::new(
+            AssetPair {
+                base: Asset::new("SOL"),
+                quote: Asset::new("USDC"),
+            },
+            1,
+        );
+
+        orderbook.process_order(test_order("ask_1", OrderSide::SELL, dec!(100), dec!(2)));
+        orderbook.process_order(test_order("ask_2", OrderSide::SELL, dec!(101), dec!(2)));
+
+        let buy = test_order("buy", OrderSide::BUY, dec!(101), dec!(5));
+        let result = orderbook.process_order(buy.clone());
+
+        // Both asks are consumed and their levels removed, the rest of the buy rests on the book
+        assert_eq!(result.executed_quantity, dec!(4));
+        assert!(orderbook.asks.is_empty());
+        let resting = &orderbook.bids[&dec!(101)][0];
+        assert_eq!(resting.filled_quantity, dec!(4));
+        assert_eq!(resting.order_status, OrderStatus::PartiallyFilled);
+
+        let report =
+            Engine::execution_report(&buy, &result, &Asset::new("SOL"), &Asset::new("USDC"));
+        let execution = report.execution.clone().unwrap();
+        assert_eq!(execution.executed_quantity, dec!(4));
+        assert_eq!(execution.avg_price, Some(dec!(100.5)));
+        assert_eq!(execution.remaining_quantity, dec!(1));
+        assert_eq!(execution.order_status, OrderStatus::PartiallyFilled);
+
+        let fills = report.fills.clone().unwrap();
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0].price, dec!(100));
+        assert_eq!(fills[0].quantity, dec!(2));
+        assert_eq!(fills[0].fee_asset, "SOL");
+        assert_eq!(fills[0].liquidity, Liquidity::TAKER);
+
+        let result_only = report.clone().with_response_type(ResponseType::RESULT);
+        assert!(result_only.execution.is_some());
+        assert!(result_only.fills.is_none());
+
+        let ack = report.with_response_type(ResponseType::ACK);
+        assert!(ack.execution.is_none());
+        assert!(ack.fills.is_none());
+    }
+
+    #[test]
+    fn test_cancel_all_orders_returns_cancelled() {
+        let mut orderbook = OrderBook::new(
+            AssetPair {
+                base: Asset::new("SOL"),
+                quote: Asset::new("USDC"),
+            },
+            1,
+        );
+
+        orderbook.process_order(test_order("bid", OrderSide::BUY, dec!(99), dec!(1)));
+        orderbook.process_order(test_order("ask", OrderSide::SELL, dec!(101), dec!(1)));
+
+        let cancelled = orderbook.cancel_all_orders("user_bid".to_string());
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].order_id, "bid");
+
+        assert!(orderbook.get_open_orders("user_bid".to_string()).is_empty());
+        assert_eq!(orderbook.get_open_orders("user_ask".to_string()).len(), 1);
+    }
+
+    fn batch_engine(user_id: &str) -> Engine {
+        let mut engine = Engine::new(test_registry());
+        engine.orderbooks.push(OrderBook::new(
+            AssetPair {
+                base: Asset::new("SOL"),
+                quote: Asset::new("USDC"),
+            },
+            1,
+        ));
+        engine.init_user_balance(user_id);
+        engine
+    }
+
+    fn batch_buy(price: Decimal, quantity: Decimal) -> CreateOrder {
+        CreateOrder {
+            market: "SOL_USDC".to_string(),
+            price,
+            quantity,
+            side: OrderSide::BUY,
+            user_id: String::new(),
+            response_type: ResponseType::ACK,
+            pubsub_id: None,
+        }
+    }
+
+    fn batch_codes<T>(results: &[BatchResult<T>]) -> Vec<Option<ErrorCode>> {
+        results
+            .iter()
+            .map(|result| match result {
+                BatchResult::Ok(_) => None,
+                BatchResult::Err(error) => Some(error.code),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_all_or_nothing_batch_checks_combined_funds() {
+        let mut engine = batch_engine("maker");
+        let bus = InMemoryBus::new();
+
+        // 600000 USDC each, the user has 1000000
+        let batch = BatchCreateOrders {
+            orders: vec![
+                batch_buy(dec!(100), dec!(6000)),
+                batch_buy(dec!(100), dec!(6000)),
+            ],
+            mode: BatchMode::AllOrNothing,
+            user_id: "maker".to_string(),
+            pubsub_id: None,
+        };
+
+        let results = engine.batch_create_orders(batch, &bus).await.unwrap();
+        assert_eq!(
+            batch_codes(&results),
+            vec![
+                Some(ErrorCode::BatchRejected),
+                Some(ErrorCode::InsufficientFunds)
+            ]
+        );
+
+        // Nothing was placed and no funds are held
+        assert!(engine.orderbooks[0].bids.is_empty());
+        let balances = engine.balances["maker"].lock().unwrap();
+        assert_eq!(balances.balance[&Asset::new("USDC")].locked, dec!(0));
+    }
+
+    #[tokio::test]
+    async fn test_all_or_nothing_batch_does_not_trade_when_a_later_order_fails() {
+        let mut engine = batch_engine("maker");
+        let bus = InMemoryBus::new();
+        engine.init_user_balance("seller");
+        engine
+            .create_order(
+                CreateOrder {
+                    side: OrderSide::SELL,
+                    user_id: "seller".to_string(),
+                    ..batch_buy(dec!(100), dec!(1))
+                },
+                &bus,
+            )
+            .await
+            .unwrap();
+
+        // The first order would trade with the ask, the second needs more USDC than is left after it
+        let batch = BatchCreateOrders {
+            orders: vec![
+                batch_buy(dec!(100), dec!(1)),
+                batch_buy(dec!(100), dec!(10000)),
+            ],
+            mode: BatchMode::AllOrNothing,
+            user_id: "maker".to_string(),
+            pubsub_id: None,
+        };
+
+        let results = engine.batch_create_orders(batch, &bus).await.unwrap();
+        assert_eq!(
+            batch_codes(&results),
+            vec![
+                Some(ErrorCode::BatchRejected),
+                Some(ErrorCode::InsufficientFunds)
+            ]
+        );
+
+        // The ask is untouched and the maker's funds are where they were
+        let ask = &engine.orderbooks[0].asks[&dec!(100)][0];
+        assert_eq!(ask.filled_quantity, dec!(0));
+        assert!(engine.orderbooks[0].bids.is_empty());
+        let balances = engine.balances["maker"].lock().unwrap();
+        let usdc = &balances.balance[&Asset::new("USDC")];
+        assert_eq!((usdc.available, usdc.locked), (dec!(1000000), dec!(0)));
+        assert_eq!(balances.balance[&Asset::new("SOL")].available, dec!(10000));
+    }
+
+    #[tokio::test]
+    async fn test_independent_batch_places_valid_orders() {
+        let mut engine = batch_engine("maker");
+        let bus = InMemoryBus::new();
+
+        let batch = BatchCreateOrders {
+            orders: vec![
+                batch_buy(dec!(100), dec!(6000)),
+                batch_buy(dec!(100), dec!(6000)),
+            ],
+            mode: BatchMode::Independent,
+            user_id: "maker".to_string(),
+            pubsub_id: None,
+        };
+
+        let results = engine.batch_create_orders(batch, &bus).await.unwrap();
+        assert_eq!(
+            batch_codes(&results),
+            vec![None, Some(ErrorCode::InsufficientFunds)]
+        );
+
+        // The batch's user_id is used for every order
+        let open_orders = engine.orderbooks[0].get_open_orders("maker".to_string());
+        assert_eq!(open_orders.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_cancel_reports_each_order() {
+        let mut engine = batch_engine("maker");
+        let bus = InMemoryBus::new();
+
+        let batch = BatchCreateOrders {
+            orders: vec![batch_buy(dec!(99), dec!(1))],
+            mode: BatchMode::Independent,
+            user_id: "maker".to_string(),
+            pubsub_id: None,
+        };
+        let placed = match &engine.batch_create_orders(batch, &bus).await.unwrap()[0] {
+            BatchResult::Ok(response) => response.order_id.clone(),
+            BatchResult::Err(error) => panic!("order rejected: {}", error.msg),
+        };
+
+        let cancel = |order_id: &str| CancelOrder {
+            order_id: order_id.to_string(),
+            user_id: String::new(),
+            price: dec!(99),
+            side: OrderSide::BUY,
+            market: "SOL_USDC".to_string(),
+            pubsub_id: None,
+        };
+        let batch = BatchCancelOrders {
+            orders: vec![cancel(&placed), cancel("missing")],
+            user_id: "maker".to_string(),
+            pubsub_id: None,
+        };
+
+        let results = engine.batch_cancel_orders(batch, &bus).await.unwrap();
+        assert_eq!(
+            batch_codes(&results),
+            vec![None, Some(ErrorCode::UnknownOrder)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cannot_cancel_another_users_order() {
+        let mut engine = batch_engine("alice");
+        engine.init_user_balance("bob");
+        let bus = InMemoryBus::new();
+
+        let order = CreateOrder {
+            user_id: "alice".to_string(),
+            ..batch_buy(dec!(99), dec!(1))
+        };
+        let order_id = engine.create_order(order, &bus).await.unwrap().order_id;
+
+        let cancel = |user_id: &str| CancelOrder {
+            order_id: order_id.clone(),
+            user_id: user_id.to_string(),
+            price: dec!(99),
+            side: OrderSide::BUY,
+            market: "SOL_USDC".to_string(),
+            pubsub_id: None,
+        };
+
+        // Bob knows the id but not the order, neither alone nor in a batch
+        assert_eq!(
+            engine.cancel_order(cancel("bob"), &bus).await,
+            Err(EngineError::UnknownOrder(order_id.clone()))
+        );
+        let batch = BatchCancelOrders {
+            orders: vec![cancel("")],
+            user_id: "bob".to_string(),
+            pubsub_id: None,
+        };
+        let results = engine.batch_cancel_orders(batch, &bus).await.unwrap();
+        assert_eq!(batch_codes(&results), vec![Some(ErrorCode::UnknownOrder)]);
+        assert_eq!(
+            engine.orderbooks[0]
+                .get_open_orders("alice".to_string())
+                .len(),
+            1
+        );
+
+        assert_eq!(
+            engine.cancel_order(cancel("alice"), &bus).await,
+            Ok(order_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_size_is_limited() {
+        let mut engine = batch_engine("maker");
+        let bus = InMemoryBus::new();
+
+        let batch = BatchCreateOrders {
+            orders: vec![],
+            mode: BatchMode::Independent,
+            user_id: "maker".to_string(),
+            pubsub_id: None,
+        };
+
+        assert_eq!(
+            engine.batch_create_orders(batch, &bus).await.unwrap_err(),
+            EngineError::InvalidBatchSize(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_order_on_market_added_at_runtime() {
+        let mut engine = batch_engine("maker");
+        let bus = InMemoryBus::new();
+
+        let btc = DbAsset {
+            symbol: "BTC".to_string(),
+            precision: 8,
+            withdrawal_fee: dec!(0),
+            enabled: true,
+        };
+        let btc_usdc = DbMarket {
+            symbol: "BTC_USDC".to_string(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDC".to_string(),
+            enabled: true,
+        };
+        engine.add_market(vec![btc], btc_usdc).unwrap();
+
+        let buy = CreateOrder {
+            market: "BTC_USDC".to_string(),
+            user_id: "maker".to_string(),
+            ..batch_buy(dec!(100), dec!(2))
+        };
+        engine.create_order(buy.clone(), &bus).await.unwrap();
+
+        // Existing users got an empty BTC balance, so selling fails on funds and not on the asset
+        let sell = CreateOrder {
+            side: OrderSide::SELL,
+            ..buy
+        };
+        assert_eq!(
+            engine.create_order(sell, &bus).await.unwrap_err(),
+            EngineError::InsufficientFunds
+        );
+
+        {
+            let balances = engine.balances["maker"].lock().unwrap();
+            assert_eq!(balances.balance[&Asset::new("USDC")].locked, dec!(200));
+            assert_eq!(balances.balance[&Asset::new("BTC")].available, dec!(0));
+        }
+
+        // Users created afterwards start with every listed asset
+        engine.init_user_balance("newcomer");
+        let balances = engine.balances["newcomer"].lock().unwrap();
+        assert_eq!(balances.balance[&Asset::new("BTC")].available, dec!(0));
+    }
+
+    #[tokio::test]
+    async fn test_expired_countdown_cancels_all_orders() {
+        let mut engine = batch_engine("maker");
+        let bus = InMemoryBus::new();
+
+        let order = CreateOrder {
+            user_id: "maker".to_string(),
+            ..batch_buy(dec!(99), dec!(1))
+        };
+        engine.create_order(order, &bus).await.unwrap();
+
+        assert_eq!(
+            engine.set_countdown("maker", CountdownSource::Api, 500, 0),
+            Err(EngineError::InvalidCountdown(500))
+        );
+        assert_eq!(
+            engine.set_countdown("maker", CountdownSource::Api, 5000, 1000),
+            Ok(Some(6000))
+        );
+
+        engine.expire_countdowns(5999, &bus).await;
+        let open_orders = engine.orderbooks[0].get_open_orders("maker".to_string());
+        assert_eq!(open_orders.len(), 1);
+
+        engine.expire_countdowns(6000, &bus).await;
+        assert!(engine.orderbooks[0]
+            .get_open_orders("maker".to_string())
+            .is_empty());
+        assert!(engine.countdowns.is_empty());
+
+        // The funds held by the cancelled order are released
+        let balances = engine.balances["maker"].lock().unwrap();
+        assert_eq!(balances.balance[&Asset::new("USDC")].locked, dec!(0));
+    }
+
+    #[test]
+    fn test_countdown_is_switched_off_with_zero() {
+        let mut engine = batch_engine("maker");
+
+        engine
+            .set_countdown("maker", CountdownSource::Api, 5000, 0)
+            .unwrap();
+        assert_eq!(
+            engine.set_countdown("maker", CountdownSource::Api, 0, 1000),
+            Ok(None)
+        );
+        assert!(engine.countdowns.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_countdown_sources_run_side_by_side() {
+        let mut engine = batch_engine("maker");
+        let bus = InMemoryBus::new();
+
+        let order = CreateOrder {
+            user_id: "maker".to_string(),
+            ..batch_buy(dec!(99), dec!(1))
+        };
+        engine.create_order(order, &bus).await.unwrap();
+
+        engine
+            .set_countdown("maker", CountdownSource::Api, 60_000, 0)
+            .unwrap();
+        engine
+            .set_countdown("maker", CountdownSource::Disconnect, 10_000, 0)
+            .unwrap();
+
+        // Neither refreshing nor switching off the disconnect countdown touches the other one
+        engine
+            .set_countdown("maker", CountdownSource::Disconnect, 10_000, 5000)
+            .unwrap();
+        engine
+            .set_countdown("maker", CountdownSource::Disconnect, 0, 6000)
+            .unwrap();
+        assert_eq!(
+            engine.countdowns["maker"].get(&CountdownSource::Api),
+            Some(&60_000)
+        );
+
+        engine.expire_countdowns(59_999, &bus).await;
+        let open_orders = engine.orderbooks[0].get_open_orders("maker".to_string());
+        assert_eq!(open_orders.len(), 1);
+
+        engine.expire_countdowns(60_000, &bus).await;
+        assert!(engine.orderbooks[0]
+            .get_open_orders("maker".to_string())
+            .is_empty());
+        assert!(engine.countdowns.is_empty());
+    }
+
+    #[test]
+    fn test_every_book_change_bumps_update_id() {
+        let mut orderbook = OrderBook::new(
+            AssetPair {
+                base: Asset::new("SOL"),
+                quote: Asset::new("USDC"),
+            },
+            1,
+        );
+        assert_eq!(orderbook.last_update_id(), 0);
+
+        orderbook.process_order(test_order("ask", OrderSide::SELL, dec!(101), dec!(2)));
+        orderbook.process_order(test_order("buy", OrderSide::BUY, dec!(101), dec!(1)));
+        assert_eq!(orderbook.last_update_id(), 2);
+
+        let cancel = |order_id: &str| CancelOrder {
+            order_id: order_id.to_string(),
+            user_id: format!("user_{}", order_id),
+            price: dec!(101),
+            side: OrderSide::SELL,
+            market: "SOL_USDC".to_string(),
+            pubsub_id: None,
+        };
+
+        // Nothing changes when there is nothing to cancel
+        assert!(orderbook.cancel_order(cancel("missing")).is_none());
+        assert!(orderbook.cancel_all_orders("nobody".to_string()).is_empty());
+        assert_eq!(orderbook.last_update_id(), 2);
+
+        assert!(orderbook.cancel_order(cancel("ask")).is_some());
+        assert_eq!(orderbook.last_update_id(), 3);
+    }
+
+    #[test]
+    fn test_depth_snapshot_carries_update_id() {
+        let mut engine = batch_engine("maker");
+        engine.orderbooks[0].process_order(test_order("bid", OrderSide::BUY, dec!(99), dec!(1)));
+
+        let depth = engine
+            .get_depth(GetDepth {
+                symbol: "SOL_USDC".to_string(),
+                limit: None,
+                step: None,
+                pubsub_id: None,
+            })
+            .unwrap();
+        assert_eq!(depth.last_update_id, 1);
+        assert_eq!(depth.bids, vec![(dec!(99), dec!(1))]);
+    }
+
+    #[test]
+    fn test_depth_limit_and_grouping() {
+        let mut engine = batch_engine("maker");
+        for (id, price) in [
+            ("bid_1", dec!(99.95)),
+            ("bid_2", dec!(99.9)),
+            ("bid_3", dec!(99.1)),
+        ] {
+            engine.orderbooks[0].process_order(test_order(id, OrderSide::BUY, price, dec!(1)));
+        }
+        for (id, price) in [("ask_1", dec!(100.05)), ("ask_2", dec!(100.3))] {
+            engine.orderbooks[0].process_order(test_order(id, OrderSide::SELL, price, dec!(2)));
+        }
+
+        let depth = |limit: Option<usize>, step: Option<Decimal>| {
+            engine.get_depth(GetDepth {
+                symbol: "SOL_USDC".to_string(),
+                limit,
+                step,
+                pubsub_id: None,
+            })
+        };
+
+        // Bids come back best-first
+        let full = depth(None, None).unwrap();
+        assert_eq!(
+            full.bids,
+            vec![
+                (dec!(99.95), dec!(1)),
+                (dec!(99.9), dec!(1)),
+                (dec!(99.1), dec!(1))
+            ]
+        );
+
+        let top = depth(Some(5), Some(dec!(0.5))).unwrap();
+        assert_eq!(top.bids, vec![(dec!(99.5), dec!(2)), (dec!(99.0), dec!(1))]);
+        assert_eq!(top.asks, vec![(dec!(100.5), dec!(4))]);
+
+        let grouped = depth(Some(5), Some(dec!(1))).unwrap();
+        assert_eq!(grouped.bids, vec![(dec!(99), dec!(3))]);
+        assert_eq!(grouped.asks, vec![(dec!(101), dec!(4))]);
+
+        assert_eq!(
+            depth(Some(7), None).unwrap_err(),
+            EngineError::InvalidDepthLimit(7)
+        );
+        assert_eq!(
+            depth(Some(5), Some(dec!(0))).unwrap_err(),
+            EngineError::InvalidDepthStep(dec!(0))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_depth_subscriptions_expire_unless_refreshed() {
+        let mut engine = batch_engine("maker");
+        let bus = InMemoryBus::new();
+        let subscription = |limit: usize| DepthSubscription {
+            symbol: "SOL_USDC".to_string(),
+            limit,
+            step: None,
+        };
+
+        engine
+            .subscribe_depth(subscription(5), 0, &bus)
+            .await
+            .unwrap();
+        engine
+            .subscribe_depth(subscription(10), 0, &bus)
+            .await
+            .unwrap();
+
+        // Refreshing one registration keeps it, the other one lapses
+        let refreshed_at = DEPTH_SUBSCRIPTION_TTL_MS - 1;
+        engine
+            .subscribe_depth(subscription(5), refreshed_at, &bus)
+            .await
+            .unwrap();
+        engine
+            .subscribe_depth(subscription(20), DEPTH_SUBSCRIPTION_TTL_MS, &bus)
+            .await
+            .unwrap();
+        assert_eq!(
+            engine.depth_subscriptions.get(&subscription(5)),
+            Some(&(refreshed_at + DEPTH_SUBSCRIPTION_TTL_MS))
+        );
+        assert!(!engine.depth_subscriptions.contains_key(&subscription(10)));
+
+        engine.unsubscribe_depth(&subscription(5));
+        assert_eq!(engine.depth_subscriptions.len(), 1);
+    }
+
+    #[test]
+    fn test_depth_diff_follows_book_changes() {
+        let mut orderbook = OrderBook::new(
+            AssetPair {
+                base: Asset::new("SOL"),
+                quote: Asset::new("USDC"),
+            },
+            1,
+        );
+        assert!(orderbook.take_depth_diff().is_none());
+
+        orderbook.process_order(test_order("ask_1", OrderSide::SELL, dec!(100), dec!(2)));
+        orderbook.process_order(test_order("ask_2", OrderSide::SELL, dec!(101), dec!(1)));
+        let diff = orderbook.take_depth_diff().unwrap();
+        assert_eq!((diff.first_update_id, diff.last_update_id), (1, 2));
+        assert_eq!(diff.asks, vec![(dec!(100), dec!(2)), (dec!(101), dec!(1))]);
+        assert!(diff.bids.is_empty());
+
+        // Eats the level at 100, partially fills 101 and rests nothing
+        orderbook.process_order(test_order("buy", OrderSide::BUY, dec!(101), dec!(2.5)));
+        let diff = orderbook.take_depth_diff().unwrap();
+        assert_eq!((diff.first_update_id, diff.last_update_id), (3, 3));
+        assert_eq!(
+            diff.asks,
+            vec![(dec!(100), dec!(0)), (dec!(101), dec!(0.5))]
+        );
+        assert!(diff.bids.is_empty());
+        assert_eq!(
+            orderbook.get_depth(100, None).1,
+            vec![(dec!(101), dec!(0.5))]
+        );
+
+        let cancelled = orderbook.cancel_all_orders("user_ask_2".to_string());
+        assert_eq!(cancelled.len(), 1);
+        let diff = orderbook.take_depth_diff().unwrap();
+        assert_eq!((diff.first_update_id, diff.last_update_id), (4, 4));
+        assert_eq!(diff.asks, vec![(dec!(101), dec!(0))]);
+        assert!(orderbook.asks.is_empty());
+    }
+
+    #[test]
+    fn test_l3_book_and_order_events() {
+        let mut engine = batch_engine("maker");
+        for (id, quantity) in [("ask_1", dec!(2)), ("ask_2", dec!(1))] {
+            let ask = test_order(id, OrderSide::SELL, dec!(100), quantity);
+            engine.orderbooks[0].process_order(ask);
+        }
+        engine.orderbooks[0].process_order(test_order("bid", OrderSide::BUY, dec!(99), dec!(1)));
+
+        let book = engine
+            .get_l3_depth(GetL3Depth {
+                symbol: "SOL_USDC".to_string(),
+                pubsub_id: None,
+            })
+            .unwrap();
+        assert_eq!(book.last_update_id, 3);
+        let queue: Vec<(String, Decimal, usize)> = book.asks[0]
+            .orders
+            .iter()
+            .map(|order| (order.order_id.clone(), order.quantity, order.queue_position))
+            .collect();
+        assert_eq!(
+            queue,
+            vec![
+                (public_order_id("ask_1"), dec!(2), 1),
+                (public_order_id("ask_2"), dec!(1), 2)
+            ]
+        );
+        assert_ne!(book.bids[0].orders[0].order_id, "bid");
+
+        let update = engine.orderbooks[0].take_l3_update().unwrap();
+        assert_eq!((update.first_update_id, update.last_update_id), (1, 3));
+        assert!(update.orders.iter().all(|e| e.action == L3Action::Add));
+
+        // Partially fills the first ask and cancels the bid
+        engine.orderbooks[0].process_order(test_order("buy", OrderSide::BUY, dec!(100), dec!(0.5)));
+        engine.orderbooks[0].cancel_order(CancelOrder {
+            order_id: "bid".to_string(),
+            user_id: "user_bid".to_string(),
+            price: dec!(99),
+            side: OrderSide::BUY,
+            market: "SOL_USDC".to_string(),
+            pubsub_id: None,
+        });
+
+        let update = engine.orderbooks[0].take_l3_update().unwrap();
+        assert_eq!((update.first_update_id, update.last_update_id), (4, 5));
+        let events: Vec<(L3Action, String, Decimal)> = update
+            .orders
+            .iter()
+            .map(|event| (event.action, event.order_id.clone(), event.quantity))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (L3Action::Modify, public_order_id("ask_1"), dec!(1.5)),
+                (L3Action::Delete, public_order_id("bid"), dec!(0))
+            ]
+        );
+        assert!(engine.orderbooks[0].take_l3_update().is_none());
+    }
+
+    #[test]
+    fn test_book_ticker_follows_top_of_book() {
+        let mut engine = batch_engine("maker");
+        let orderbook = &mut engine.orderbooks[0];
+        assert!(orderbook.take_book_ticker().is_none());
+
+        orderbook.process_order(test_order("ask", OrderSide::SELL, dec!(101), dec!(2)));
+        let ticker = orderbook.take_book_ticker().unwrap();
+        assert_eq!(ticker.bid_price, None);
+        assert_eq!(
+            (ticker.ask_price, ticker.ask_quantity),
+            (Some(dec!(101)), Some(dec!(2)))
+        );
+
+        // Deeper levels do not move the top
+        orderbook.process_order(test_order("deep_ask", OrderSide::SELL, dec!(105), dec!(1)));
+        assert!(orderbook.take_book_ticker().is_none());
+
+        orderbook.process_order(test_order("buy", OrderSide::BUY, dec!(101), dec!(0.5)));
+        let ticker = orderbook.take_book_ticker().unwrap();
+        assert_eq!(ticker.last_update_id, 3);
+        assert_eq!(ticker.ask_quantity, Some(dec!(1.5)));
+
+        let book_ticker = engine
+            .get_book_ticker(GetBookTicker {
+                symbol: "SOL_USDC".to_string(),
+                pubsub_id: None,
+            })
+            .unwrap();
+        assert_eq!(book_ticker.ask_price, Some(dec!(101)));
+        assert_eq!(book_ticker.bid_quantity, None);
+    }
+
+    #[test]
+    fn test_rolling_ticker_window() {
+        let mut ticker = RollingTicker::new();
+        assert!(ticker.snapshot("SOL_USDC", 0).is_none());
+
+        let start = 1_700_000_000_000;
+        ticker.record_trade(dec!(100), dec!(1), start);
+        ticker.record_trade(dec!(110), dec!(2), start + 1_000);
+        ticker.record_trade(dec!(95), dec!(1), start + 120_000);
+
+        let stats = ticker.snapshot("SOL_USDC", start + 180_000).unwrap();
+        assert_eq!(
+            (stats.open, stats.high, stats.low),
+            (dec!(100), dec!(110), dec!(95))
+        );
+        assert_eq!(stats.last_price, dec!(95));
+        assert_eq!(stats.volume, dec!(4));
+        assert_eq!(stats.quote_volume, dec!(415));
+        assert_eq!(stats.trades, 3);
+        assert_eq!(stats.price_change_percent, dec!(-5));
+
+        // The first minute leaves the window
+        let stats = ticker
+            .snapshot("SOL_USDC", start + TICKER_WINDOW_MS + 60_000)
+            .unwrap();
+        assert_eq!(
+            (stats.open, stats.high, stats.trades),
+            (dec!(95), dec!(95), 1)
+        );
+
+        // A quiet market keeps its last price
+        let stats = ticker
+            .snapshot("SOL_USDC", start + 2 * TICKER_WINDOW_MS)
+            .unwrap();
+        assert_eq!((stats.last_price, stats.volume), (dec!(95), dec!(0)));
+        assert_eq!(stats.price_change, dec!(0));
+    }
+
+    #[test]
+    fn test_live_klines() {
+        let mut klines = LiveKlines::new();
+        // Tuesday 2023-11-14 22:13:20 UTC
+        let start = 1_700_000_000_000;
+
+        assert!(klines.record_trade(dec!(100), dec!(1), start).is_empty());
+        assert!(klines
+            .record_trade(dec!(110), dec!(2), start + 1_000)
+            .is_empty());
+
+        let open = klines.open_klines();
+        assert_eq!(open.len(), KlineInterval::ALL.len());
+        let minute = &open[0];
+        assert_eq!(minute.interval, KlineInterval::Minute1);
+        assert_eq!(
+            (minute.open_time, minute.close_time),
+            (1_699_999_980_000, 1_700_000_039_999)
+        );
+        assert_eq!(
+            (minute.open, minute.high, minute.low, minute.close),
+            (dec!(100), dec!(110), dec!(100), dec!(110))
+        );
+        assert_eq!((minute.volume, minute.quote_volume), (dec!(3), dec!(320)));
+        assert_eq!((minute.trades, minute.closed), (2, false));
+
+        // Weeks start on Monday and months on the 1st, in UTC
+        let week = open
+            .iter()
+            .find(|kline| kline.interval == KlineInterval::Week1)
+            .unwrap();
+        assert_eq!(week.open_time, 1_699_833_600_000);
+        let month = open
+            .iter()
+            .find(|kline| kline.interval == KlineInterval::Month1)
+            .unwrap();
+        assert_eq!(
+            (month.open_time, month.close_time),
+            (1_698_796_800_000, 1_701_388_799_999)
+        );
+
+        // The next minute's trade closes the minute candle only
+        let closed = klines.record_trade(dec!(90), dec!(1), start + 60_000);
+        assert_eq!(closed.len(), 1);
+        assert!(closed[0].closed);
+        assert_eq!((closed[0].close, closed[0].trades), (dec!(110), 2));
+        assert_eq!(klines.open_klines()[0].open, dec!(90));
+        let hour = klines
+            .open_klines()
+            .into_iter()
+            .find(|kline| kline.interval == KlineInterval::Hour1)
+            .unwrap();
+        assert_eq!((hour.open_time, hour.trades), (1_699_999_200_000, 3));
+
+        // A quiet market closes its candles once the bucket is over
+        let closed = klines.close_due(start + 60 * 60 * 1000);
+        assert_eq!(
+            closed
+                .iter()
+                .map(|kline| kline.interval)
+                .collect::<Vec<_>>(),
+            vec![
+                KlineInterval::Minute1,
+                KlineInterval::Minute3,
+                KlineInterval::Minute5,
+                KlineInterval::Minute15,
+                KlineInterval::Minute30,
+                KlineInterval::Hour1
+            ]
+        );
+        assert!(klines.close_due(start + 60 * 60 * 1000).is_empty());
+        assert_eq!(klines.open_klines().len(), 9);
+
+        // 4h and 3d buckets are aligned to the epoch
+        assert_eq!(KlineInterval::Hour4.bucket_start(start), 1_699_992_000_000);
+        assert_eq!(KlineInterval::Day3.bucket_start(start), 1_699_833_600_000);
+    }
+}
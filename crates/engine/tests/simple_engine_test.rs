@@ -1,20 +1,22 @@
+mod common;
+
 #[cfg(test)]
 mod tests {
-    use engine::engine::Engine;
-    use engine::types::engine::{Asset, CreateOrder, OrderSide};
-    use rust_decimal::Decimal;
+    use crate::common::test_registry;
+    use engine::engine::engine::Engine;
+    use protocol::orders::{CreateOrder, OrderSide, ResponseType};
     use rust_decimal_macros::dec;
 
     #[test]
     fn test_engine_creation() {
-        let engine = Engine::new();
+        let engine = Engine::new(test_registry());
         assert_eq!(engine.orderbooks.len(), 0);
         assert_eq!(engine.balances.len(), 0);
     }
 
     #[test]
     fn test_init_user_balance() {
-        let mut engine = Engine::new();
+        let mut engine = Engine::new(test_registry());
         let user_id = "test_user";
         
         engine.init_user_balance(user_id);
@@ -24,7 +26,7 @@ mod tests {
 
     #[test]
     fn test_check_and_lock_funds_insufficient_funds() {
-        let mut engine = Engine::new();
+        let mut engine = Engine::new(test_registry());
         let user_id = "test_user";
         
         // 初始化用户余额
@@ -37,6 +39,7 @@ mod tests {
             quantity: dec!(5),
             side: OrderSide::BUY,
             user_id: user_id.to_string(),
+            response_type: ResponseType::FULL,
             pubsub_id: None,
         };
         
@@ -1,6 +1,10 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
+// ----------------------------------------
+// REQUESTS - engine -> db-processor on the database queue
+// ----------------------------------------
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DatabaseRequests {
     InsertTrade(DbTrade),
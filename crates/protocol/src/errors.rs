@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+// Stable numeric codes shared by the engine and the router - never reuse a retired code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "u16", try_from = "u16")]
+pub enum ErrorCode {
+    Internal = 1000,
+    ProtocolMismatch = 1001,
+    EngineTimeout = 1002,
+    UnknownMarket = 2000,
+    UnknownAsset = 2001,
+    MarketDisabled = 2002,
+    AssetDisabled = 2003,
+    InvalidPrecision = 2010,
+    InvalidQuantity = 2011,
+    InvalidPrice = 2012,
+    InvalidBatch = 2020,
+    BatchRejected = 2021,
+    InvalidCountdown = 2030,
+    InvalidDepth = 2040,
+    InvalidKlines = 2050,
+    UnknownUser = 3000,
+    InsufficientFunds = 3001,
+    UnknownOrder = 4000,
+    Unauthorized = 5000,
+    InvalidSignature = 5001,
+    InvalidTimestamp = 5002,
+    EmailTaken = 5003,
+    InvalidCredentials = 5004,
+    InvalidRegistration = 5005,
+    TwoFactorRequired = 5006,
+    InvalidTwoFactorCode = 5007,
+    TwoFactorAlreadyEnabled = 5008,
+    TwoFactorNotEnrolled = 5009,
+    RateLimited = 6000,
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> u16 {
+        code as u16
+    }
+}
+
+impl TryFrom<u16> for ErrorCode {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<ErrorCode, String> {
+        match code {
+            1000 => Ok(ErrorCode::Internal),
+            1001 => Ok(ErrorCode::ProtocolMismatch),
+            1002 => Ok(ErrorCode::EngineTimeout),
+            2000 => Ok(ErrorCode::UnknownMarket),
+            2001 => Ok(ErrorCode::UnknownAsset),
+            2002 => Ok(ErrorCode::MarketDisabled),
+            2003 => Ok(ErrorCode::AssetDisabled),
+            2010 => Ok(ErrorCode::InvalidPrecision),
+            2011 => Ok(ErrorCode::InvalidQuantity),
+            2012 => Ok(ErrorCode::InvalidPrice),
+            2020 => Ok(ErrorCode::InvalidBatch),
+            2021 => Ok(ErrorCode::BatchRejected),
+            2030 => Ok(ErrorCode::InvalidCountdown),
+            2040 => Ok(ErrorCode::InvalidDepth),
+            2050 => Ok(ErrorCode::InvalidKlines),
+            3000 => Ok(ErrorCode::UnknownUser),
+            3001 => Ok(ErrorCode::InsufficientFunds),
+            4000 => Ok(ErrorCode::UnknownOrder),
+            5000 => Ok(ErrorCode::Unauthorized),
+            5001 => Ok(ErrorCode::InvalidSignature),
+            5002 => Ok(ErrorCode::InvalidTimestamp),
+            5003 => Ok(ErrorCode::EmailTaken),
+            5004 => Ok(ErrorCode::InvalidCredentials),
+            5005 => Ok(ErrorCode::InvalidRegistration),
+            5006 => Ok(ErrorCode::TwoFactorRequired),
+            5007 => Ok(ErrorCode::InvalidTwoFactorCode),
+            5008 => Ok(ErrorCode::TwoFactorAlreadyEnabled),
+            5009 => Ok(ErrorCode::TwoFactorNotEnrolled),
+            6000 => Ok(ErrorCode::RateLimited),
+            _ => Err(format!("Unknown error code {}", code)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub msg: String,
+}
+
+// Every engine reply is either the request's response or an error with a code
+pub type EngineReply<T> = Result<T, ErrorResponse>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, encode};
+
+    #[test]
+    fn error_codes_are_numeric() {
+        let reply: EngineReply<()> = Err(ErrorResponse {
+            code: ErrorCode::InsufficientFunds,
+            msg: "Insufficient funds".to_string(),
+        });
+
+        let data = encode(&reply);
+        assert!(data.contains(r#""code":3001"#));
+
+        let decoded: EngineReply<()> = decode(&data).unwrap();
+        assert_eq!(decoded.unwrap_err().code, ErrorCode::InsufficientFunds);
+    }
+}
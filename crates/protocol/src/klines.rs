@@ -0,0 +1,209 @@
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+const MINUTE_MS: i64 = 60 * 1000;
+const HOUR_MS: i64 = 60 * MINUTE_MS;
+const DAY_MS: i64 = 24 * HOUR_MS;
+const WEEK_MS: i64 = 7 * DAY_MS;
+// 1970-01-01 was a Thursday, weeks start on Monday like Postgres' date_trunc('week')
+const FIRST_MONDAY_MS: i64 = 4 * DAY_MS;
+
+// Candles returned by GET /klines when no limit is given, and the most it returns at once
+pub const DEFAULT_KLINE_LIMIT: usize = 500;
+pub const MAX_KLINE_LIMIT: usize = 1000;
+
+// Candle intervals, named like Binance - "1m" is a minute and "1M" a month
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KlineInterval {
+    #[serde(rename = "1m")]
+    Minute1,
+    #[serde(rename = "3m")]
+    Minute3,
+    #[serde(rename = "5m")]
+    Minute5,
+    #[serde(rename = "15m")]
+    Minute15,
+    #[serde(rename = "30m")]
+    Minute30,
+    #[serde(rename = "1h")]
+    Hour1,
+    #[serde(rename = "2h")]
+    Hour2,
+    #[serde(rename = "4h")]
+    Hour4,
+    #[serde(rename = "6h")]
+    Hour6,
+    #[serde(rename = "8h")]
+    Hour8,
+    #[serde(rename = "12h")]
+    Hour12,
+    #[serde(rename = "1d")]
+    Day1,
+    #[serde(rename = "3d")]
+    Day3,
+    #[serde(rename = "1w")]
+    Week1,
+    #[serde(rename = "1M")]
+    Month1,
+}
+
+impl KlineInterval {
+    pub const ALL: [KlineInterval; 15] = [
+        KlineInterval::Minute1,
+        KlineInterval::Minute3,
+        KlineInterval::Minute5,
+        KlineInterval::Minute15,
+        KlineInterval::Minute30,
+        KlineInterval::Hour1,
+        KlineInterval::Hour2,
+        KlineInterval::Hour4,
+        KlineInterval::Hour6,
+        KlineInterval::Hour8,
+        KlineInterval::Hour12,
+        KlineInterval::Day1,
+        KlineInterval::Day3,
+        KlineInterval::Week1,
+        KlineInterval::Month1,
+    ];
+
+    // Intervals db-processor stores as candles, the others are rolled up from them
+    pub const BASE: [KlineInterval; 3] = [
+        KlineInterval::Minute1,
+        KlineInterval::Hour1,
+        KlineInterval::Day1,
+    ];
+
+    // Case sensitive, there is no fallback for an unknown interval
+    pub fn parse(code: &str) -> Option<KlineInterval> {
+        KlineInterval::ALL
+            .into_iter()
+            .find(|interval| interval.code() == code)
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            KlineInterval::Minute1 => "1m",
+            KlineInterval::Minute3 => "3m",
+            KlineInterval::Minute5 => "5m",
+            KlineInterval::Minute15 => "15m",
+            KlineInterval::Minute30 => "30m",
+            KlineInterval::Hour1 => "1h",
+            KlineInterval::Hour2 => "2h",
+            KlineInterval::Hour4 => "4h",
+            KlineInterval::Hour6 => "6h",
+            KlineInterval::Hour8 => "8h",
+            KlineInterval::Hour12 => "12h",
+            KlineInterval::Day1 => "1d",
+            KlineInterval::Day3 => "3d",
+            KlineInterval::Week1 => "1w",
+            KlineInterval::Month1 => "1M",
+        }
+    }
+
+    // The stored interval this one is rolled up from, each of its buckets is made of whole base buckets
+    pub fn base_interval(&self) -> KlineInterval {
+        match self {
+            KlineInterval::Minute1
+            | KlineInterval::Minute3
+            | KlineInterval::Minute5
+            | KlineInterval::Minute15
+            | KlineInterval::Minute30 => KlineInterval::Minute1,
+            KlineInterval::Hour1
+            | KlineInterval::Hour2
+            | KlineInterval::Hour4
+            | KlineInterval::Hour6
+            | KlineInterval::Hour8
+            | KlineInterval::Hour12 => KlineInterval::Hour1,
+            KlineInterval::Day1
+            | KlineInterval::Day3
+            | KlineInterval::Week1
+            | KlineInterval::Month1 => KlineInterval::Day1,
+        }
+    }
+
+    // (length, offset from the epoch) of the buckets, None for months which differ in length.
+    // Buckets are aligned to the epoch like Binance's, so 3d buckets do not start on a fixed weekday
+    pub fn fixed_bucket(&self) -> Option<(i64, i64)> {
+        let length = match self {
+            KlineInterval::Minute1 => MINUTE_MS,
+            KlineInterval::Minute3 => 3 * MINUTE_MS,
+            KlineInterval::Minute5 => 5 * MINUTE_MS,
+            KlineInterval::Minute15 => 15 * MINUTE_MS,
+            KlineInterval::Minute30 => 30 * MINUTE_MS,
+            KlineInterval::Hour1 => HOUR_MS,
+            KlineInterval::Hour2 => 2 * HOUR_MS,
+            KlineInterval::Hour4 => 4 * HOUR_MS,
+            KlineInterval::Hour6 => 6 * HOUR_MS,
+            KlineInterval::Hour8 => 8 * HOUR_MS,
+            KlineInterval::Hour12 => 12 * HOUR_MS,
+            KlineInterval::Day1 => DAY_MS,
+            KlineInterval::Day3 => 3 * DAY_MS,
+            KlineInterval::Week1 => return Some((WEEK_MS, FIRST_MONDAY_MS)),
+            KlineInterval::Month1 => return None,
+        };
+        Some((length, 0))
+    }
+
+    // Start of the bucket holding `timestamp`, in ms and UTC
+    pub fn bucket_start(&self, timestamp: i64) -> i64 {
+        match self.fixed_bucket() {
+            Some((length, offset)) => timestamp - (timestamp - offset).rem_euclid(length),
+            None => {
+                let date = utc(timestamp);
+                month_start(date.year(), date.month())
+            }
+        }
+    }
+
+    // Start of the bucket after the one starting at `start`
+    pub fn next_bucket_start(&self, start: i64) -> i64 {
+        match self.fixed_bucket() {
+            Some((length, _)) => start + length,
+            None => {
+                let date = utc(start);
+                match date.month() {
+                    12 => month_start(date.year() + 1, 1),
+                    month => month_start(date.year(), month + 1),
+                }
+            }
+        }
+    }
+}
+
+fn utc(timestamp: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(timestamp).unwrap_or_default()
+}
+
+fn month_start(year: i32, month: u32) -> i64 {
+    Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0)
+        .single()
+        .map_or(0, |date| date.timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_up_from_whole_base_buckets() {
+        // A base bucket never straddles two buckets of the intervals rolled up from it
+        for interval in KlineInterval::ALL {
+            let base = interval.base_interval();
+            assert!(KlineInterval::BASE.contains(&base));
+
+            let mut timestamp = 1_700_000_000_000;
+            for _ in 0..200 {
+                let base_start = base.bucket_start(timestamp);
+                let base_end = base.next_bucket_start(base_start) - 1;
+                assert_eq!(
+                    interval.bucket_start(base_start),
+                    interval.bucket_start(base_end),
+                    "{:?} at {}",
+                    interval,
+                    timestamp
+                );
+                timestamp += 7 * 60 * 60 * 1000 + 13;
+            }
+        }
+    }
+}
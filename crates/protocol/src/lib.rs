@@ -0,0 +1,144 @@
+pub mod db;
+pub mod errors;
+pub mod klines;
+pub mod orders;
+pub mod users;
+pub mod ws_stream;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+// Bump whenever a message changes shape - components reject messages from any other version
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub version: u32,
+    pub payload: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    VersionMismatch { expected: u32, received: Option<u32> },
+    Malformed(String),
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::VersionMismatch {
+                expected,
+                received: Some(received),
+            } => write!(
+                f,
+                "Protocol version mismatch - expected {}, received {}",
+                expected, received
+            ),
+            ProtocolError::VersionMismatch {
+                expected,
+                received: None,
+            } => write!(
+                f,
+                "Protocol version mismatch - expected {}, received unversioned message",
+                expected
+            ),
+            ProtocolError::Malformed(e) => write!(f, "Malformed message - {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+// Only the version is read first, so a newer payload shape is reported as a mismatch and not as garbage
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: Option<u32>,
+}
+
+pub fn encode<T: Serialize>(payload: &T) -> String {
+    let envelope = Envelope {
+        version: PROTOCOL_VERSION,
+        payload,
+    };
+
+    serde_json::to_string(&envelope).unwrap()
+}
+
+pub fn decode<T: DeserializeOwned>(data: &str) -> Result<T, ProtocolError> {
+    let probe: VersionProbe =
+        serde_json::from_str(data).map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+
+    if probe.version != Some(PROTOCOL_VERSION) {
+        return Err(ProtocolError::VersionMismatch {
+            expected: PROTOCOL_VERSION,
+            received: probe.version,
+        });
+    }
+
+    serde_json::from_str::<Envelope<T>>(data)
+        .map(|envelope| envelope.payload)
+        .map_err(|e| ProtocolError::Malformed(e.to_string()))
+}
+
+// Best effort lookup of the reply channel of a request we could not decode,
+// e.g. {"version":2,"payload":{"CreateOrder":{"pubsub_id":"..."}}}
+pub fn reply_channel(data: &str) -> Option<Uuid> {
+    let value: serde_json::Value = serde_json::from_str(data).ok()?;
+    let request = value.get("payload")?.as_object()?.values().next()?;
+    let pubsub_id = request.get("pubsub_id")?.as_str()?;
+
+    Uuid::parse_str(pubsub_id).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use orders::{GetDepth, OrderRequests};
+
+    #[test]
+    fn round_trips_current_version() {
+        let request = OrderRequests::GetDepth(GetDepth {
+            symbol: "SOL_USDC".to_string(),
+            limit: None,
+            step: None,
+            pubsub_id: None,
+        });
+
+        let decoded: OrderRequests = decode(&encode(&request)).unwrap();
+        match decoded {
+            OrderRequests::GetDepth(depth) => assert_eq!(depth.symbol, "SOL_USDC"),
+            _ => panic!("decoded wrong request"),
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let pubsub_id = Uuid::new_v4();
+        let data = format!(
+            r#"{{"version":2,"payload":{{"GetDepth":{{"symbol":"SOL_USDC","pubsub_id":"{}"}}}}}}"#,
+            pubsub_id
+        );
+
+        assert_eq!(
+            decode::<OrderRequests>(&data).unwrap_err(),
+            ProtocolError::VersionMismatch {
+                expected: PROTOCOL_VERSION,
+                received: Some(2),
+            }
+        );
+        assert_eq!(reply_channel(&data), Some(pubsub_id));
+    }
+
+    #[test]
+    fn rejects_unversioned_messages() {
+        let data = r#"{"GetDepth":{"symbol":"SOL_USDC"}}"#;
+
+        assert_eq!(
+            decode::<OrderRequests>(data).unwrap_err(),
+            ProtocolError::VersionMismatch {
+                expected: PROTOCOL_VERSION,
+                received: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use crate::errors::{EngineReply, ErrorResponse};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Most orders placed or cancelled by a single batch request
+pub const MAX_BATCH_ORDERS: usize = 50;
+
+// Shortest countdown for CountdownCancelAll, 0 switches it off
+pub const MIN_COUNTDOWN_MS: u64 = 1000;
+
+// A partial book depth stream the engine was not asked for again within this long is dropped
+pub const DEPTH_SUBSCRIPTION_TTL_MS: i64 = 30_000;
+
+// Price levels a depth snapshot or partial book depth stream can be limited to
+pub const DEPTH_LIMITS: [usize; 6] = [5, 10, 20, 100, 500, 1000];
+pub const DEFAULT_DEPTH_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OrderSide {
+    BUY,
+    SELL,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OrderType {
+    LIMIT,
+    MARKET,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    Pending,
+    Filled,
+    PartiallyFilled,
+    Cancelled,
+}
+
+// How much of the execution report the engine sends back for a new order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResponseType {
+    ACK,
+    RESULT,
+    #[default]
+    FULL,
+}
+
+// How a batch handles orders that fail validation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BatchMode {
+    // Every order is placed or rejected on its own
+    #[default]
+    Independent,
+    // The whole batch is checked first (markets, precision, combined funds) and nothing is placed if any order fails
+    AllOrNothing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Liquidity {
+    MAKER,
+    TAKER,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order {
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub filled_quantity: Decimal,
+    pub order_id: String,
+    pub user_id: String,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub order_status: OrderStatus,
+    pub timestamp: i64, // chrono::Utc::now().timestamp_millis();
+}
+
+// (price, quantity) aggregated over all orders at a price level
+pub type PriceLevel = (Decimal, Decimal);
+
+// ----------------------------------------
+// REQUESTS - router -> engine on the orders queue
+// ----------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateOrder {
+    pub market: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub side: OrderSide,
+    #[serde(default)] // filled in from the authenticated user by the router
+    pub user_id: String,
+    #[serde(default)]
+    pub response_type: ResponseType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetOpenOrder {
+    #[serde(default)] // filled in from the authenticated user by the router
+    pub user_id: String,
+    pub order_id: String,
+    pub market: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelOrder {
+    pub order_id: String,
+    #[serde(default)] // filled in from the authenticated user by the router
+    pub user_id: String,
+    pub price: Decimal,
+    pub side: OrderSide,
+    pub market: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetOpenOrders {
+    #[serde(default)] // filled in from the authenticated user by the router
+    pub user_id: String,
+    pub market: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelAllOrders {
+    #[serde(default)] // filled in from the authenticated user by the router
+    pub user_id: String,
+    pub market: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetDepth {
+    pub symbol: String,
+    // One of DEPTH_LIMITS, DEFAULT_DEPTH_LIMIT levels per side when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    // Merges levels into buckets of this size, bids round down and asks round up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetBookTicker {
+    pub symbol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetL3Depth {
+    pub symbol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+// Sent by ws-stream when the first connection subscribes to a partial book depth stream, again
+// while it has subscribers and once more when the last one leaves - the engine publishes those
+// streams only while they are registered, and forgets them after DEPTH_SUBSCRIPTION_TTL_MS
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DepthSubscription {
+    pub symbol: String,
+    pub limit: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<Decimal>,
+}
+
+// The user_id and pubsub_id of the batch apply to all of its orders
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchCreateOrders {
+    pub orders: Vec<CreateOrder>,
+    #[serde(default)]
+    pub mode: BatchMode,
+    #[serde(default)] // filled in from the authenticated user by the router
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchCancelOrders {
+    pub orders: Vec<CancelOrder>,
+    #[serde(default)] // filled in from the authenticated user by the router
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+// Dead man's switch - unless refreshed within countdown_time ms, all of the user's orders are cancelled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountdownCancelAll {
+    pub countdown_time: u64,
+    #[serde(default)] // filled in from the authenticated user by the router
+    pub user_id: String,
+    #[serde(default)] // set by the router or ws-stream, never taken from a client
+    pub source: CountdownSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+// Every source has its own countdown per user, refreshing or switching off one leaves the other running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CountdownSource {
+    #[default]
+    Api, // POST /countdownCancelAll
+    Disconnect, // ws-stream's cancel on disconnect
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OrderRequests {
+    CreateOrder(CreateOrder),
+    GetOpenOrder(GetOpenOrder),
+    CancelOrder(CancelOrder),
+    GetOpenOrders(GetOpenOrders),
+    GetDepth(GetDepth),
+    GetL3Depth(GetL3Depth),
+    GetBookTicker(GetBookTicker),
+    CancelAllOrders(CancelAllOrders),
+    BatchCreate(BatchCreateOrders),
+    BatchCancel(BatchCancelOrders),
+    CountdownCancelAll(CountdownCancelAll),
+    SubscribeDepth(DepthSubscription),
+    UnsubscribeDepth(DepthSubscription),
+}
+
+// ----------------------------------------
+// RESPONSES - engine -> router on the request's pubsub_id channel
+// ----------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateOrderResponse {
+    pub status: String,
+    pub order_id: String,
+    // RESULT and FULL only
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub execution: Option<OrderExecution>,
+    // FULL only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fills: Option<Vec<FillReport>>,
+}
+
+impl CreateOrderResponse {
+    // Drop the parts of the report the caller did not ask for
+    pub fn with_response_type(mut self, response_type: ResponseType) -> CreateOrderResponse {
+        match response_type {
+            ResponseType::ACK => {
+                self.execution = None;
+                self.fills = None;
+            }
+            ResponseType::RESULT => self.fills = None,
+            ResponseType::FULL => {}
+        }
+
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderExecution {
+    pub executed_quantity: Decimal,
+    // None when nothing traded
+    pub avg_price: Option<Decimal>,
+    pub remaining_quantity: Decimal,
+    pub order_status: OrderStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FillReport {
+    pub trade_id: i64,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub fee: Decimal,
+    pub fee_asset: String,
+    pub liquidity: Liquidity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelOrderResponse {
+    pub status: String,
+    pub order_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelAllOrdersResponse {
+    pub status: String,
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountdownCancelAllResponse {
+    pub user_id: String,
+    pub countdown_time: u64,
+    // When the orders will be cancelled in ms, None once the countdown is switched off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trigger_time: Option<i64>,
+}
+
+// One per order of a batch, in request order - the response itself or {"code", "msg"}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BatchResult<T> {
+    Ok(T),
+    Err(ErrorResponse),
+}
+
+impl<T> From<EngineReply<T>> for BatchResult<T> {
+    fn from(reply: EngineReply<T>) -> BatchResult<T> {
+        match reply {
+            Ok(response) => BatchResult::Ok(response),
+            Err(error) => BatchResult::Err(error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthResponse {
+    // Diffs with a last update id (u) up to this one are already part of the snapshot
+    pub last_update_id: i64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+// Best bid and ask, None while that side of the book is empty
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookTickerResponse {
+    pub symbol: String,
+    pub last_update_id: i64,
+    pub bid_price: Option<Decimal>,
+    pub bid_quantity: Option<Decimal>,
+    pub ask_price: Option<Decimal>,
+    pub ask_quantity: Option<Decimal>,
+}
+
+// Every resting order of the book, in matching order per level
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct L3DepthResponse {
+    pub last_update_id: i64,
+    pub bids: Vec<L3Level>,
+    pub asks: Vec<L3Level>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct L3Level {
+    pub price: Decimal,
+    pub orders: Vec<L3Order>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct L3Order {
+    // Stands in for the real order id, which must stay private to its owner
+    pub order_id: String,
+    // What is left to fill
+    pub quantity: Decimal,
+    pub timestamp: i64,
+    // 1 is next in line to be filled at this price
+    pub queue_position: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{ErrorCode, ErrorResponse};
+
+    #[test]
+    fn batch_results_are_responses_or_errors() {
+        let results: Vec<BatchResult<CancelOrderResponse>> = vec![
+            Ok(CancelOrderResponse {
+                status: "Cancelled Order".to_string(),
+                order_id: "1".to_string(),
+            })
+            .into(),
+            Err(ErrorResponse {
+                code: ErrorCode::UnknownOrder,
+                msg: "Unknown order 2".to_string(),
+            })
+            .into(),
+        ];
+
+        let data = serde_json::to_string(&results).unwrap();
+        assert!(data.starts_with(r#"[{"status":"Cancelled Order""#));
+
+        let decoded: Vec<BatchResult<CancelOrderResponse>> = serde_json::from_str(&data).unwrap();
+        assert!(matches!(&decoded[0], BatchResult::Ok(r) if r.order_id == "1"));
+        assert!(matches!(
+            &decoded[1],
+            BatchResult::Err(e) if e.code == ErrorCode::UnknownOrder
+        ));
+    }
+}
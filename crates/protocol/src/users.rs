@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// ----------------------------------------
+// REQUESTS - router -> engine on the users queue
+// ----------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateUserInput {
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubsub_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UserRequests {
+    CreateUser(CreateUserInput),
+}
+
+// ----------------------------------------
+// RESPONSES - engine -> router on the request's pubsub_id channel
+// ----------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateUserResponse {
+    pub status: String,
+    pub user_id: String,
+}
@@ -0,0 +1,280 @@
+use crate::klines::KlineInterval;
+use crate::orders::{OrderSide, OrderStatus, PriceLevel};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+// ----------------------------------------
+// STREAMS - engine -> ws-stream on the `<type>.<market>` pubsub channels
+// ----------------------------------------
+
+// {"data":{"e":"trade","t":1,"m":true,"p":"100","q":"1","s":"SOL_USDC","T":1727866324088},"stream":"trade.SOL_USDC"}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WsResponse<T = serde_json::Value> {
+    pub stream: String,
+    pub data: T, // any kind of JSON-like data
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TradeUpdate {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "t")]
+    pub trade_id: i64,
+    #[serde(rename = "m")]
+    pub is_buyer_maker: bool,
+    #[serde(rename = "p")]
+    pub price: Decimal,
+    #[serde(rename = "q")]
+    pub quantity: Decimal,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "T")]
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DepthUpdate {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    // Book changes covered by this diff - the next diff starts at last_update_id + 1
+    #[serde(rename = "U")]
+    pub first_update_id: i64,
+    #[serde(rename = "u")]
+    pub last_update_id: i64,
+    #[serde(rename = "b")]
+    pub bids: Vec<PriceLevel>,
+    #[serde(rename = "a")]
+    pub asks: Vec<PriceLevel>,
+}
+
+// Partial book depth - `depth.<market>.<limit>` or `depth.<market>.<limit>.<step>`
+pub fn partial_depth_stream(symbol: &str, limit: usize, step: Option<Decimal>) -> String {
+    match step {
+        Some(step) => format!("depth.{}.{}.{}", symbol, limit, step.normalize()),
+        None => format!("depth.{}.{}", symbol, limit),
+    }
+}
+
+// The top levels of the book after every change, bids best-first
+// {"data":{"e":"partialDepth","s":"SOL_USDC","u":42,"b":[["99.5","3"]],"a":[["100","1"]]},"stream":"depth.SOL_USDC.5"}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PartialDepthUpdate {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "u")]
+    pub last_update_id: i64,
+    #[serde(rename = "b")]
+    pub bids: Vec<PriceLevel>,
+    #[serde(rename = "a")]
+    pub asks: Vec<PriceLevel>,
+}
+
+pub fn kline_stream(interval: KlineInterval, symbol: &str) -> String {
+    format!("kline_{}.{}", interval.code(), symbol)
+}
+
+// The open candle after every order that traded, and once more with `x` set when it closes
+// {"data":{"e":"kline","s":"SOL_USDC","k":{"t":1727866320000,"T":1727866379999,"i":"1m","o":"100","h":"101","l":"100","c":"101","v":"3","q":"302","n":2,"x":false}},"stream":"kline_1m.SOL_USDC"}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KlineUpdate {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "k")]
+    pub kline: Kline,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Kline {
+    #[serde(rename = "t")]
+    pub open_time: i64,
+    // Last ms of the bucket
+    #[serde(rename = "T")]
+    pub close_time: i64,
+    #[serde(rename = "i")]
+    pub interval: KlineInterval,
+    #[serde(rename = "o")]
+    pub open: Decimal,
+    #[serde(rename = "h")]
+    pub high: Decimal,
+    #[serde(rename = "l")]
+    pub low: Decimal,
+    #[serde(rename = "c")]
+    pub close: Decimal,
+    #[serde(rename = "v")]
+    pub volume: Decimal,
+    #[serde(rename = "q")]
+    pub quote_volume: Decimal,
+    #[serde(rename = "n")]
+    pub trades: u64,
+    #[serde(rename = "x")]
+    pub closed: bool,
+}
+
+// Every market's ticker, as one list per publish
+pub const ALL_TICKERS_STREAM: &str = "!ticker";
+
+// Rolling 24h statistics, published every second on `ticker.<market>` and, for all markets, on `!ticker`
+// {"data":{"e":"24hrTicker","s":"SOL_USDC","o":"100","h":"104","l":"99","c":"103","p":"3","P":"3.00","v":"12","q":"1230","n":40,"E":1727866324088},"stream":"ticker.SOL_USDC"}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TickerUpdate {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "o")]
+    pub open: Decimal,
+    #[serde(rename = "h")]
+    pub high: Decimal,
+    #[serde(rename = "l")]
+    pub low: Decimal,
+    #[serde(rename = "c")]
+    pub last_price: Decimal,
+    #[serde(rename = "p")]
+    pub price_change: Decimal,
+    #[serde(rename = "P")]
+    pub price_change_percent: Decimal,
+    #[serde(rename = "v")]
+    pub volume: Decimal,
+    #[serde(rename = "q")]
+    pub quote_volume: Decimal,
+    #[serde(rename = "n")]
+    pub trades: u64,
+    #[serde(rename = "E")]
+    pub timestamp: i64,
+}
+
+// Sent whenever the best bid or ask price or quantity changes, null for an empty side
+// {"data":{"e":"bookTicker","s":"SOL_USDC","u":42,"b":"99.5","B":"3","a":"100","A":"1"},"stream":"bookTicker.SOL_USDC"}
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BookTickerUpdate {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "u")]
+    pub last_update_id: i64,
+    #[serde(rename = "b")]
+    pub bid_price: Option<Decimal>,
+    #[serde(rename = "B")]
+    pub bid_quantity: Option<Decimal>,
+    #[serde(rename = "a")]
+    pub ask_price: Option<Decimal>,
+    #[serde(rename = "A")]
+    pub ask_quantity: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum L3Action {
+    #[serde(rename = "add")]
+    Add,
+    #[serde(rename = "modify")]
+    Modify,
+    #[serde(rename = "delete")]
+    Delete,
+}
+
+// One resting order changing - adds join the back of their level, modifies carry the quantity
+// left after a fill and deletes are fills or cancels that took the order off the book
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct L3Event {
+    #[serde(rename = "x")]
+    pub action: L3Action,
+    #[serde(rename = "i")]
+    pub order_id: String,
+    #[serde(rename = "S")]
+    pub side: OrderSide,
+    #[serde(rename = "p")]
+    pub price: Decimal,
+    #[serde(rename = "q")]
+    pub quantity: Decimal,
+    // When the order was placed, adds only
+    #[serde(rename = "T", skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+}
+
+// {"data":{"e":"l3","s":"SOL_USDC","U":7,"u":7,"o":[{"x":"delete","i":"9f86d081884c7d65","S":"SELL","p":"100","q":"0"}]},"stream":"l3.SOL_USDC"}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct L3Update {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "U")]
+    pub first_update_id: i64,
+    #[serde(rename = "u")]
+    pub last_update_id: i64,
+    #[serde(rename = "o")]
+    pub orders: Vec<L3Event>,
+}
+
+// ----------------------------------------
+// PRIVATE STREAMS - engine -> ws-stream on the `user.<user_id>` pubsub channels
+// Only delivered to connections that subscribed with a valid listen key for that user
+// ----------------------------------------
+
+// Listen keys are handed out by the router and live in redis until they expire or are closed
+pub const LISTEN_KEY_TTL_SECONDS: i64 = 60 * 60;
+
+pub fn listen_key(key: &str) -> String {
+    format!("listenKey.{}", key)
+}
+
+pub fn user_stream(user_id: &str) -> String {
+    format!("user.{}", user_id)
+}
+
+// {"data":{"e":"orderUpdate","s":"SOL_USDC","i":"...","S":"BUY","p":"100","q":"5","z":"2","X":"PartiallyFilled","l":"2","L":"100","t":12,"T":1727866324088},"stream":"user.<user_id>"}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OrderUpdate {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "i")]
+    pub order_id: String,
+    #[serde(rename = "S")]
+    pub side: OrderSide,
+    #[serde(rename = "p")]
+    pub price: Decimal,
+    #[serde(rename = "q")]
+    pub quantity: Decimal,
+    #[serde(rename = "z")]
+    pub filled_quantity: Decimal,
+    #[serde(rename = "X")]
+    pub status: OrderStatus,
+    // Last fill - zero for updates that are not caused by a trade
+    #[serde(rename = "l")]
+    pub last_filled_quantity: Decimal,
+    #[serde(rename = "L")]
+    pub last_filled_price: Decimal,
+    #[serde(rename = "t", skip_serializing_if = "Option::is_none")]
+    pub trade_id: Option<i64>,
+    #[serde(rename = "T")]
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AssetBalance {
+    #[serde(rename = "a")]
+    pub asset: String,
+    #[serde(rename = "f")]
+    pub available: Decimal,
+    #[serde(rename = "l")]
+    pub locked: Decimal,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BalanceUpdate {
+    #[serde(rename = "e")]
+    pub event: String,
+    #[serde(rename = "B")]
+    pub balances: Vec<AssetBalance>,
+    #[serde(rename = "T")]
+    pub timestamp: i64,
+}
@@ -0,0 +1,316 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use fred::interfaces::PubsubInterface;
+use tokio::sync::{mpsc, Notify};
+use uuid::Uuid;
+
+use crate::metrics::now_millis;
+use crate::streams::READ_BLOCK;
+use crate::{BusError, RedisManager, RedisQueues, StreamMessage, DEFAULT_REPLY_TIMEOUT};
+
+pub type Subscription = mpsc::UnboundedReceiver<String>;
+
+// Transport between the router, engine and db-processor - RedisManager in production,
+// InMemoryBus to run them in one process for tests and local demos
+#[async_trait]
+pub trait MessageBus: Send + Sync {
+    async fn push(&self, queue: RedisQueues, value: String) -> Result<(), BusError>;
+
+    // Up to `count` messages, waiting a short while when the queue is empty
+    async fn pop(&self, queue: RedisQueues, count: u64) -> Result<Vec<StreamMessage>, BusError>;
+
+    // Failures are logged - an unacknowledged message is delivered again
+    async fn ack(&self, queue: RedisQueues, message: &StreamMessage);
+
+    // Leaves the message unacknowledged
+    async fn fail(&self, queue: RedisQueues, message: &StreamMessage, reason: &str);
+
+    async fn publish(&self, channel: &str, value: String) -> Result<(), BusError>;
+
+    // One subscription per channel - subscribing again replaces the earlier one
+    async fn subscribe(&self, channel: &str) -> Result<Subscription, BusError>;
+
+    async fn unsubscribe(&self, channel: &str) -> Result<(), BusError>;
+
+    async fn push_and_wait_for_subscriber(
+        &self,
+        queue: RedisQueues,
+        value: String,
+        channel: Uuid,
+    ) -> Result<String, BusError> {
+        self.push_and_wait_for_subscriber_with_timeout(queue, value, channel, DEFAULT_REPLY_TIMEOUT)
+            .await
+    }
+
+    // Pushes a request and waits for the reply published on its own channel
+    async fn push_and_wait_for_subscriber_with_timeout(
+        &self,
+        queue: RedisQueues,
+        value: String,
+        channel: Uuid,
+        timeout: Duration,
+    ) -> Result<String, BusError> {
+        let channel = channel.to_string();
+
+        // Subscribe first, the reply can arrive before push returns
+        let mut subscription = self.subscribe(&channel).await.map_err(|e| {
+            println!("Failed to subscribe to channel - {}", e);
+            e
+        })?;
+
+        // Then push message
+        let result = match self.push(queue, value).await {
+            Ok(()) => match tokio::time::timeout(timeout, subscription.recv()).await {
+                Ok(Some(published_message)) => Ok(published_message),
+                Ok(None) => Err(BusError::Closed),
+                Err(_) => Err(BusError::Timeout(timeout)),
+            },
+            Err(e) => {
+                println!("Couldn't push into queue - {}", e);
+                Err(e)
+            }
+        };
+
+        let _ = self.unsubscribe(&channel).await;
+
+        if let Ok(published_message) = &result {
+            println!("Recv {:?} on channel {}", published_message, channel);
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl MessageBus for RedisManager {
+    async fn push(&self, queue: RedisQueues, value: String) -> Result<(), BusError> {
+        Ok(self.append(&queue.to_string(), value).await?)
+    }
+
+    async fn pop(&self, queue: RedisQueues, count: u64) -> Result<Vec<StreamMessage>, BusError> {
+        let mut consumer = self.consumers[&queue].lock().await;
+
+        let consumer = match consumer.as_mut() {
+            Some(consumer) => consumer,
+            None => consumer.insert(crate::QueueConsumer::new(self, queue).await?),
+        };
+
+        Ok(consumer.next_batch(self, count).await?)
+    }
+
+    async fn ack(&self, queue: RedisQueues, message: &StreamMessage) {
+        match self.consumers[&queue].lock().await.as_mut() {
+            Some(consumer) => consumer.ack(self, message).await,
+            None => println!(
+                "Can't acknowledge {} message {} before popping",
+                queue, message.id
+            ),
+        }
+    }
+
+    async fn fail(&self, queue: RedisQueues, message: &StreamMessage, reason: &str) {
+        if let Some(consumer) = self.consumers[&queue].lock().await.as_mut() {
+            consumer.fail(message, reason);
+        }
+    }
+
+    async fn publish(&self, channel: &str, value: String) -> Result<(), BusError> {
+        Ok(self.publisher.publish(channel, value).await?)
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<Subscription, BusError> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .insert(channel.to_string(), sender);
+
+        if let Err(e) = self.subscriber.subscribe(channel).await {
+            self.subscriptions.lock().unwrap().remove(channel);
+            return Err(e.into());
+        }
+
+        Ok(receiver)
+    }
+
+    async fn unsubscribe(&self, channel: &str) -> Result<(), BusError> {
+        self.subscriptions.lock().unwrap().remove(channel);
+        Ok(self.subscriber.unsubscribe(channel).await?)
+    }
+}
+
+// Queues and pub/sub on tokio channels - popped messages are gone, there is no redelivery
+#[derive(Default)]
+pub struct InMemoryBus {
+    queues: Mutex<HashMap<RedisQueues, VecDeque<StreamMessage>>>,
+    pushed: Notify,
+    subscriptions: Mutex<HashMap<String, mpsc::UnboundedSender<String>>>,
+    sequence: AtomicU64,
+    failed: Mutex<HashMap<RedisQueues, Vec<(StreamMessage, String)>>>,
+}
+
+impl InMemoryBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn take(&self, queue: RedisQueues, count: u64) -> Vec<StreamMessage> {
+        let mut queues = self.queues.lock().unwrap();
+        let messages = queues.entry(queue).or_default();
+        let count = messages.len().min(count as usize);
+
+        messages.drain(..count).collect()
+    }
+
+    // Messages a consumer gave up on, with the reason it gave
+    pub fn failed(&self, queue: RedisQueues) -> Vec<(StreamMessage, String)> {
+        self.failed
+            .lock()
+            .unwrap()
+            .get(&queue)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl MessageBus for InMemoryBus {
+    async fn push(&self, queue: RedisQueues, value: String) -> Result<(), BusError> {
+        // Same "<milliseconds>-<sequence>" ids as redis streams
+        let id = format!(
+            "{}-{}",
+            now_millis(),
+            self.sequence.fetch_add(1, Ordering::Relaxed)
+        );
+
+        self.queues
+            .lock()
+            .unwrap()
+            .entry(queue)
+            .or_default()
+            .push_back(StreamMessage { id, data: value });
+        self.pushed.notify_waiters();
+
+        Ok(())
+    }
+
+    async fn pop(&self, queue: RedisQueues, count: u64) -> Result<Vec<StreamMessage>, BusError> {
+        // Registered before checking the queue, so a push in between still wakes us up
+        let pushed = self.pushed.notified();
+        tokio::pin!(pushed);
+        pushed.as_mut().enable();
+
+        let messages = self.take(queue, count);
+        if !messages.is_empty() {
+            return Ok(messages);
+        }
+
+        let _ = tokio::time::timeout(READ_BLOCK, pushed).await;
+        Ok(self.take(queue, count))
+    }
+
+    async fn ack(&self, _queue: RedisQueues, _message: &StreamMessage) {}
+
+    async fn fail(&self, queue: RedisQueues, message: &StreamMessage, reason: &str) {
+        println!("Dropping {} message {} - {}", queue, message.id, reason);
+        self.failed
+            .lock()
+            .unwrap()
+            .entry(queue)
+            .or_default()
+            .push((message.clone(), reason.to_string()));
+    }
+
+    async fn publish(&self, channel: &str, value: String) -> Result<(), BusError> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+
+        if let Some(sender) = subscriptions.get(channel) {
+            if sender.send(value).is_err() {
+                subscriptions.remove(channel);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<Subscription, BusError> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .insert(channel.to_string(), sender);
+
+        Ok(receiver)
+    }
+
+    async fn unsubscribe(&self, channel: &str) -> Result<(), BusError> {
+        self.subscriptions.lock().unwrap().remove(channel);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn pops_in_push_order() {
+        let bus = InMemoryBus::new();
+        for value in ["a", "b", "c"] {
+            bus.push(RedisQueues::ORDERS, value.to_string())
+                .await
+                .unwrap();
+        }
+
+        let messages = bus.pop(RedisQueues::ORDERS, 2).await.unwrap();
+        let data: Vec<&str> = messages.iter().map(|m| m.data.as_str()).collect();
+        assert_eq!(data, ["a", "b"]);
+        assert!(messages[0].queued_at_millis().is_some());
+
+        assert_eq!(bus.pop(RedisQueues::ORDERS, 2).await.unwrap().len(), 1);
+        assert!(bus.pop(RedisQueues::USERS, 2).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn waits_for_reply_on_own_channel() {
+        let bus = Arc::new(InMemoryBus::new());
+
+        // Echoes every request back on the channel named in it
+        let responder = Arc::clone(&bus);
+        tokio::spawn(async move {
+            loop {
+                for message in responder.pop(RedisQueues::ORDERS, 10).await.unwrap() {
+                    let reply = format!("reply to {}", message.data);
+                    responder.publish(&message.data, reply).await.unwrap();
+                }
+            }
+        });
+
+        let channel = Uuid::new_v4();
+        let reply = bus
+            .push_and_wait_for_subscriber(RedisQueues::ORDERS, channel.to_string(), channel)
+            .await
+            .unwrap();
+        assert_eq!(reply, format!("reply to {}", channel));
+    }
+
+    #[tokio::test]
+    async fn times_out_without_reply() {
+        let bus = InMemoryBus::new();
+        let timeout = Duration::from_millis(10);
+
+        let result = bus
+            .push_and_wait_for_subscriber_with_timeout(
+                RedisQueues::USERS,
+                "request".to_string(),
+                Uuid::new_v4(),
+                timeout,
+            )
+            .await;
+        assert!(matches!(result, Err(BusError::Timeout(t)) if t == timeout));
+    }
+}
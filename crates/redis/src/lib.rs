@@ -1,29 +1,73 @@
-use std::fmt::Error;
-use uuid::Uuid;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast::error::RecvError, mpsc};
 
-use fred::types::RedisConfig;
+use fred::types::{Expiration, RedisConfig};
 use fred::{clients::SubscriberClient, prelude::*};
 
+pub mod bus;
+pub mod metrics;
+pub mod streams;
+pub use bus::{InMemoryBus, MessageBus, Subscription};
+pub use streams::{QueueConsumer, StreamMessage};
+
+// How long push_and_wait_for_subscriber waits for the engine's reply
+pub const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Each queue is a redis stream read through a consumer group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RedisQueues {
     ORDERS,
     USERS,
     DATABASE,
 }
 
-impl ToString for RedisQueues {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for RedisQueues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            RedisQueues::ORDERS => "orders".to_string(),
-            RedisQueues::USERS => "users".to_string(),
-            RedisQueues::DATABASE => "database".to_string(),
+            RedisQueues::ORDERS => write!(f, "orders"),
+            RedisQueues::USERS => write!(f, "users"),
+            RedisQueues::DATABASE => write!(f, "database"),
         }
     }
 }
 
+#[derive(Debug)]
+pub enum BusError {
+    Redis(RedisError),
+    Timeout(Duration),
+    Closed, // the subscription ended, or the message could not be read
+}
+
+impl std::fmt::Display for BusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BusError::Redis(e) => write!(f, "{}", e),
+            BusError::Timeout(timeout) => write!(f, "No reply within {:?}", timeout),
+            BusError::Closed => write!(f, "Subscription closed"),
+        }
+    }
+}
+
+impl std::error::Error for BusError {}
+
+impl From<RedisError> for BusError {
+    fn from(e: RedisError) -> Self {
+        BusError::Redis(e)
+    }
+}
+
+// Subscriptions made through MessageBus::subscribe, by channel
+type Subscriptions = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<String>>>>;
+
 pub struct RedisManager {
     pub client: RedisClient,
     pub publisher: RedisClient,
     pub subscriber: SubscriberClient,
+    subscriptions: Subscriptions,
+    // One consumer per queue, each with its own lock so a blocking read of one queue doesn't hold up the others
+    consumers: HashMap<RedisQueues, tokio::sync::Mutex<Option<QueueConsumer>>>,
 }
 
 impl RedisManager {
@@ -44,69 +88,97 @@ impl RedisManager {
         publisher.init().await?;
         subscriber.init().await?;
 
+        let subscriptions = Subscriptions::default();
+        Self::dispatch_messages(&subscriber, subscriptions.clone());
+
+        let consumers = [
+            RedisQueues::ORDERS,
+            RedisQueues::USERS,
+            RedisQueues::DATABASE,
+        ]
+        .into_iter()
+        .map(|queue| (queue, tokio::sync::Mutex::new(None)))
+        .collect();
+
         Ok(Self {
             client,
             publisher,
             subscriber,
+            subscriptions,
+            consumers,
         })
     }
 
-    pub async fn push(&self, key: &str, value: String) -> Result<(), RedisError> {
-        self.client.lpush(key, value).await
+    // Hands every pub/sub message to the subscription of its channel, other messages are left to other receivers of message_rx
+    fn dispatch_messages(subscriber: &SubscriberClient, subscriptions: Subscriptions) {
+        let mut message_stream = subscriber.message_rx();
+
+        tokio::spawn(async move {
+            loop {
+                let message = match message_stream.recv().await {
+                    Ok(message) => message,
+                    Err(RecvError::Lagged(skipped)) => {
+                        println!("Message dispatcher skipped {} messages", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let mut subscriptions = subscriptions.lock().unwrap();
+                if let Some(sender) = subscriptions.get(&*message.channel) {
+                    match message.value.convert::<String>() {
+                        Ok(published_message) => {
+                            // The receiver is gone once the request timed out
+                            if sender.send(published_message).is_err() {
+                                subscriptions.remove(&*message.channel);
+                            }
+                        }
+                        Err(e) => {
+                            println!("Invalid message on channel {} - {}", message.channel, e)
+                        }
+                    }
+                }
+            }
+        });
     }
 
-    pub async fn pop(
+    pub async fn set_with_expiry(
         &self,
         key: &str,
-        count: Option<usize>,
-    ) -> Result<Vec<RedisValue>, RedisError> {
-        self.client.rpop(key, count).await
+        value: String,
+        seconds: i64,
+    ) -> Result<(), RedisError> {
+        self.client
+            .set(key, value, Some(Expiration::EX(seconds)), None, false)
+            .await
     }
 
-    pub async fn publish(&self, channel: &str, value: String) -> Result<(), RedisError> {
-        self.publisher.publish(channel, value).await
+    pub async fn get(&self, key: &str) -> Result<Option<String>, RedisError> {
+        self.client.get(key).await
     }
 
-    pub async fn subscribe(&self, channel: &str) -> Result<(), RedisError> {
-        self.subscriber.subscribe(channel).await
+    // Returns false if the key does not exist (anymore)
+    pub async fn expire(&self, key: &str, seconds: i64) -> Result<bool, RedisError> {
+        self.client.expire(key, seconds).await
     }
 
-    pub async fn unsubscribe(&self, channel: &str) -> Result<(), RedisError> {
-        self.subscriber.unsubscribe(channel).await
+    pub async fn del(&self, key: &str) -> Result<(), RedisError> {
+        self.client.del(key).await
     }
 
-    pub async fn push_and_wait_for_subscriber(
+    // Counter shared by every process using the key - returns the value after incrementing
+    pub async fn incr_with_expiry(
         &self,
-        key: String,
-        value: String,
-        channel: Uuid,
-    ) -> Result<String, Error> {
-        let channel = channel.to_string();
-        let channel_ref = channel.as_str();
-
-        // Subscribe first
-        self.subscribe(channel_ref).await.map_err(|e| {
-            println!("Failed to subscribe to channel - {}", e);
-            Error
-        })?;
-
-        // Then push message
-        self.push(key.as_str(), value).await.map_err(|e| {
-            println!("Couldn't push into queue - {}", e);
-            Error
-        })?;
-
-        let mut message_stream = self.subscriber.message_rx();
-
-        if let Ok(message) = message_stream.recv().await {
-            println!("Recv {:?} on channel {}", message.value, message.channel);
-            let published_message = message.value.convert::<String>().unwrap();
-
-            let _ = self.unsubscribe(channel_ref).await;
-            return Ok(published_message);
-        }
-
-        Err(Error)
+        key: &str,
+        amount: i64,
+        seconds: i64,
+    ) -> Result<i64, RedisError> {
+        let pipeline = self.client.pipeline();
+        pipeline.incr_by::<(), _>(key, amount).await?;
+        pipeline.expire::<(), _>(key, seconds).await?;
+
+        let (count, _): (i64, bool) = pipeline.all().await?;
+        Ok(count)
     }
 }
 
@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{RedisQueues, StreamMessage};
+
+pub const METRICS_INTERVAL: Duration = Duration::from_secs(60);
+
+// CPU time (user + system) used by the whole process so far
+pub fn process_cpu_time() -> Duration {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+
+    // SAFETY: getrusage only writes into the struct we pass it, and we only read it on success
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) != 0 {
+            return Duration::ZERO;
+        }
+        usage.assume_init()
+    };
+
+    let to_duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    to_duration(usage.ru_utime) + to_duration(usage.ru_stime)
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl StreamMessage {
+    // Stream ids are "<milliseconds>-<sequence>", set by redis when the message was pushed
+    pub fn queued_at_millis(&self) -> Option<u64> {
+        self.id.split('-').next()?.parse().ok()
+    }
+}
+
+// Queue latency, processing time and CPU use of one consumer, logged every METRICS_INTERVAL
+pub struct QueueMetrics {
+    queue: RedisQueues,
+    window_start: Instant,
+    cpu_at_window_start: Duration,
+    reads: u64,
+    empty_reads: u64,
+    received: u64,
+    processed: u64,
+    failed: u64,
+    total_latency: Duration,
+    max_latency: Duration,
+    total_processing: Duration,
+}
+
+impl QueueMetrics {
+    pub fn new(queue: RedisQueues) -> Self {
+        Self {
+            queue,
+            window_start: Instant::now(),
+            cpu_at_window_start: process_cpu_time(),
+            reads: 0,
+            empty_reads: 0,
+            received: 0,
+            processed: 0,
+            failed: 0,
+            total_latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+            total_processing: Duration::ZERO,
+        }
+    }
+
+    pub fn record_read(&mut self, messages: &[StreamMessage]) {
+        self.reads += 1;
+        if messages.is_empty() {
+            self.empty_reads += 1;
+        }
+        self.received += messages.len() as u64;
+
+        // Time spent waiting in the stream until this consumer got the message
+        let now = now_millis();
+        for message in messages {
+            if let Some(queued_at) = message.queued_at_millis() {
+                let latency = Duration::from_millis(now.saturating_sub(queued_at));
+                self.total_latency += latency;
+                self.max_latency = self.max_latency.max(latency);
+            }
+        }
+    }
+
+    pub fn record_processed(&mut self, processing: Duration, success: bool) {
+        self.total_processing += processing;
+        if success {
+            self.processed += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    pub fn report_if_due(&mut self) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < METRICS_INTERVAL {
+            return;
+        }
+
+        let cpu_time = process_cpu_time();
+        let received = self.received.max(1) as u32;
+        let handled = (self.processed + self.failed).max(1) as u32;
+        println!(
+            "[metrics] {}: {} processed, {} failed, {} reads ({} empty), queue latency avg {:?} max {:?}, processing avg {:?}, process cpu {:.1}%",
+            self.queue,
+            self.processed,
+            self.failed,
+            self.reads,
+            self.empty_reads,
+            self.total_latency / received,
+            self.max_latency,
+            self.total_processing / handled,
+            cpu_time.saturating_sub(self.cpu_at_window_start).as_secs_f64() / elapsed.as_secs_f64() * 100.0
+        );
+
+        *self = Self::new(self.queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_queue_time_from_stream_id() {
+        let message = StreamMessage {
+            id: "1727866324123-4".to_string(),
+            data: String::new(),
+        };
+        assert_eq!(message.queued_at_millis(), Some(1727866324123));
+
+        let message = StreamMessage {
+            id: "not an id".to_string(),
+            data: String::new(),
+        };
+        assert_eq!(message.queued_at_millis(), None);
+    }
+
+    #[test]
+    fn measures_process_cpu_time() {
+        // The test harness has used some CPU time by the time this runs
+        assert!(process_cpu_time() > Duration::ZERO);
+    }
+}
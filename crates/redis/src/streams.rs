@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use fred::prelude::*;
+use fred::types::XReadResponse;
+
+use crate::metrics::QueueMetrics;
+use crate::{RedisManager, RedisQueues};
+
+const DATA_FIELD: &str = "data";
+
+// Acknowledged entries are only removed by trimming, so streams are capped (approximately) at this length
+const STREAM_MAX_LENGTH: i64 = 100_000;
+
+// Messages of other consumers are only taken over once they have been pending this long
+pub const PENDING_IDLE: Duration = Duration::from_secs(60);
+
+// A message delivered this often without being acknowledged goes to the dead-letter stream
+pub const MAX_DELIVERIES: u64 = 3;
+
+const RECOVERY_BATCH: u64 = 100;
+
+// How long a read waits for new messages - short enough for pending messages and metrics to be checked regularly
+pub const READ_BLOCK: Duration = Duration::from_secs(1);
+
+impl RedisQueues {
+    pub fn group(&self) -> &'static str {
+        match self {
+            RedisQueues::ORDERS | RedisQueues::USERS => "engine",
+            RedisQueues::DATABASE => "db-processor",
+        }
+    }
+
+    pub fn dead_letter(&self) -> String {
+        format!("{}.dead", self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamMessage {
+    pub id: String,
+    pub data: String,
+}
+
+fn into_messages(entries: Vec<(String, HashMap<String, String>)>) -> Vec<StreamMessage> {
+    entries
+        .into_iter()
+        .map(|(id, mut fields)| StreamMessage {
+            id,
+            data: fields.remove(DATA_FIELD).unwrap_or_default(),
+        })
+        .collect()
+}
+
+// Messages never delivered to any consumer of the group, waiting up to `block` for the first one
+async fn read_group(
+    client: &RedisClient,
+    queue: RedisQueues,
+    consumer: &str,
+    count: u64,
+    block: Duration,
+) -> Result<Vec<StreamMessage>, RedisError> {
+    let response: XReadResponse<String, String, String, String> = client
+        .xreadgroup_map(
+            queue.group(),
+            consumer,
+            Some(count),
+            Some(block.as_millis() as u64),
+            false,
+            queue.to_string(),
+            ">",
+        )
+        .await?;
+
+    Ok(response.into_values().flat_map(into_messages).collect())
+}
+
+impl RedisManager {
+    pub(crate) async fn append(&self, key: &str, value: String) -> Result<(), RedisError> {
+        let _id: String = self
+            .client
+            .xadd(
+                key,
+                false,
+                ("MAXLEN", "~", STREAM_MAX_LENGTH),
+                "*",
+                vec![(DATA_FIELD, value)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    // Starts at the beginning of the stream so messages pushed before the first consumer started are not skipped
+    pub async fn create_group(&self, queue: RedisQueues) -> Result<(), RedisError> {
+        let result: Result<(), RedisError> = self
+            .client
+            .xgroup_create(queue.to_string(), queue.group(), "0", true)
+            .await;
+
+        match result {
+            Err(e) if e.details().starts_with("BUSYGROUP") => Ok(()), // already exists
+            result => result,
+        }
+    }
+
+    pub(crate) async fn ack_id(&self, queue: RedisQueues, id: &str) -> Result<(), RedisError> {
+        self.client
+            .xack::<(), _, _, _>(queue.to_string(), queue.group(), id)
+            .await
+    }
+
+    async fn claim(
+        &self,
+        queue: RedisQueues,
+        consumer: &str,
+        min_idle: Duration,
+        ids: Vec<String>,
+    ) -> Result<Vec<StreamMessage>, RedisError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let entries: Vec<(String, HashMap<String, String>)> = self
+            .client
+            .xclaim_values(
+                queue.to_string(),
+                queue.group(),
+                consumer,
+                min_idle.as_millis() as u64,
+                ids,
+                None,
+                None,
+                None,
+                false,
+                false,
+            )
+            .await?;
+
+        Ok(into_messages(entries))
+    }
+
+    // Takes over this consumer's own unacknowledged messages (left by a crash or a failed attempt)
+    // and those of other consumers that have been idle for PENDING_IDLE
+    pub async fn claim_pending(
+        &self,
+        queue: RedisQueues,
+        consumer: &str,
+        count: u64,
+    ) -> Result<Vec<StreamMessage>, RedisError> {
+        // (id, consumer, idle ms, deliveries)
+        let pending: Vec<(String, String, u64, u64)> = self
+            .client
+            .xpending(queue.to_string(), queue.group(), ("-", "+", count))
+            .await?;
+
+        let mut deliveries = HashMap::new();
+        let mut own_ids = vec![];
+        let mut idle_ids = vec![];
+        for (id, owner, idle, delivery_count) in pending {
+            if owner == consumer {
+                own_ids.push(id.clone());
+            } else if idle >= PENDING_IDLE.as_millis() as u64 {
+                idle_ids.push(id.clone());
+            } else {
+                continue;
+            }
+            deliveries.insert(id, delivery_count);
+        }
+
+        let mut messages = self.claim(queue, consumer, Duration::ZERO, own_ids).await?;
+        messages.extend(self.claim(queue, consumer, PENDING_IDLE, idle_ids).await?);
+
+        let mut retries = vec![];
+        for message in messages {
+            if deliveries.get(&message.id).copied().unwrap_or(0) >= MAX_DELIVERIES {
+                self.dead_letter(queue, &message).await?;
+            } else {
+                retries.push(message);
+            }
+        }
+
+        Ok(retries)
+    }
+
+    // Keeps the message and its original id for inspection, and removes it from the group's pending list
+    pub async fn dead_letter(
+        &self,
+        queue: RedisQueues,
+        message: &StreamMessage,
+    ) -> Result<(), RedisError> {
+        println!(
+            "Moving message {} to {} after {} deliveries",
+            message.id,
+            queue.dead_letter(),
+            MAX_DELIVERIES
+        );
+
+        let _id: String = self
+            .client
+            .xadd(
+                queue.dead_letter(),
+                false,
+                None::<()>,
+                "*",
+                vec![
+                    (DATA_FIELD, message.data.clone()),
+                    ("id", message.id.clone()),
+                ],
+            )
+            .await?;
+
+        self.ack_id(queue, &message.id).await
+    }
+}
+
+// Reads one queue as a named consumer of the queue's group - messages stay pending until acknowledged
+pub struct QueueConsumer {
+    queue: RedisQueues,
+    name: String,
+    // Blocking reads hold their connection, so every consumer gets its own
+    blocking_client: RedisClient,
+    last_recovery: Option<Instant>,
+    last_handled: Instant,
+    metrics: QueueMetrics,
+}
+
+impl QueueConsumer {
+    // The name has to stay the same across restarts for a consumer to get its own pending messages back
+    pub async fn new(redis: &RedisManager, queue: RedisQueues) -> Result<Self, RedisError> {
+        redis.create_group(queue).await?;
+
+        let blocking_client = redis.client.clone_new();
+        blocking_client.init().await?;
+
+        let name = std::env::var("REDIS_CONSUMER").unwrap_or_else(|_| queue.group().to_string());
+        println!("Consuming {} as {} in group {}", queue, name, queue.group());
+
+        Ok(Self {
+            queue,
+            name,
+            blocking_client,
+            last_recovery: None,
+            last_handled: Instant::now(),
+            metrics: QueueMetrics::new(queue),
+        })
+    }
+
+    // Up to `count` messages, waiting up to READ_BLOCK when the queue is empty.
+    // Pending messages are redelivered first - on start, and then every PENDING_IDLE
+    pub async fn next_batch(
+        &mut self,
+        redis: &RedisManager,
+        count: u64,
+    ) -> Result<Vec<StreamMessage>, RedisError> {
+        self.metrics.report_if_due();
+
+        let recovery_due = self
+            .last_recovery
+            .is_none_or(|last_recovery| last_recovery.elapsed() >= PENDING_IDLE);
+
+        let mut messages = vec![];
+        if recovery_due {
+            self.last_recovery = Some(Instant::now());
+
+            messages = redis
+                .claim_pending(self.queue, &self.name, RECOVERY_BATCH)
+                .await?;
+            if !messages.is_empty() {
+                println!(
+                    "Redelivering {} pending {} messages",
+                    messages.len(),
+                    self.queue
+                );
+            }
+        }
+
+        if messages.is_empty() {
+            messages = read_group(
+                &self.blocking_client,
+                self.queue,
+                &self.name,
+                count,
+                READ_BLOCK,
+            )
+            .await?;
+        }
+
+        self.metrics.record_read(&messages);
+        self.last_handled = Instant::now();
+        Ok(messages)
+    }
+
+    pub async fn ack(&mut self, redis: &RedisManager, message: &StreamMessage) {
+        if let Err(e) = redis.ack_id(self.queue, &message.id).await {
+            println!(
+                "Failed to acknowledge {} message {} - {}",
+                self.queue, message.id, e
+            );
+        }
+        self.record_handled(true);
+    }
+
+    // Leaves the message pending - it is redelivered after PENDING_IDLE, up to MAX_DELIVERIES times
+    pub fn fail(&mut self, message: &StreamMessage, reason: &str) {
+        println!(
+            "Failed to process {} message {} - {}",
+            self.queue, message.id, reason
+        );
+        self.record_handled(false);
+    }
+
+    // Messages of a batch are handled one after the other, so each one took the time since the previous one
+    fn record_handled(&mut self, success: bool) {
+        let now = Instant::now();
+        self.metrics
+            .record_processed(now - self.last_handled, success);
+        self.last_handled = now;
+    }
+}
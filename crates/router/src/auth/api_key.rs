@@ -0,0 +1,277 @@
+use actix_web::{
+    dev::ServiceRequest,
+    web::{Bytes, Data},
+};
+use db_processor::{query::get_api_key_from_db, types::DbApiKey};
+use hmac::{Hmac, Mac};
+use protocol::errors::{ErrorCode, ErrorResponse};
+use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use super::{auth_error, header_value, AuthenticatedUser};
+use crate::types::app::AppState;
+
+pub const API_KEY_HEADER: &str = "X-API-KEY";
+pub const SIGNATURE_HEADER: &str = "X-SIGNATURE";
+pub const TIMESTAMP_HEADER: &str = "X-TIMESTAMP";
+pub const RECV_WINDOW_HEADER: &str = "X-RECV-WINDOW";
+
+const DEFAULT_RECV_WINDOW: i64 = 5000;
+const MAX_RECV_WINDOW: i64 = 60000;
+// Allowed clock drift for requests stamped slightly in the future
+const MAX_CLOCK_SKEW: i64 = 1000;
+
+// HMAC needs the secret itself to verify, so it is kept encrypted with AES-256-GCM under a key from
+// the router's config - the api_keys table alone is not enough to sign requests
+pub struct SecretCipher {
+    key: LessSafeKey,
+}
+
+impl SecretCipher {
+    pub fn from_hex(key: &str) -> Result<SecretCipher, String> {
+        let key = hex::decode(key).map_err(|e| e.to_string())?;
+        let key = UnboundKey::new(&AES_256_GCM, &key)
+            .map_err(|_| "Encryption key must be 32 bytes".to_string())?;
+
+        Ok(SecretCipher {
+            key: LessSafeKey::new(key),
+        })
+    }
+
+    // hex of nonce + ciphertext + tag, bound to the key id so it can't be moved to another key
+    pub fn encrypt(&self, key_id: &str, secret: &str) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut in_out = secret.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(key_id.as_bytes()),
+                &mut in_out,
+            )
+            .expect("AES-GCM encrypts secrets of any size");
+
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend_from_slice(&in_out);
+        hex::encode(encrypted)
+    }
+
+    pub fn decrypt(&self, key_id: &str, encrypted: &str) -> Option<String> {
+        let encrypted = hex::decode(encrypted).ok()?;
+        if encrypted.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+
+        let mut in_out = ciphertext.to_vec();
+        let secret = self
+            .key
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(nonce).ok()?,
+                Aad::from(key_id.as_bytes()),
+                &mut in_out,
+            )
+            .ok()?;
+
+        String::from_utf8(secret.to_vec()).ok()
+    }
+}
+
+// The secret is only shown once, the database keeps it encrypted
+pub fn generate_api_key(user_id: &str, cipher: &SecretCipher) -> (DbApiKey, String) {
+    let mut secret_bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret_bytes);
+    let secret = hex::encode(secret_bytes);
+    let key_id = Uuid::new_v4().simple().to_string();
+
+    let api_key = DbApiKey {
+        secret_encrypted: cipher.encrypt(&key_id, &secret),
+        key_id,
+        user_id: user_id.to_string(),
+        created_at: chrono::Utc::now().timestamp_millis(),
+        revoked: false,
+    };
+
+    (api_key, secret)
+}
+
+// timestamp + recvWindow + query string + body, e.g. "17278663240885000symbol=SOL_USDC{...}"
+pub fn signature_payload(timestamp: i64, recv_window: i64, query: &str, body: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{}{}{}", timestamp, recv_window, query).into_bytes();
+    payload.extend_from_slice(body);
+    payload
+}
+
+pub fn sign(secret: &str, payload: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload);
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn verify_signature(secret: &str, payload: &[u8], signature: &str) -> bool {
+    let signature = match hex::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload);
+
+    // Constant time comparison
+    mac.verify_slice(&signature).is_ok()
+}
+
+pub fn verify_timestamp(timestamp: i64, recv_window: i64, now: i64) -> Result<(), ErrorResponse> {
+    if recv_window <= 0 || recv_window > MAX_RECV_WINDOW {
+        return Err(auth_error(
+            ErrorCode::InvalidTimestamp,
+            format!("recvWindow must be between 1 and {}", MAX_RECV_WINDOW),
+        ));
+    }
+
+    if timestamp > now + MAX_CLOCK_SKEW || now - timestamp > recv_window {
+        return Err(auth_error(
+            ErrorCode::InvalidTimestamp,
+            "Timestamp for this request is outside of the recvWindow".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Replace the consumed body so the handler's extractors can still read it
+fn restore_body(req: &mut ServiceRequest, body: Bytes) {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    req.set_payload(payload.into());
+}
+
+pub async fn authenticate(req: &mut ServiceRequest) -> Result<AuthenticatedUser, ErrorResponse> {
+    let missing = |header: &str| auth_error(ErrorCode::Unauthorized, format!("Missing {}", header));
+    let invalid = |msg: &str| auth_error(ErrorCode::InvalidTimestamp, msg.to_string());
+
+    let key_id = header_value(req, API_KEY_HEADER).ok_or_else(|| missing(API_KEY_HEADER))?;
+    let signature = header_value(req, SIGNATURE_HEADER).ok_or_else(|| missing(SIGNATURE_HEADER))?;
+    let timestamp = header_value(req, TIMESTAMP_HEADER)
+        .ok_or_else(|| missing(TIMESTAMP_HEADER))?
+        .parse::<i64>()
+        .map_err(|_| invalid("Invalid timestamp"))?;
+    let recv_window = match header_value(req, RECV_WINDOW_HEADER) {
+        Some(recv_window) => recv_window
+            .parse::<i64>()
+            .map_err(|_| invalid("Invalid recvWindow"))?,
+        None => DEFAULT_RECV_WINDOW,
+    };
+
+    let now = chrono::Utc::now().timestamp_millis();
+    verify_timestamp(timestamp, recv_window, now)?;
+
+    let body = req
+        .extract::<Bytes>()
+        .await
+        .map_err(|e| auth_error(ErrorCode::Unauthorized, e.to_string()))?;
+    restore_body(req, body.clone());
+
+    let app_state = req
+        .app_data::<Data<AppState>>()
+        .expect("AppState must be registered before the auth middleware");
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+
+    let api_key = match get_api_key_from_db(&pg_pool, &key_id).await {
+        Ok(Some(api_key)) if !api_key.revoked => api_key,
+        Ok(_) => {
+            return Err(auth_error(
+                ErrorCode::Unauthorized,
+                "Invalid API key".to_string(),
+            ))
+        }
+        Err(e) => {
+            println!("Failed to look up API key - {}", e);
+            return Err(auth_error(ErrorCode::Internal, e.to_string()));
+        }
+    };
+
+    let secret = match app_state
+        .secret_cipher
+        .decrypt(&api_key.key_id, &api_key.secret_encrypted)
+    {
+        Some(secret) => secret,
+        None => {
+            println!("Failed to decrypt the secret of API key {}", api_key.key_id);
+            return Err(auth_error(
+                ErrorCode::Internal,
+                "API key could not be verified".to_string(),
+            ));
+        }
+    };
+
+    let payload = signature_payload(timestamp, recv_window, req.query_string(), &body);
+    if !verify_signature(&secret, &payload, &signature) {
+        return Err(auth_error(
+            ErrorCode::InvalidSignature,
+            "Signature for this request is not valid".to_string(),
+        ));
+    }
+
+    Ok(AuthenticatedUser {
+        user_id: api_key.user_id,
+        session: None,
+        api_key: Some(api_key.key_id),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_signed_payload() {
+        let payload = signature_payload(1727866324088, 5000, "symbol=SOL_USDC", b"{}");
+        let signature = sign("secret", &payload);
+
+        assert!(verify_signature("secret", &payload, &signature));
+
+        let tampered = signature_payload(1727866324088, 5000, "symbol=SOL_USDC", b"{\"a\":1}");
+        assert!(!verify_signature("secret", &tampered, &signature));
+        assert!(!verify_signature("other", &payload, &signature));
+        assert!(!verify_signature("secret", &payload, "not hex"));
+    }
+
+    #[test]
+    fn stores_secrets_encrypted() {
+        let cipher = SecretCipher::from_hex(&"07".repeat(32)).unwrap();
+        let (api_key, secret) = generate_api_key("user", &cipher);
+
+        // What is stored can't sign by itself
+        assert!(!api_key.secret_encrypted.contains(&secret));
+        assert_eq!(
+            cipher.decrypt(&api_key.key_id, &api_key.secret_encrypted),
+            Some(secret)
+        );
+
+        // Bound to its key id and to the server key
+        assert_eq!(cipher.decrypt("other", &api_key.secret_encrypted), None);
+        let other = SecretCipher::from_hex(&"08".repeat(32)).unwrap();
+        assert_eq!(
+            other.decrypt(&api_key.key_id, &api_key.secret_encrypted),
+            None
+        );
+        assert!(SecretCipher::from_hex("abcd").is_err());
+    }
+
+    #[test]
+    fn rejects_timestamps_outside_recv_window() {
+        let now = 1727866324088;
+
+        assert!(verify_timestamp(now - 4000, 5000, now).is_ok());
+        assert!(verify_timestamp(now - 6000, 5000, now).is_err());
+        assert!(verify_timestamp(now + 5000, 5000, now).is_err());
+        assert!(verify_timestamp(now, MAX_RECV_WINDOW + 1, now).is_err());
+    }
+}
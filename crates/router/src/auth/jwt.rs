@@ -0,0 +1,128 @@
+use actix_web::web::Data;
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use protocol::errors::{ErrorCode, ErrorResponse};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{auth_error, AuthenticatedUser};
+use crate::types::app::AppState;
+
+pub const ACCESS_TOKEN_TTL_SECONDS: i64 = 15 * 60;
+pub const REFRESH_TOKEN_TTL_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String, // user_id
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String, // lets a single access token be revoked on logout
+}
+
+// Access tokens are stateless, logged out ones are kept in redis until they would have expired anyway
+pub fn revoked_token_key(jti: &str) -> String {
+    format!("revokedToken.{}", jti)
+}
+
+pub fn issue_access_token(
+    secret: &str,
+    user_id: &str,
+    now: i64,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let claims = Claims {
+        sub: user_id.to_string(),
+        iat: now,
+        exp: now + ACCESS_TOKEN_TTL_SECONDS,
+        jti: Uuid::new_v4().simple().to_string(),
+    };
+
+    jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+}
+
+pub fn decode_access_token(
+    secret: &str,
+    token: &str,
+) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let mut validation = Validation::default();
+    validation.leeway = 0;
+
+    jsonwebtoken::decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .map(|data| data.claims)
+}
+
+// Opaque random token - only its SHA-256 is stored
+pub fn generate_refresh_token() -> String {
+    let mut token_bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut token_bytes);
+
+    hex::encode(token_bytes)
+}
+
+pub async fn authenticate(
+    app_state: &Data<AppState>,
+    token: &str,
+) -> Result<AuthenticatedUser, ErrorResponse> {
+    let claims = decode_access_token(&app_state.jwt_secret, token).map_err(|e| {
+        auth_error(
+            ErrorCode::Unauthorized,
+            format!("Invalid access token - {}", e),
+        )
+    })?;
+
+    match app_state
+        .redis_connection
+        .get(&revoked_token_key(&claims.jti))
+        .await
+    {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return Err(auth_error(
+                ErrorCode::Unauthorized,
+                "Access token has been revoked".to_string(),
+            ))
+        }
+        Err(e) => {
+            println!("Failed to check revoked tokens - {}", e);
+            return Err(auth_error(ErrorCode::Internal, e.to_string()));
+        }
+    }
+
+    Ok(AuthenticatedUser {
+        user_id: claims.sub.clone(),
+        session: Some(claims),
+        api_key: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_access_token() {
+        let now = chrono::Utc::now().timestamp();
+        let token = issue_access_token("secret", "user_1", now).unwrap();
+
+        let claims = decode_access_token("secret", &token).unwrap();
+        assert_eq!(claims.sub, "user_1");
+        assert_eq!(claims.exp, now + ACCESS_TOKEN_TTL_SECONDS);
+
+        assert!(decode_access_token("other secret", &token).is_err());
+    }
+
+    #[test]
+    fn rejects_expired_access_token() {
+        let issued_at = chrono::Utc::now().timestamp() - ACCESS_TOKEN_TTL_SECONDS - 1;
+        let token = issue_access_token("secret", "user_1", issued_at).unwrap();
+
+        assert!(decode_access_token("secret", &token).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+pub mod api_key;
+pub mod jwt;
+pub mod password;
+pub mod totp;
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web::Data,
+    Error, HttpMessage,
+};
+use protocol::errors::{ErrorCode, ErrorResponse};
+use sha2::{Digest, Sha256};
+
+use crate::rate_limit::{self, RateLimitScope};
+use crate::routes::error_status;
+use crate::types::app::AppState;
+use jwt::Claims;
+
+// Inserted into the request extensions by the auth middleware - handlers take it as ReqData<AuthenticatedUser>
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: String,
+    pub session: Option<Claims>, // set when authenticated with an access token instead of an API key
+    pub api_key: Option<String>, // set when authenticated with a signed API key request
+}
+
+pub fn sha256_hex(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+pub fn auth_error(code: ErrorCode, msg: String) -> ErrorResponse {
+    ErrorResponse { code, msg }
+}
+
+pub fn header_value(req: &ServiceRequest, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+// "Authorization: Bearer <access token>" for browser sessions, signed API key requests otherwise
+async fn authenticate(req: &mut ServiceRequest) -> Result<AuthenticatedUser, ErrorResponse> {
+    let bearer_token = header_value(req, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer ").map(|token| token.to_string()));
+
+    match bearer_token {
+        Some(token) => {
+            let app_state = req
+                .app_data::<Data<AppState>>()
+                .expect("AppState must be registered before the auth middleware");
+
+            jwt::authenticate(app_state, &token).await
+        }
+        None => api_key::authenticate(req).await,
+    }
+}
+
+// Rejects unauthenticated requests, and hands the authenticated user to the handlers
+pub async fn require_auth(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<EitherBody<impl MessageBody>>>, Error> {
+    match authenticate(&mut req).await {
+        Ok(user) => {
+            let mut scopes = vec![(RateLimitScope::User, user.user_id.clone())];
+            if let Some(api_key) = &user.api_key {
+                scopes.push((RateLimitScope::ApiKey, api_key.clone()));
+            }
+
+            req.extensions_mut().insert(user);
+            rate_limit::limit(req, next, &scopes)
+                .await
+                .map(|res| res.map_into_left_body())
+        }
+        Err(error) => {
+            println!("Rejected request to {} - {}", req.path(), error.msg);
+            let response = actix_web::HttpResponse::build(error_status(error.code)).json(error);
+            Ok(req.into_response(response).map_into_right_body())
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use actix_web::HttpRequest;
+use db_processor::query::{get_user_by_id, use_recovery_code, use_totp_step};
+use protocol::errors::ErrorCode;
+use rand::RngCore;
+use sqlx::{Pool, Postgres};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use super::sha256_hex;
+use crate::routes::error_response;
+use crate::types::app::AppState;
+
+// Either a current TOTP code or one of the recovery codes
+pub const TWO_FACTOR_HEADER: &str = "X-2FA-CODE";
+
+const ISSUER: &str = "Exchange";
+const RECOVERY_CODE_COUNT: usize = 10;
+
+// Base32 secret as shown to authenticator apps
+pub fn generate_secret() -> String {
+    match Secret::generate_secret().to_encoded() {
+        Secret::Encoded(secret) => secret,
+        Secret::Raw(_) => unreachable!("to_encoded always returns an encoded secret"),
+    }
+}
+
+// 6 digits, 30 second steps, one step of clock drift either way
+fn totp(secret: &str, account_name: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(ISSUER.to_string()),
+        account_name.to_string(),
+    )
+    .ok()
+}
+
+// otpauth://totp/Exchange:<email>?secret=...&issuer=Exchange - rendered as a QR code by the frontend
+pub fn provisioning_uri(secret: &str, account_name: &str) -> Option<String> {
+    totp(secret, account_name).map(|totp| totp.get_url())
+}
+
+// The time step the code was generated for, so the caller can refuse to accept it twice
+pub fn verify_code(secret: &str, code: &str, now: u64) -> Option<u64> {
+    let mut totp = totp(secret, "")?;
+    let current_step = now / totp.step;
+
+    // Each step is checked on its own to know which one matched
+    let skew = totp.skew as u64;
+    totp.skew = 0;
+    (current_step.saturating_sub(skew)..=current_step + skew)
+        .find(|step| totp.check(code.trim(), step * totp.step))
+}
+
+// Shown to the user once, only their hashes are stored
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut code_bytes = [0u8; 5];
+            rand::thread_rng().fill_bytes(&mut code_bytes);
+            hex::encode(code_bytes)
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    sha256_hex(&code.trim().to_lowercase())
+}
+
+// How often one TOTP code can be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeUse {
+    // Login and credential changes - every code works once
+    Credential,
+    // Order cancels and the countdown cancel - a code can be repeated within its 30 second step,
+    // a countdown refreshed every few seconds would otherwise run out
+    Action,
+}
+
+impl CodeUse {
+    // A code of `step` is accepted while the last step the user passed is below this
+    pub fn step_bound(self, step: u64) -> u64 {
+        match self {
+            CodeUse::Credential => step,
+            CodeUse::Action => step + 1,
+        }
+    }
+
+    pub fn accepts(self, last_step: Option<u64>, step: u64) -> bool {
+        last_step.is_none_or(|last_step| last_step < self.step_bound(step))
+    }
+}
+
+// Sensitive actions call this before doing anything - a no-op for users without 2FA
+pub async fn require_second_factor(
+    app_state: &AppState,
+    req: &HttpRequest,
+    user_id: &str,
+    code_use: CodeUse,
+) -> Result<(), actix_web::HttpResponse> {
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+
+    let user = match get_user_by_id(&pg_pool, user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("Failed to look up user - {}", e);
+            return Err(actix_web::HttpResponse::InternalServerError().finish());
+        }
+    };
+
+    // Users created through API keys only, or without 2FA, have no second factor to check
+    let secret = match user {
+        Some(user) if user.totp_enabled => user.totp_secret.unwrap_or_default(),
+        _ => return Ok(()),
+    };
+
+    let code = match req
+        .headers()
+        .get(TWO_FACTOR_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(code) => code.to_string(),
+        None => {
+            return Err(error_response(
+                ErrorCode::TwoFactorRequired,
+                format!("This action requires a 2FA code in {}", TWO_FACTOR_HEADER),
+            ))
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    if let Some(step) = verify_code(&secret, &code, now) {
+        return use_code_step(&pg_pool, user_id, step, code_use).await;
+    }
+
+    match use_recovery_code(&pg_pool, user_id, &hash_recovery_code(&code)).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(error_response(
+            ErrorCode::InvalidTwoFactorCode,
+            "Invalid 2FA code".to_string(),
+        )),
+        Err(e) => {
+            println!("Failed to check recovery code - {}", e);
+            Err(actix_web::HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+// Records the step, refusing codes older than the last one the user passed
+pub async fn use_code_step(
+    pg_pool: &Pool<Postgres>,
+    user_id: &str,
+    step: u64,
+    code_use: CodeUse,
+) -> Result<(), actix_web::HttpResponse> {
+    let step_bound = code_use.step_bound(step) as i64;
+
+    match use_totp_step(pg_pool, user_id, step as i64, step_bound).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(error_response(
+            ErrorCode::InvalidTwoFactorCode,
+            "2FA code was already used".to_string(),
+        )),
+        Err(e) => {
+            println!("Failed to record 2FA code - {}", e);
+            Err(actix_web::HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_current_code_only() {
+        let secret = generate_secret();
+        let now = 1727866324;
+        let code = totp(&secret, "").unwrap().generate(now);
+
+        let step = now / 30;
+        assert_eq!(verify_code(&secret, &code, now), Some(step));
+        assert_eq!(verify_code(&secret, &code, now + 30), Some(step)); // one step of drift
+        assert_eq!(verify_code(&secret, &code, now - 30), Some(step));
+        assert_eq!(verify_code(&secret, &code, now + 90), None);
+        assert_eq!(verify_code("not base32!", &code, now), None);
+    }
+
+    #[test]
+    fn builds_provisioning_uri() {
+        let uri = provisioning_uri(&generate_secret(), "user@example.com").unwrap();
+
+        assert!(uri.starts_with("otpauth://totp/Exchange:user%40example.com?secret="));
+        assert!(uri.contains("issuer=Exchange"));
+    }
+
+    #[test]
+    fn recovery_codes_are_unique() {
+        let codes = generate_recovery_codes();
+
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(
+            hash_recovery_code(&codes[0]),
+            hash_recovery_code(&codes[0].to_uppercase())
+        );
+        assert_ne!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[1]));
+    }
+
+    #[test]
+    fn actions_can_repeat_a_code_within_its_step() {
+        let step = 1727866324 / 30;
+
+        // Two countdown refreshes with the same code, then a fresh login code is still needed
+        assert!(CodeUse::Action.accepts(None, step));
+        assert!(CodeUse::Action.accepts(Some(step), step));
+        assert!(!CodeUse::Credential.accepts(Some(step), step));
+        assert!(CodeUse::Credential.accepts(Some(step), step + 1));
+
+        // Older codes are refused either way
+        assert!(!CodeUse::Action.accepts(Some(step), step - 1));
+        assert!(!CodeUse::Credential.accepts(Some(step), step - 1));
+    }
+}
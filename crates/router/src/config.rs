@@ -3,4 +3,6 @@ use confik::Configuration;
 #[derive(Debug, Default, Configuration)]
 pub struct RouterConfig {
     pub server_addr: String,
+    pub jwt_secret: String,
+    pub api_key_encryption_key: String, // 32 bytes in hex, encrypts API key secrets at rest
 }
@@ -1,16 +1,23 @@
 use actix_cors::Cors;
 use actix_web::{
+    middleware::from_fn,
     web::{self, scope},
     App, HttpResponse, HttpServer,
 };
 use confik::{Configuration as _, EnvSource};
 use dotenvy::dotenv;
-use routes::{depth, klines, order, tickers, trade, user};
+use routes::{
+    api_key, auth as auth_routes, depth, klines, order, tickers, trade, two_factor, user_stream,
+};
 use sqlx_postgres::PostgresDb;
+use std::sync::Arc;
 
+pub mod auth;
 pub mod config;
+pub mod rate_limit;
 pub mod routes;
 pub mod types;
+use crate::auth::api_key::SecretCipher;
 use crate::config::RouterConfig;
 use crate::types::app::AppState;
 
@@ -27,9 +34,14 @@ async fn main() -> std::io::Result<()> {
         .try_build()
         .unwrap();
 
+    let redis_connection = Arc::new(RedisManager::new().await.unwrap());
     let app_state = web::Data::new(AppState {
-        redis_connection: RedisManager::new().await.unwrap(),
+        bus: redis_connection.clone(),
+        redis_connection,
         postgres_db: PostgresDb::new().await.unwrap(),
+        jwt_secret: config.jwt_secret.clone(),
+        secret_cipher: SecretCipher::from_hex(&config.api_key_encryption_key)
+            .expect("API_KEY_ENCRYPTION_KEY must be 32 bytes in hex"),
     });
 
     let server = HttpServer::new(move || {
@@ -44,22 +56,82 @@ async fn main() -> std::io::Result<()> {
             .service(
                 scope("/api/v1")
                     .app_data(app_state.clone())
+                    .wrap(from_fn(rate_limit::limit_by_ip))
                     .service(web::scope("/health").route("", web::get().to(HttpResponse::Ok))) // GET /ping
-                    .service(web::scope("/users").route("", web::post().to(user::create_user))) // POST /users
-                    .service(web::scope("/depth").route("", web::get().to(depth::get_depth))) // GET /depth?symbol=SOL_USDC
+                    .service(
+                        web::scope("/auth")
+                            .route("/register", web::post().to(auth_routes::register)) // POST /auth/register
+                            .route("/login", web::post().to(auth_routes::login)) // POST /auth/login
+                            .route("/refresh", web::post().to(auth_routes::refresh)) // POST /auth/refresh
+                            .service(
+                                web::resource("/logout")
+                                    .wrap(from_fn(auth::require_auth))
+                                    .route(web::post().to(auth_routes::logout)), // POST /auth/logout
+                            )
+                            .service(
+                                web::resource("/password")
+                                    .wrap(from_fn(auth::require_auth))
+                                    .route(web::post().to(auth_routes::change_password)), // POST /auth/password
+                            )
+                            .service(
+                                web::scope("/2fa")
+                                    .wrap(from_fn(auth::require_auth))
+                                    .route("/enroll", web::post().to(two_factor::enroll)) // POST /auth/2fa/enroll
+                                    .route("/confirm", web::post().to(two_factor::confirm)) // POST /auth/2fa/confirm
+                                    .route("/disable", web::post().to(two_factor::disable)), // POST /auth/2fa/disable
+                            ),
+                    )
+                    .service(
+                        web::scope("/apiKeys")
+                            .wrap(from_fn(auth::require_auth))
+                            .route("", web::post().to(api_key::create_api_key)), // POST /apiKeys
+                    )
+                    .service(
+                        web::scope("/depth")
+                            .route("", web::get().to(depth::get_depth)) // GET /depth?symbol=SOL_USDC&limit=100
+                            .service(
+                                web::resource("/l3")
+                                    .wrap(from_fn(auth::require_auth))
+                                    .route(web::get().to(depth::get_l3_depth)), // GET /depth/l3?symbol=SOL_USDC
+                            ),
+                    )
                     .service(web::scope("/trades").route("", web::get().to(trade::get_trades))) // GET /trades?symbol=SOL_USDC
-                    .service(web::scope("/klines").route("", web::get().to(klines::get_klines))) // GET /klines?symbol=SOL_USDC&interval=1m&startTime=1727022600
+                    .service(web::scope("/klines").route("", web::get().to(klines::get_klines))) // GET /klines?symbol=SOL_USDC&interval=15m&startTime=1727022600000&limit=100
                     .service(web::scope("/tickers").route("", web::get().to(tickers::get_tickers))) // GET /klines?symbol=SOL_USDC&interval=1m&startTime=1727022600
+                    .service(
+                        web::scope("/ticker")
+                            .route("/bookTicker", web::get().to(tickers::get_book_ticker)), // GET /ticker/bookTicker?symbol=SOL_USDC
+                    )
                     .service(
                         web::scope("/order")
+                            .wrap(from_fn(auth::require_auth))
                             .route("", web::get().to(order::get_open_order)) // GET /order
                             .route("", web::post().to(order::execute_order)) // POST /order
                             .route("", web::delete().to(order::cancel_order)), // DELETE /order
                     )
+                    .service(
+                        web::scope("/userDataStream")
+                            .wrap(from_fn(auth::require_auth))
+                            .route("", web::post().to(user_stream::create_listen_key)) // POST /userDataStream
+                            .route("", web::put().to(user_stream::keep_alive_listen_key)) // PUT /userDataStream?listenKey=...
+                            .route("", web::delete().to(user_stream::close_listen_key)), // DELETE /userDataStream?listenKey=...
+                    )
                     .service(
                         web::scope("/orders")
+                            .wrap(from_fn(auth::require_auth))
                             .route("", web::get().to(order::get_open_orders)) // GET /orders
                             .route("", web::delete().to(order::cancel_all_orders)), // DELETE /orders
+                    )
+                    .service(
+                        web::scope("/batchOrders")
+                            .wrap(from_fn(auth::require_auth))
+                            .route("", web::post().to(order::execute_batch_orders)) // POST /batchOrders
+                            .route("", web::delete().to(order::cancel_batch_orders)), // DELETE /batchOrders
+                    )
+                    .service(
+                        web::scope("/countdownCancelAll")
+                            .wrap(from_fn(auth::require_auth))
+                            .route("", web::post().to(order::countdown_cancel_all)), // POST /countdownCancelAll
                     ),
             )
     })
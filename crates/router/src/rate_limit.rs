@@ -0,0 +1,233 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER},
+        Method,
+    },
+    middleware::Next,
+    web::Data,
+    Error,
+};
+use protocol::errors::ErrorCode;
+
+use crate::routes::error_response;
+use crate::types::app::AppState;
+
+// Fixed one minute windows - counters live in redis so every router instance shares them
+pub const WINDOW_SECONDS: i64 = 60;
+
+pub const IP_WEIGHT_LIMIT: i64 = 1200;
+pub const USER_WEIGHT_LIMIT: i64 = 2400; // a user can spread requests over several API keys
+pub const API_KEY_WEIGHT_LIMIT: i64 = 1200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitScope {
+    Ip,
+    User,
+    ApiKey,
+}
+
+impl RateLimitScope {
+    fn name(&self) -> &'static str {
+        match self {
+            RateLimitScope::Ip => "ip",
+            RateLimitScope::User => "user",
+            RateLimitScope::ApiKey => "api-key",
+        }
+    }
+
+    fn limit(&self) -> i64 {
+        match self {
+            RateLimitScope::Ip => IP_WEIGHT_LIMIT,
+            RateLimitScope::User => USER_WEIGHT_LIMIT,
+            RateLimitScope::ApiKey => API_KEY_WEIGHT_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitUsage {
+    pub scope: RateLimitScope,
+    pub used: i64,
+    pub reset_in: i64, // seconds until the current window ends
+}
+
+impl RateLimitUsage {
+    pub fn exceeded(&self) -> bool {
+        self.used > self.scope.limit()
+    }
+
+    // X-RATELIMIT-USED-WEIGHT-<SCOPE> and X-RATELIMIT-LIMIT-<SCOPE> on every limited response
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            ("x-ratelimit-used-weight", self.used),
+            ("x-ratelimit-limit", self.scope.limit()),
+        ];
+
+        for (prefix, value) in values {
+            let name = format!("{}-{}", prefix, self.scope.name());
+            if let Ok(name) = HeaderName::try_from(name) {
+                headers.insert(name, HeaderValue::from(value));
+            }
+        }
+    }
+}
+
+// Requests that put load on the engine cost more than market data reads
+pub fn request_weight(method: &Method, path: &str) -> i64 {
+    let path = path.strip_prefix("/api/v1").unwrap_or(path);
+
+    match (method.as_str(), path.trim_end_matches('/')) {
+        ("POST", "/order") => 10,
+        ("DELETE", "/orders") => 10,
+        ("DELETE", "/order") | ("GET", "/orders") => 5,
+        // Up to 50 orders each, cheaper per order than placing them one by one
+        ("POST", "/batchOrders") | ("DELETE", "/batchOrders") => 50,
+        // Slows down password guessing and account creation from a single IP
+        ("POST", "/auth/login") | ("POST", "/auth/register") | ("POST", "/auth/refresh") => 10,
+        ("POST", "/apiKeys") => 10,
+        ("GET", "/depth") | ("GET", "/klines") => 2,
+        // Every resting order of the book
+        ("GET", "/depth/l3") => 20,
+        _ => 1,
+    }
+}
+
+fn window_key(scope: RateLimitScope, id: &str, window: i64) -> String {
+    format!("rateLimit.{}.{}.{}", scope.name(), id, window)
+}
+
+// Adds the weight to the current window of every scope - rejected requests still count
+pub async fn consume(
+    app_state: &AppState,
+    scopes: &[(RateLimitScope, String)],
+    weight: i64,
+) -> Vec<RateLimitUsage> {
+    let now = chrono::Utc::now().timestamp();
+    let window = now / WINDOW_SECONDS;
+    let reset_in = WINDOW_SECONDS - now % WINDOW_SECONDS;
+    let mut usages = Vec::with_capacity(scopes.len());
+
+    for (scope, id) in scopes {
+        let result = app_state
+            .redis_connection
+            .incr_with_expiry(&window_key(*scope, id, window), weight, WINDOW_SECONDS)
+            .await;
+
+        // Fails open - an unavailable redis should not take the whole API down with it
+        match result {
+            Ok(used) => usages.push(RateLimitUsage {
+                scope: *scope,
+                used,
+                reset_in,
+            }),
+            Err(e) => println!("Failed to update rate limit counter - {}", e),
+        }
+    }
+
+    usages
+}
+
+pub fn rate_limited_response(usages: &[RateLimitUsage]) -> Option<actix_web::HttpResponse> {
+    let exceeded = usages.iter().find(|usage| usage.exceeded())?;
+
+    let mut response = error_response(
+        ErrorCode::RateLimited,
+        format!(
+            "Too much request weight for {}, retry in {} seconds",
+            exceeded.scope.name(),
+            exceeded.reset_in
+        ),
+    );
+
+    let headers = response.headers_mut();
+    headers.insert(RETRY_AFTER, HeaderValue::from(exceeded.reset_in));
+    for usage in usages {
+        usage.apply_headers(headers);
+    }
+
+    Some(response)
+}
+
+// Checks the limits of the given scopes before calling the handler
+pub async fn limit<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+    scopes: &[(RateLimitScope, String)],
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let app_state = req
+        .app_data::<Data<AppState>>()
+        .expect("AppState must be registered before the rate limit middleware")
+        .clone();
+
+    let weight = request_weight(req.method(), req.path());
+    let usages = consume(&app_state, scopes, weight).await;
+
+    if let Some(response) = rate_limited_response(&usages) {
+        println!("Rate limited request to {}", req.path());
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    let mut res = next.call(req).await?;
+    for usage in &usages {
+        usage.apply_headers(res.headers_mut());
+    }
+
+    Ok(res.map_into_left_body())
+}
+
+// Wraps every route - authenticated routes are also limited per user and API key in require_auth
+pub async fn limit_by_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    // The socket address, forwarded headers are trivially spoofed
+    let scopes = match req.peer_addr() {
+        Some(addr) => vec![(RateLimitScope::Ip, addr.ip().to_string())],
+        None => vec![],
+    };
+
+    limit(req, next, &scopes).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order_placement_outweighs_market_data() {
+        assert_eq!(request_weight(&Method::POST, "/api/v1/order"), 10);
+        assert_eq!(request_weight(&Method::GET, "/api/v1/order"), 1);
+        assert_eq!(request_weight(&Method::POST, "/api/v1/batchOrders"), 50);
+        assert_eq!(request_weight(&Method::GET, "/api/v1/depth"), 2);
+        assert_eq!(request_weight(&Method::GET, "/api/v1/depth/l3"), 20);
+        assert_eq!(request_weight(&Method::GET, "/api/v1/trades/"), 1);
+    }
+
+    #[test]
+    fn reports_exceeded_scope() {
+        let usages = vec![
+            RateLimitUsage {
+                scope: RateLimitScope::Ip,
+                used: IP_WEIGHT_LIMIT,
+                reset_in: 12,
+            },
+            RateLimitUsage {
+                scope: RateLimitScope::ApiKey,
+                used: API_KEY_WEIGHT_LIMIT + 1,
+                reset_in: 12,
+            },
+        ];
+
+        let response = rate_limited_response(&usages).unwrap();
+        let headers = response.headers();
+
+        assert_eq!(response.status(), 429);
+        assert_eq!(headers.get(RETRY_AFTER).unwrap(), "12");
+        assert_eq!(headers.get("x-ratelimit-used-weight-ip").unwrap(), "1200");
+        assert_eq!(headers.get("x-ratelimit-limit-api-key").unwrap(), "1200");
+
+        assert!(rate_limited_response(&usages[..1]).is_none());
+    }
+}
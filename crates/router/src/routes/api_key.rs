@@ -0,0 +1,34 @@
+use actix_web::web::{Data, ReqData};
+use actix_web::HttpRequest;
+use db_processor::query::insert_api_key;
+
+use crate::auth::totp::{require_second_factor, CodeUse};
+use crate::auth::{api_key::generate_api_key, AuthenticatedUser};
+use crate::types::{app::AppState, routes::ApiKeyResponse};
+
+// Issues a new API key for the authenticated user - the secret is only returned once
+pub async fn create_api_key(
+    req: HttpRequest,
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    if let Err(response) =
+        require_second_factor(&app_state, &req, &user.user_id, CodeUse::Credential).await
+    {
+        return response;
+    }
+
+    let (api_key, secret_key) = generate_api_key(&user.user_id, &app_state.secret_cipher);
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+
+    if let Err(e) = insert_api_key(&pg_pool, &api_key).await {
+        println!("Failed to store API key - {}", e);
+        return actix_web::HttpResponse::InternalServerError().finish();
+    }
+
+    actix_web::HttpResponse::Ok().json(ApiKeyResponse {
+        user_id: api_key.user_id,
+        api_key: api_key.key_id,
+        secret_key,
+    })
+}
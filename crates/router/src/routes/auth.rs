@@ -0,0 +1,344 @@
+use actix_web::web::{Data, Json, ReqData};
+use actix_web::HttpRequest;
+use db_processor::query::{
+    delete_user, get_user_by_email, get_user_by_id, insert_refresh_token, insert_user,
+    revoke_refresh_token, revoke_user_refresh_tokens, update_password_hash,
+};
+use db_processor::types::{DbRefreshToken, DbUser};
+use protocol::errors::{EngineReply, ErrorCode};
+use protocol::users::CreateUserResponse;
+use sqlx::{Pool, Postgres};
+use std::time::Instant;
+use uuid::Uuid;
+
+use crate::auth::jwt::{
+    generate_refresh_token, issue_access_token, revoked_token_key, ACCESS_TOKEN_TTL_SECONDS,
+    REFRESH_TOKEN_TTL_SECONDS,
+};
+use crate::auth::password::{hash_password, verify_password, MIN_PASSWORD_LENGTH};
+use crate::auth::totp::{require_second_factor, CodeUse};
+use crate::auth::{sha256_hex, AuthenticatedUser};
+use crate::routes::{
+    engine_response, error_response, reply_error_response, user::create_engine_user,
+};
+use crate::types::app::AppState;
+use crate::types::routes::{
+    ChangePasswordInput, CredentialsInput, RefreshTokenInput, TokenResponse,
+};
+
+// Short lived access token plus a single use refresh token
+async fn issue_tokens(
+    app_state: &AppState,
+    user_id: &str,
+) -> Result<TokenResponse, actix_web::HttpResponse> {
+    let now = chrono::Utc::now().timestamp();
+
+    let access_token = issue_access_token(&app_state.jwt_secret, user_id, now).map_err(|e| {
+        println!("Failed to issue access token - {}", e);
+        actix_web::HttpResponse::InternalServerError().finish()
+    })?;
+
+    let refresh_token = generate_refresh_token();
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+    let stored_token = DbRefreshToken {
+        token_hash: sha256_hex(&refresh_token),
+        user_id: user_id.to_string(),
+        expires_at: now + REFRESH_TOKEN_TTL_SECONDS,
+        revoked: false,
+    };
+
+    insert_refresh_token(&pg_pool, &stored_token)
+        .await
+        .map_err(|e| {
+            println!("Failed to store refresh token - {}", e);
+            actix_web::HttpResponse::InternalServerError().finish()
+        })?;
+
+    Ok(TokenResponse {
+        user_id: user_id.to_string(),
+        access_token,
+        refresh_token,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_TTL_SECONDS,
+    })
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub async fn register(
+    body: Json<CredentialsInput>,
+    app_state: Data<AppState>,
+) -> actix_web::HttpResponse {
+    let starttime = Instant::now();
+    let input = body.into_inner();
+    let email = normalize_email(&input.email);
+
+    if !email.contains('@') {
+        return error_response(ErrorCode::InvalidRegistration, "Invalid email".to_string());
+    }
+    if input.password.len() < MIN_PASSWORD_LENGTH {
+        return error_response(
+            ErrorCode::InvalidRegistration,
+            format!(
+                "Password must be at least {} characters",
+                MIN_PASSWORD_LENGTH
+            ),
+        );
+    }
+
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+
+    match get_user_by_email(&pg_pool, &email).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return error_response(
+                ErrorCode::EmailTaken,
+                "Email already registered".to_string(),
+            )
+        }
+        Err(e) => {
+            println!("Failed to look up user - {}", e);
+            return actix_web::HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let password_hash = match hash_password(&input.password) {
+        Ok(password_hash) => password_hash,
+        Err(e) => {
+            println!("Failed to hash password - {}", e);
+            return actix_web::HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user = DbUser {
+        user_id: Uuid::new_v4().to_string(),
+        email,
+        password_hash,
+        created_at: chrono::Utc::now().timestamp_millis(),
+        totp_secret: None,
+        totp_enabled: false,
+    };
+
+    // The unique email constraint catches concurrent registrations
+    match insert_user(&pg_pool, &user).await {
+        Ok(()) => {}
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return error_response(
+                ErrorCode::EmailTaken,
+                "Email already registered".to_string(),
+            )
+        }
+        Err(e) => {
+            println!("Failed to store user - {}", e);
+            return actix_web::HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let published_data = match create_engine_user(app_state.bus.as_ref(), &user.user_id).await {
+        Ok(published_data) => published_data,
+        Err(e) => {
+            remove_unregistered_user(&pg_pool, &user.user_id).await;
+            return reply_error_response(&e);
+        }
+    };
+
+    if !matches!(
+        protocol::decode::<EngineReply<CreateUserResponse>>(&published_data),
+        Ok(Ok(_))
+    ) {
+        remove_unregistered_user(&pg_pool, &user.user_id).await;
+        return engine_response(&published_data);
+    }
+
+    println!("Time: {:?}", starttime.elapsed());
+    match issue_tokens(&app_state, &user.user_id).await {
+        Ok(tokens) => actix_web::HttpResponse::Ok().json(tokens),
+        Err(response) => response,
+    }
+}
+
+// Without an engine account the user could log in but never trade, so the email is freed
+// again and the client can simply retry the registration
+async fn remove_unregistered_user(pg_pool: &Pool<Postgres>, user_id: &str) {
+    if let Err(e) = delete_user(pg_pool, user_id).await {
+        println!(
+            "Failed to remove user {} after engine error - {}",
+            user_id, e
+        );
+    }
+}
+
+// Accounts with 2FA enabled also need X-2FA-CODE
+pub async fn login(
+    req: HttpRequest,
+    body: Json<CredentialsInput>,
+    app_state: Data<AppState>,
+) -> actix_web::HttpResponse {
+    let input = body.into_inner();
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+
+    let user = match get_user_by_email(&pg_pool, &normalize_email(&input.email)).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("Failed to look up user - {}", e);
+            return actix_web::HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    // Same error for unknown emails and wrong passwords
+    let user = match user {
+        Some(user) if verify_password(&input.password, &user.password_hash) => user,
+        _ => {
+            return error_response(
+                ErrorCode::InvalidCredentials,
+                "Invalid email or password".to_string(),
+            )
+        }
+    };
+
+    if let Err(response) =
+        require_second_factor(&app_state, &req, &user.user_id, CodeUse::Credential).await
+    {
+        return response;
+    }
+
+    match issue_tokens(&app_state, &user.user_id).await {
+        Ok(tokens) => actix_web::HttpResponse::Ok().json(tokens),
+        Err(response) => response,
+    }
+}
+
+// Refresh tokens are rotated - the one presented here can not be used again
+pub async fn refresh(
+    body: Json<RefreshTokenInput>,
+    app_state: Data<AppState>,
+) -> actix_web::HttpResponse {
+    let input = body.into_inner();
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+
+    let token_hash = sha256_hex(&input.refresh_token);
+    let stored_token = match revoke_refresh_token(&pg_pool, &token_hash).await {
+        Ok(stored_token) => stored_token,
+        Err(e) => {
+            println!("Failed to redeem refresh token - {}", e);
+            return actix_web::HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match stored_token {
+        Some(token) if token.expires_at > chrono::Utc::now().timestamp() => token.user_id,
+        _ => {
+            return error_response(
+                ErrorCode::Unauthorized,
+                "Invalid or expired refresh token".to_string(),
+            )
+        }
+    };
+
+    match issue_tokens(&app_state, &user_id).await {
+        Ok(tokens) => actix_web::HttpResponse::Ok().json(tokens),
+        Err(response) => response,
+    }
+}
+
+// Revokes every refresh token of the user and the access token used for this request
+pub async fn logout(
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+
+    if let Err(e) = revoke_user_refresh_tokens(&pg_pool, &user.user_id).await {
+        println!("Failed to revoke refresh tokens - {}", e);
+        return actix_web::HttpResponse::InternalServerError().finish();
+    }
+
+    if let Some(session) = &user.session {
+        let remaining = session.exp - chrono::Utc::now().timestamp();
+
+        if remaining > 0 {
+            let result = app_state
+                .redis_connection
+                .set_with_expiry(
+                    &revoked_token_key(&session.jti),
+                    user.user_id.clone(),
+                    remaining,
+                )
+                .await;
+
+            if let Err(e) = result {
+                println!("Failed to revoke access token - {}", e);
+                return actix_web::HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+
+    actix_web::HttpResponse::Ok().json(serde_json::json!({}))
+}
+
+// Also revokes every refresh token, so other sessions have to log in with the new password
+pub async fn change_password(
+    req: HttpRequest,
+    body: Json<ChangePasswordInput>,
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let input = body.into_inner();
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+
+    let db_user = match get_user_by_id(&pg_pool, &user.user_id).await {
+        Ok(db_user) => db_user,
+        Err(e) => {
+            println!("Failed to look up user - {}", e);
+            return actix_web::HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match db_user {
+        Some(db_user) if verify_password(&input.current_password, &db_user.password_hash) => {}
+        _ => {
+            return error_response(
+                ErrorCode::InvalidCredentials,
+                "Invalid current password".to_string(),
+            )
+        }
+    }
+
+    if input.new_password.len() < MIN_PASSWORD_LENGTH {
+        return error_response(
+            ErrorCode::InvalidRegistration,
+            format!(
+                "Password must be at least {} characters",
+                MIN_PASSWORD_LENGTH
+            ),
+        );
+    }
+
+    if let Err(response) =
+        require_second_factor(&app_state, &req, &user.user_id, CodeUse::Credential).await
+    {
+        return response;
+    }
+
+    let password_hash = match hash_password(&input.new_password) {
+        Ok(password_hash) => password_hash,
+        Err(e) => {
+            println!("Failed to hash password - {}", e);
+            return actix_web::HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    if let Err(e) = update_password_hash(&pg_pool, &user.user_id, &password_hash).await {
+        println!("Failed to update password - {}", e);
+        return actix_web::HttpResponse::InternalServerError().finish();
+    }
+
+    if let Err(e) = revoke_user_refresh_tokens(&pg_pool, &user.user_id).await {
+        println!("Failed to revoke refresh tokens - {}", e);
+        return actix_web::HttpResponse::InternalServerError().finish();
+    }
+
+    actix_web::HttpResponse::Ok().json(serde_json::json!({}))
+}
@@ -1,18 +1,16 @@
 use actix_web::web::Data;
 
-use serde_json::to_string;
 use std::time::Instant;
 use uuid::Uuid;
 
-use crate::types::{
-    app::AppState,
-    routes::{GetDepthInput, OrderRequests},
-};
+use crate::routes::{engine_response, reply_error_response};
+use crate::types::app::AppState;
+use protocol::orders::{GetDepth, GetL3Depth, OrderRequests};
 
 use redis::RedisQueues;
 
 pub async fn get_depth(
-    query: actix_web::web::Query<GetDepthInput>,
+    query: actix_web::web::Query<GetDepth>,
     app_state: Data<AppState>,
 ) -> actix_web::HttpResponse {
     let starttime = Instant::now();
@@ -21,31 +19,24 @@ pub async fn get_depth(
     market_data.pubsub_id = pubsub_id;
 
     let get_depth_request = OrderRequests::GetDepth(market_data);
-    let get_depth_data = to_string(&get_depth_request).unwrap();
+    let get_depth_data = protocol::encode(&get_depth_request);
     println!("Get Depth: {}", get_depth_data);
 
-    let redis_connection = &app_state.redis_connection;
+    let bus = app_state.bus.as_ref();
     if let Some(pubsub_id_value) = pubsub_id {
-        let result = redis_connection
-            .push_and_wait_for_subscriber(
-                RedisQueues::ORDERS.to_string(),
-                get_depth_data,
-                pubsub_id_value,
-            )
+        let result = bus
+            .push_and_wait_for_subscriber(RedisQueues::ORDERS, get_depth_data, pubsub_id_value)
             .await;
 
         match result {
             Ok(published_data) => {
-                let published_data_json: serde_json::Value =
-                    serde_json::from_str(&published_data).unwrap();
-
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::Ok().json(published_data_json);
+                return engine_response(&published_data);
             }
             Err(e) => {
                 println!("Failed to get depth from redis - {}", e);
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::InternalServerError().finish();
+                return reply_error_response(&e);
             }
         }
     }
@@ -53,3 +44,32 @@ pub async fn get_depth(
     println!("Timeout: {:?}", starttime.elapsed());
     actix_web::HttpResponse::Ok().finish()
 }
+
+// Every resting order instead of aggregated levels - for analytics, so it is authenticated and weighs more
+pub async fn get_l3_depth(
+    query: actix_web::web::Query<GetL3Depth>,
+    app_state: Data<AppState>,
+) -> actix_web::HttpResponse {
+    let starttime = Instant::now();
+    let mut market_data = query.into_inner();
+    let pubsub_id = Uuid::new_v4();
+    market_data.pubsub_id = Some(pubsub_id);
+
+    let get_l3_depth_request = OrderRequests::GetL3Depth(market_data);
+    let get_l3_depth_data = protocol::encode(&get_l3_depth_request);
+    println!("Get L3 Depth: {}", get_l3_depth_data);
+
+    let result = app_state
+        .bus
+        .push_and_wait_for_subscriber(RedisQueues::ORDERS, get_l3_depth_data, pubsub_id)
+        .await;
+    println!("Time: {:?}", starttime.elapsed());
+
+    match result {
+        Ok(published_data) => engine_response(&published_data),
+        Err(e) => {
+            println!("Failed to get L3 depth from redis - {}", e);
+            reply_error_response(&e)
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use actix_web::web::Data;
 use db_processor::query::get_klines_timeseries_data;
+use protocol::errors::ErrorCode;
+use protocol::klines::{KlineInterval, DEFAULT_KLINE_LIMIT, MAX_KLINE_LIMIT};
 
 use std::time::Instant;
 
+use crate::routes::error_response;
 use crate::types::{app::AppState, routes::GetKlinesInput};
 
 pub async fn get_klines(
@@ -10,16 +13,11 @@ pub async fn get_klines(
     app_state: Data<AppState>,
 ) -> actix_web::HttpResponse {
     let starttime = Instant::now();
-    let mut klines_input = query.into_inner();
-
-    klines_input.interval = match klines_input.interval.as_str() {
-        "1y" | "1Y" => "year".to_string(),
-        "1m" | "1M" => "month".to_string(),
-        "1w" | "1W" => "week".to_string(),
-        "1d" | "1D" => "day".to_string(),
-        "1h" | "1H" => "hour".to_string(),
-        "1min" | "1MIN" => "minute".to_string(),
-        _ => "week".to_string(),
+    let klines_input = query.into_inner();
+
+    let (interval, limit) = match validate_klines_input(&klines_input) {
+        Ok(valid) => valid,
+        Err(msg) => return error_response(ErrorCode::InvalidKlines, msg),
     };
 
     println!("Get Klines: {}", klines_input.symbol);
@@ -29,8 +27,11 @@ pub async fn get_klines(
     let klines = get_klines_timeseries_data(
         &pg_pool,
         klines_input.symbol,
-        klines_input.interval,
+        interval,
         klines_input.start_time,
+        klines_input.end_time,
+        limit,
+        klines_input.fill_gaps.unwrap_or(false),
     )
     .await
     .unwrap();
@@ -39,3 +40,71 @@ pub async fn get_klines(
 
     actix_web::HttpResponse::Ok().json(klines)
 }
+
+// Unknown intervals are rejected rather than mapped onto a default
+fn validate_klines_input(input: &GetKlinesInput) -> Result<(KlineInterval, usize), String> {
+    let interval = KlineInterval::parse(&input.interval).ok_or_else(|| {
+        let codes: Vec<&str> = KlineInterval::ALL.iter().map(|i| i.code()).collect();
+        format!(
+            "Interval {} is not supported, it must be one of {}",
+            input.interval,
+            codes.join(", ")
+        )
+    })?;
+
+    let limit = input.limit.unwrap_or(DEFAULT_KLINE_LIMIT);
+    if limit == 0 || limit > MAX_KLINE_LIMIT {
+        return Err(format!(
+            "Limit {} is not supported, it must be between 1 and {}",
+            limit, MAX_KLINE_LIMIT
+        ));
+    }
+
+    if let (Some(start_time), Some(end_time)) = (input.start_time, input.end_time) {
+        if start_time > end_time {
+            return Err("startTime must not be after endTime".to_string());
+        }
+    }
+
+    Ok((interval, limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(interval: &str, limit: Option<usize>) -> GetKlinesInput {
+        GetKlinesInput {
+            symbol: "SOL_USDC".to_string(),
+            interval: interval.to_string(),
+            start_time: None,
+            end_time: None,
+            limit,
+            fill_gaps: None,
+        }
+    }
+
+    #[test]
+    fn validates_intervals_and_limits() {
+        assert_eq!(
+            validate_klines_input(&input("15m", None)),
+            Ok((KlineInterval::Minute15, DEFAULT_KLINE_LIMIT))
+        );
+        assert_eq!(
+            validate_klines_input(&input("1M", Some(12))),
+            Ok((KlineInterval::Month1, 12))
+        );
+
+        // No more guessing - "1min" and "1y" used to be accepted, anything else became a week
+        for interval in ["1min", "1y", "1W", "2d", ""] {
+            assert!(validate_klines_input(&input(interval, None)).is_err());
+        }
+        assert!(validate_klines_input(&input("1h", Some(0))).is_err());
+        assert!(validate_klines_input(&input("1h", Some(MAX_KLINE_LIMIT + 1))).is_err());
+
+        let mut backwards = input("1h", None);
+        backwards.start_time = Some(2_000);
+        backwards.end_time = Some(1_000);
+        assert!(validate_klines_input(&backwards).is_err());
+    }
+}
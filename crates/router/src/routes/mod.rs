@@ -1,6 +1,72 @@
-pub mod order;
-pub mod user;
+pub mod api_key;
+pub mod auth;
 pub mod depth;
-pub mod trade;
 pub mod klines;
-pub mod tickers;
\ No newline at end of file
+pub mod order;
+pub mod tickers;
+pub mod trade;
+pub mod two_factor;
+pub mod user;
+pub mod user_stream;
+
+use actix_web::http::StatusCode;
+use protocol::errors::{EngineReply, ErrorCode, ErrorResponse};
+use redis::BusError;
+
+// Replies from the engine are versioned envelopes - only the payload is returned to the client
+pub fn engine_response(published_data: &str) -> actix_web::HttpResponse {
+    match protocol::decode::<EngineReply<serde_json::Value>>(published_data) {
+        Ok(Ok(published_data_json)) => actix_web::HttpResponse::Ok().json(published_data_json),
+        Ok(Err(error)) => actix_web::HttpResponse::build(error_status(error.code)).json(error),
+        Err(e) => {
+            println!("Invalid reply from engine - {}", e);
+            actix_web::HttpResponse::InternalServerError().json(ErrorResponse {
+                code: ErrorCode::ProtocolMismatch,
+                msg: e.to_string(),
+            })
+        }
+    }
+}
+
+// The engine did not answer in time - the request may still be processed
+pub fn reply_error_response(error: &BusError) -> actix_web::HttpResponse {
+    match error {
+        BusError::Timeout(_) => error_response(ErrorCode::EngineTimeout, error.to_string()),
+        _ => actix_web::HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub fn error_response(code: ErrorCode, msg: String) -> actix_web::HttpResponse {
+    actix_web::HttpResponse::build(error_status(code)).json(ErrorResponse { code, msg })
+}
+
+pub fn error_status(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::UnknownMarket
+        | ErrorCode::UnknownAsset
+        | ErrorCode::MarketDisabled
+        | ErrorCode::AssetDisabled
+        | ErrorCode::InvalidPrecision
+        | ErrorCode::InvalidQuantity
+        | ErrorCode::InvalidPrice
+        | ErrorCode::InvalidBatch
+        | ErrorCode::BatchRejected
+        | ErrorCode::InvalidCountdown
+        | ErrorCode::InvalidDepth
+        | ErrorCode::InvalidKlines
+        | ErrorCode::InsufficientFunds
+        | ErrorCode::InvalidRegistration
+        | ErrorCode::TwoFactorNotEnrolled => StatusCode::BAD_REQUEST,
+        ErrorCode::UnknownUser | ErrorCode::UnknownOrder => StatusCode::NOT_FOUND,
+        ErrorCode::Unauthorized
+        | ErrorCode::InvalidSignature
+        | ErrorCode::InvalidTimestamp
+        | ErrorCode::InvalidCredentials
+        | ErrorCode::TwoFactorRequired
+        | ErrorCode::InvalidTwoFactorCode => StatusCode::UNAUTHORIZED,
+        ErrorCode::EmailTaken | ErrorCode::TwoFactorAlreadyEnabled => StatusCode::CONFLICT,
+        ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::Internal | ErrorCode::ProtocolMismatch => StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorCode::EngineTimeout => StatusCode::GATEWAY_TIMEOUT,
+    }
+}
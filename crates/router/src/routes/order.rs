@@ -1,54 +1,51 @@
-use actix_web::web::{Data, Json};
+use actix_web::web::{Data, Json, ReqData};
+use actix_web::HttpRequest;
 
-use serde_json::to_string;
 use std::time::Instant;
 use uuid::Uuid;
 
-use crate::types::{
-    app::AppState,
-    routes::{
-        CancelAllOrdersInput, CancelOrderInput, CreateOrderInput, GetOpenOrderInput, GetOpenOrdersInput, OrderRequests
-    },
+use crate::auth::totp::{require_second_factor, CodeUse};
+use crate::auth::AuthenticatedUser;
+use crate::routes::{engine_response, reply_error_response};
+use crate::types::app::AppState;
+use protocol::orders::{
+    BatchCancelOrders, BatchCreateOrders, CancelAllOrders, CancelOrder, CountdownCancelAll,
+    CountdownSource, CreateOrder, GetOpenOrder, GetOpenOrders, OrderRequests,
 };
 
 use redis::RedisQueues;
 
 pub async fn execute_order(
-    body: Json<CreateOrderInput>,
+    body: Json<CreateOrder>,
     app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
 ) -> actix_web::HttpResponse {
     let starttime = Instant::now();
     let mut order = body.into_inner();
+    order.user_id = user.user_id.clone(); // never trust a client supplied user_id
     let pubsub_id = Some(Uuid::new_v4());
     order.pubsub_id = pubsub_id;
 
     let create_order_request = OrderRequests::CreateOrder(order);
-    let create_order_data = to_string(&create_order_request).unwrap();
+    let create_order_data = protocol::encode(&create_order_request);
     println!("Create Order: {}", create_order_data);
 
-    let redis_connection = &app_state.redis_connection;
+    let bus = app_state.bus.as_ref();
 
     if let Some(pubsub_id_value) = pubsub_id {
-        let result = redis_connection
-            .push_and_wait_for_subscriber(
-                RedisQueues::ORDERS.to_string(),
-                create_order_data,
-                pubsub_id_value,
-            )
+        let result = bus
+            .push_and_wait_for_subscriber(RedisQueues::ORDERS, create_order_data, pubsub_id_value)
             .await;
 
         match result {
             Ok(published_data) => {
-                let published_data_json: serde_json::Value =
-                    serde_json::from_str(&published_data).unwrap();
-
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::Ok().json(published_data_json);
+                return engine_response(&published_data);
             }
             Err(e) => {
                 println!("Failed to get created order from redis - {}", e);
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::InternalServerError().finish();
+                return reply_error_response(&e);
             }
         }
     }
@@ -58,40 +55,35 @@ pub async fn execute_order(
 }
 
 pub async fn get_open_order(
-    body: Json<GetOpenOrderInput>,
+    body: Json<GetOpenOrder>,
     app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
 ) -> actix_web::HttpResponse {
     let starttime = Instant::now();
     let mut order = body.into_inner();
+    order.user_id = user.user_id.clone(); // never trust a client supplied user_id
     let pubsub_id = Some(Uuid::new_v4());
     order.pubsub_id = pubsub_id;
 
     let get_open_order_request = OrderRequests::GetOpenOrder(order);
-    let get_open_order_data = to_string(&get_open_order_request).unwrap();
+    let get_open_order_data = protocol::encode(&get_open_order_request);
     println!("Get Open Order: {}", get_open_order_data);
 
-    let redis_connection = &app_state.redis_connection;
+    let bus = app_state.bus.as_ref();
     if let Some(pubsub_id_value) = pubsub_id {
-        let result = redis_connection
-            .push_and_wait_for_subscriber(
-                RedisQueues::ORDERS.to_string(),
-                get_open_order_data,
-                pubsub_id_value,
-            )
+        let result = bus
+            .push_and_wait_for_subscriber(RedisQueues::ORDERS, get_open_order_data, pubsub_id_value)
             .await;
 
         match result {
             Ok(published_data) => {
-                let published_data_json: serde_json::Value =
-                    serde_json::from_str(&published_data).unwrap();
-
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::Ok().json(published_data_json);
+                return engine_response(&published_data);
             }
             Err(e) => {
                 println!("Failed to get open orders from redis - {}", e);
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::InternalServerError().finish();
+                return reply_error_response(&e);
             }
         }
     }
@@ -101,41 +93,44 @@ pub async fn get_open_order(
     actix_web::HttpResponse::Ok().finish()
 }
 
+// Every cancel endpoint needs the second factor, so splitting a cancel up doesn't get around it
 pub async fn cancel_order(
-    body: Json<CancelOrderInput>,
+    req: HttpRequest,
+    body: Json<CancelOrder>,
     app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
 ) -> actix_web::HttpResponse {
     let starttime = Instant::now();
+    if let Err(response) =
+        require_second_factor(&app_state, &req, &user.user_id, CodeUse::Action).await
+    {
+        return response;
+    }
+
     let mut order = body.into_inner();
+    order.user_id = user.user_id.clone(); // never trust a client supplied user_id
     let pubsub_id = Some(Uuid::new_v4());
     order.pubsub_id = pubsub_id;
 
     let cancel_order_request = OrderRequests::CancelOrder(order);
-    let cancel_order_data = to_string(&cancel_order_request).unwrap();
+    let cancel_order_data = protocol::encode(&cancel_order_request);
     println!("Cancel Order: {}", cancel_order_data);
 
-    let redis_connection = &app_state.redis_connection;
+    let bus = app_state.bus.as_ref();
     if let Some(pubsub_id_value) = pubsub_id {
-        let result = redis_connection
-            .push_and_wait_for_subscriber(
-                RedisQueues::ORDERS.to_string(),
-                cancel_order_data,
-                pubsub_id_value,
-            )
+        let result = bus
+            .push_and_wait_for_subscriber(RedisQueues::ORDERS, cancel_order_data, pubsub_id_value)
             .await;
 
         match result {
             Ok(published_data) => {
-                let published_data_json: serde_json::Value =
-                    serde_json::from_str(&published_data).unwrap();
-
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::Ok().json(published_data_json);
+                return engine_response(&published_data);
             }
             Err(e) => {
                 println!("Failed to get cancelled order from redis - {}", e);
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::InternalServerError().finish();
+                return reply_error_response(&e);
             }
         }
     }
@@ -145,23 +140,25 @@ pub async fn cancel_order(
 }
 
 pub async fn get_open_orders(
-    body: Json<GetOpenOrdersInput>,
+    body: Json<GetOpenOrders>,
     app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
 ) -> actix_web::HttpResponse {
     let starttime = Instant::now();
     let mut order = body.into_inner();
+    order.user_id = user.user_id.clone(); // never trust a client supplied user_id
     let pubsub_id = Some(Uuid::new_v4());
     order.pubsub_id = pubsub_id;
 
     let get_open_orders_request = OrderRequests::GetOpenOrders(order);
-    let get_open_orders_data = to_string(&get_open_orders_request).unwrap();
+    let get_open_orders_data = protocol::encode(&get_open_orders_request);
     println!("Get Open Orders: {}", get_open_orders_data);
 
-    let redis_connection = &app_state.redis_connection;
+    let bus = app_state.bus.as_ref();
     if let Some(pubsub_id_value) = pubsub_id {
-        let result = redis_connection
+        let result = bus
             .push_and_wait_for_subscriber(
-                RedisQueues::ORDERS.to_string(),
+                RedisQueues::ORDERS,
                 get_open_orders_data,
                 pubsub_id_value,
             )
@@ -169,16 +166,13 @@ pub async fn get_open_orders(
 
         match result {
             Ok(published_data) => {
-                let published_data_json: serde_json::Value =
-                    serde_json::from_str(&published_data).unwrap();
-
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::Ok().json(published_data_json);
+                return engine_response(&published_data);
             }
             Err(e) => {
                 println!("Failed to get open orders from redis - {}", e);
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::InternalServerError().finish();
+                return reply_error_response(&e);
             }
         }
     }
@@ -189,23 +183,32 @@ pub async fn get_open_orders(
 }
 
 pub async fn cancel_all_orders(
-    body: Json<CancelAllOrdersInput>,
+    req: HttpRequest,
+    body: Json<CancelAllOrders>,
     app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
 ) -> actix_web::HttpResponse {
     let starttime = Instant::now();
+    if let Err(response) =
+        require_second_factor(&app_state, &req, &user.user_id, CodeUse::Action).await
+    {
+        return response;
+    }
+
     let mut order = body.into_inner();
+    order.user_id = user.user_id.clone(); // never trust a client supplied user_id
     let pubsub_id = Some(Uuid::new_v4());
     order.pubsub_id = pubsub_id;
 
     let cancel_all_orders_request = OrderRequests::CancelAllOrders(order);
-    let cancel_all_orders_data = to_string(&cancel_all_orders_request).unwrap();
+    let cancel_all_orders_data = protocol::encode(&cancel_all_orders_request);
     println!("Cancel All Orders: {}", cancel_all_orders_data);
 
-    let redis_connection = &app_state.redis_connection;
+    let bus = app_state.bus.as_ref();
     if let Some(pubsub_id_value) = pubsub_id {
-        let result = redis_connection
+        let result = bus
             .push_and_wait_for_subscriber(
-                RedisQueues::ORDERS.to_string(),
+                RedisQueues::ORDERS,
                 cancel_all_orders_data,
                 pubsub_id_value,
             )
@@ -213,16 +216,13 @@ pub async fn cancel_all_orders(
 
         match result {
             Ok(published_data) => {
-                let published_data_json: serde_json::Value =
-                    serde_json::from_str(&published_data).unwrap();
-
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::Ok().json(published_data_json);
+                return engine_response(&published_data);
             }
             Err(e) => {
                 println!("Failed to get all cancelled orders from redis - {}", e);
                 println!("Time: {:?}", starttime.elapsed());
-                return actix_web::HttpResponse::InternalServerError().finish();
+                return reply_error_response(&e);
             }
         }
     }
@@ -230,3 +230,110 @@ pub async fn cancel_all_orders(
     println!("Timeout: {:?}", starttime.elapsed());
     actix_web::HttpResponse::Ok().finish()
 }
+
+// Places up to MAX_BATCH_ORDERS orders with one engine round trip, replying with a result per order
+pub async fn execute_batch_orders(
+    body: Json<BatchCreateOrders>,
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let starttime = Instant::now();
+    let mut batch = body.into_inner();
+    batch.user_id = user.user_id.clone(); // never trust a client supplied user_id
+    let pubsub_id = Uuid::new_v4();
+    batch.pubsub_id = Some(pubsub_id);
+
+    let batch_create_request = OrderRequests::BatchCreate(batch);
+    let batch_create_data = protocol::encode(&batch_create_request);
+    println!("Batch Create Orders: {}", batch_create_data);
+
+    let result = app_state
+        .bus
+        .push_and_wait_for_subscriber(RedisQueues::ORDERS, batch_create_data, pubsub_id)
+        .await;
+    println!("Time: {:?}", starttime.elapsed());
+
+    match result {
+        Ok(published_data) => engine_response(&published_data),
+        Err(e) => {
+            println!("Failed to get batch orders from redis - {}", e);
+            reply_error_response(&e)
+        }
+    }
+}
+
+pub async fn cancel_batch_orders(
+    req: HttpRequest,
+    body: Json<BatchCancelOrders>,
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let starttime = Instant::now();
+    if let Err(response) =
+        require_second_factor(&app_state, &req, &user.user_id, CodeUse::Action).await
+    {
+        return response;
+    }
+
+    let mut batch = body.into_inner();
+    batch.user_id = user.user_id.clone(); // never trust a client supplied user_id
+    let pubsub_id = Uuid::new_v4();
+    batch.pubsub_id = Some(pubsub_id);
+
+    let batch_cancel_request = OrderRequests::BatchCancel(batch);
+    let batch_cancel_data = protocol::encode(&batch_cancel_request);
+    println!("Batch Cancel Orders: {}", batch_cancel_data);
+
+    let result = app_state
+        .bus
+        .push_and_wait_for_subscriber(RedisQueues::ORDERS, batch_cancel_data, pubsub_id)
+        .await;
+    println!("Time: {:?}", starttime.elapsed());
+
+    match result {
+        Ok(published_data) => engine_response(&published_data),
+        Err(e) => {
+            println!("Failed to get batch cancelled orders from redis - {}", e);
+            reply_error_response(&e)
+        }
+    }
+}
+
+// Dead man's switch - clients refresh the countdown, if it runs out all their orders are cancelled
+pub async fn countdown_cancel_all(
+    req: HttpRequest,
+    body: Json<CountdownCancelAll>,
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let starttime = Instant::now();
+    if let Err(response) =
+        require_second_factor(&app_state, &req, &user.user_id, CodeUse::Action).await
+    {
+        return response;
+    }
+
+    let mut countdown = body.into_inner();
+    countdown.user_id = user.user_id.clone(); // never trust a client supplied user_id
+    countdown.source = CountdownSource::Api;
+    let pubsub_id = Uuid::new_v4();
+    countdown.pubsub_id = Some(pubsub_id);
+
+    let countdown_request = OrderRequests::CountdownCancelAll(countdown);
+    let countdown_data = protocol::encode(&countdown_request);
+    println!("Countdown Cancel All: {}", countdown_data);
+
+    let result = app_state
+        .bus
+        .push_and_wait_for_subscriber(RedisQueues::ORDERS, countdown_data, pubsub_id)
+        .await;
+    println!("Time: {:?}", starttime.elapsed());
+
+    match result {
+        Ok(published_data) => engine_response(&published_data),
+        Err(e) => {
+            println!("Failed to set countdown cancel from redis - {}", e);
+            reply_error_response(&e)
+        }
+    }
+}
@@ -1,9 +1,13 @@
+use crate::routes::{engine_response, reply_error_response};
 use crate::types::app::AppState;
 use actix_web::web::Data;
 
 use db_processor::query::get_tickers_from_db;
+use protocol::orders::{GetBookTicker, OrderRequests};
+use redis::RedisQueues;
 
 use std::time::Instant;
+use uuid::Uuid;
 
 pub async fn get_tickers(app_state: Data<AppState>) -> actix_web::HttpResponse {
     let starttime = Instant::now();
@@ -18,3 +22,32 @@ pub async fn get_tickers(app_state: Data<AppState>) -> actix_web::HttpResponse {
 
     actix_web::HttpResponse::Ok().json(tickers)
 }
+
+// Best bid and ask straight from the engine's book
+pub async fn get_book_ticker(
+    query: actix_web::web::Query<GetBookTicker>,
+    app_state: Data<AppState>,
+) -> actix_web::HttpResponse {
+    let starttime = Instant::now();
+    let mut market_data = query.into_inner();
+    let pubsub_id = Uuid::new_v4();
+    market_data.pubsub_id = Some(pubsub_id);
+
+    let get_book_ticker_request = OrderRequests::GetBookTicker(market_data);
+    let get_book_ticker_data = protocol::encode(&get_book_ticker_request);
+    println!("Get Book Ticker: {}", get_book_ticker_data);
+
+    let result = app_state
+        .bus
+        .push_and_wait_for_subscriber(RedisQueues::ORDERS, get_book_ticker_data, pubsub_id)
+        .await;
+    println!("Time: {:?}", starttime.elapsed());
+
+    match result {
+        Ok(published_data) => engine_response(&published_data),
+        Err(e) => {
+            println!("Failed to get book ticker from redis - {}", e);
+            reply_error_response(&e)
+        }
+    }
+}
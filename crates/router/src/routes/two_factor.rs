@@ -0,0 +1,170 @@
+use actix_web::web::{Data, Json, ReqData};
+use actix_web::HttpRequest;
+use db_processor::query::{get_user_by_id, replace_recovery_codes, update_totp};
+use db_processor::types::DbUser;
+use protocol::errors::ErrorCode;
+
+use crate::auth::totp::{
+    generate_recovery_codes, generate_secret, hash_recovery_code, provisioning_uri,
+    require_second_factor, use_code_step, verify_code, CodeUse,
+};
+use crate::auth::AuthenticatedUser;
+use crate::routes::error_response;
+use crate::types::app::AppState;
+use crate::types::routes::{RecoveryCodesResponse, TwoFactorCodeInput, TwoFactorEnrollResponse};
+
+// 2FA is only available to accounts registered with an email and password
+async fn registered_user(
+    app_state: &AppState,
+    user_id: &str,
+) -> Result<DbUser, actix_web::HttpResponse> {
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+
+    match get_user_by_id(&pg_pool, user_id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(error_response(
+            ErrorCode::TwoFactorNotEnrolled,
+            "2FA requires a registered account".to_string(),
+        )),
+        Err(e) => {
+            println!("Failed to look up user - {}", e);
+            Err(actix_web::HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+// Stores a new, not yet enabled secret - enrolling again before confirming replaces it
+pub async fn enroll(
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let db_user = match registered_user(&app_state, &user.user_id).await {
+        Ok(db_user) => db_user,
+        Err(response) => return response,
+    };
+
+    if db_user.totp_enabled {
+        return error_response(
+            ErrorCode::TwoFactorAlreadyEnabled,
+            "2FA is already enabled".to_string(),
+        );
+    }
+
+    let secret = generate_secret();
+    let provisioning_uri = match provisioning_uri(&secret, &db_user.email) {
+        Some(provisioning_uri) => provisioning_uri,
+        None => return actix_web::HttpResponse::InternalServerError().finish(),
+    };
+
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+    if let Err(e) = update_totp(&pg_pool, &user.user_id, Some(&secret), false).await {
+        println!("Failed to store 2FA secret - {}", e);
+        return actix_web::HttpResponse::InternalServerError().finish();
+    }
+
+    actix_web::HttpResponse::Ok().json(TwoFactorEnrollResponse {
+        secret,
+        provisioning_uri,
+    })
+}
+
+// Enables 2FA once the user proves their authenticator app has the secret
+pub async fn confirm(
+    body: Json<TwoFactorCodeInput>,
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let db_user = match registered_user(&app_state, &user.user_id).await {
+        Ok(db_user) => db_user,
+        Err(response) => return response,
+    };
+
+    if db_user.totp_enabled {
+        return error_response(
+            ErrorCode::TwoFactorAlreadyEnabled,
+            "2FA is already enabled".to_string(),
+        );
+    }
+
+    let secret = match db_user.totp_secret {
+        Some(secret) => secret,
+        None => {
+            return error_response(
+                ErrorCode::TwoFactorNotEnrolled,
+                "Enroll before confirming 2FA".to_string(),
+            )
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    let step = match verify_code(&secret, &body.code, now) {
+        Some(step) => step,
+        None => {
+            return error_response(
+                ErrorCode::InvalidTwoFactorCode,
+                "Invalid 2FA code".to_string(),
+            )
+        }
+    };
+
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+    if let Err(response) = use_code_step(&pg_pool, &user.user_id, step, CodeUse::Credential).await {
+        return response;
+    }
+
+    let recovery_codes = generate_recovery_codes();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect();
+
+    if let Err(e) = replace_recovery_codes(&pg_pool, &user.user_id, &code_hashes).await {
+        println!("Failed to store recovery codes - {}", e);
+        return actix_web::HttpResponse::InternalServerError().finish();
+    }
+
+    if let Err(e) = update_totp(&pg_pool, &user.user_id, Some(&secret), true).await {
+        println!("Failed to enable 2FA - {}", e);
+        return actix_web::HttpResponse::InternalServerError().finish();
+    }
+
+    actix_web::HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes })
+}
+
+// Requires a current code (or a recovery code) in X-2FA-CODE
+pub async fn disable(
+    req: HttpRequest,
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let db_user = match registered_user(&app_state, &user.user_id).await {
+        Ok(db_user) => db_user,
+        Err(response) => return response,
+    };
+
+    if !db_user.totp_enabled {
+        return error_response(
+            ErrorCode::TwoFactorNotEnrolled,
+            "2FA is not enabled".to_string(),
+        );
+    }
+
+    if let Err(response) =
+        require_second_factor(&app_state, &req, &user.user_id, CodeUse::Credential).await
+    {
+        return response;
+    }
+
+    let pg_pool = app_state.postgres_db.get_pg_connection().unwrap();
+    if let Err(e) = update_totp(&pg_pool, &user.user_id, None, false).await {
+        println!("Failed to disable 2FA - {}", e);
+        return actix_web::HttpResponse::InternalServerError().finish();
+    }
+
+    if let Err(e) = replace_recovery_codes(&pg_pool, &user.user_id, &[]).await {
+        println!("Failed to remove recovery codes - {}", e);
+        return actix_web::HttpResponse::InternalServerError().finish();
+    }
+
+    actix_web::HttpResponse::Ok().json(serde_json::json!({}))
+}
@@ -0,0 +1,134 @@
+use actix_web::http::StatusCode;
+use actix_web::web::{Data, Query, ReqData};
+use protocol::ws_stream::{listen_key, LISTEN_KEY_TTL_SECONDS};
+use std::time::Instant;
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::types::{
+    app::AppState,
+    routes::{ListenKeyInput, ListenKeyResponse},
+};
+
+// Hands out a listen key for the user's private stream - ws-stream resolves it on SUBSCRIBE ["user.<listenKey>"]
+pub async fn create_listen_key(
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let starttime = Instant::now();
+
+    let key = Uuid::new_v4().simple().to_string();
+
+    let result = app_state
+        .redis_connection
+        .set_with_expiry(
+            &listen_key(&key),
+            user.user_id.clone(),
+            LISTEN_KEY_TTL_SECONDS,
+        )
+        .await;
+
+    println!("Time: {:?}", starttime.elapsed());
+
+    match result {
+        Ok(_) => actix_web::HttpResponse::Ok().json(ListenKeyResponse { listen_key: key }),
+        Err(e) => {
+            println!("Failed to store listen key - {}", e);
+            actix_web::HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Listen keys expire after an hour unless they are kept alive
+pub async fn keep_alive_listen_key(
+    query: Query<ListenKeyInput>,
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let input = query.into_inner();
+
+    if let Err(response) = authorize_listen_key(&app_state, &input.listen_key, &user.user_id).await
+    {
+        return response;
+    }
+
+    let result = app_state
+        .redis_connection
+        .expire(&listen_key(&input.listen_key), LISTEN_KEY_TTL_SECONDS)
+        .await;
+
+    match result {
+        Ok(true) => actix_web::HttpResponse::Ok().json(serde_json::json!({})),
+        Ok(false) => actix_web::HttpResponse::NotFound().finish(),
+        Err(e) => {
+            println!("Failed to keep listen key alive - {}", e);
+            actix_web::HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn close_listen_key(
+    query: Query<ListenKeyInput>,
+    app_state: Data<AppState>,
+    user: ReqData<AuthenticatedUser>,
+) -> actix_web::HttpResponse {
+    let input = query.into_inner();
+
+    if let Err(response) = authorize_listen_key(&app_state, &input.listen_key, &user.user_id).await
+    {
+        return response;
+    }
+
+    let result = app_state
+        .redis_connection
+        .del(&listen_key(&input.listen_key))
+        .await;
+
+    match result {
+        Ok(_) => actix_web::HttpResponse::Ok().json(serde_json::json!({})),
+        Err(e) => {
+            println!("Failed to close listen key - {}", e);
+            actix_web::HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+async fn authorize_listen_key(
+    app_state: &AppState,
+    key: &str,
+    user_id: &str,
+) -> Result<(), actix_web::HttpResponse> {
+    match app_state.redis_connection.get(&listen_key(key)).await {
+        Ok(owner) => check_key_owner(owner.as_deref(), user_id)
+            .map_err(|status| actix_web::HttpResponse::build(status).finish()),
+        Err(e) => {
+            println!("Failed to look up listen key - {}", e);
+            Err(actix_web::HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+// Only the user a listen key was handed out to may keep it alive or close it
+fn check_key_owner(owner: Option<&str>, user_id: &str) -> Result<(), StatusCode> {
+    match owner {
+        Some(owner) if owner == user_id => Ok(()),
+        Some(_) => Err(StatusCode::FORBIDDEN),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_owner_can_use_a_listen_key() {
+        assert_eq!(check_key_owner(Some("alice"), "alice"), Ok(()));
+        assert_eq!(
+            check_key_owner(Some("alice"), "bob"),
+            Err(StatusCode::FORBIDDEN)
+        );
+        // Unknown and expired keys look the same
+        assert_eq!(check_key_owner(None, "alice"), Err(StatusCode::NOT_FOUND));
+    }
+}
@@ -1,7 +1,12 @@
-use redis::RedisManager;
+use crate::auth::api_key::SecretCipher;
+use redis::{MessageBus, RedisManager};
 use sqlx_postgres::PostgresDb;
+use std::sync::Arc;
 
 pub struct AppState {
-    pub redis_connection: RedisManager,
+    pub redis_connection: Arc<RedisManager>, // listen keys, revoked tokens and rate limits
+    pub bus: Arc<dyn MessageBus>,            // requests to the engine
     pub postgres_db: PostgresDb,
+    pub jwt_secret: String,
+    pub secret_cipher: SecretCipher, // API key secrets
 }
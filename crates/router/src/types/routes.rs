@@ -1,99 +1,83 @@
-use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum OrderSide {
-    BUY,
-    SELL,
+pub struct GetTradesInput {
+    pub symbol: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CreateOrderInput {
-    market: String,
-    price: Decimal,
-    quantity: Decimal,
-    side: OrderSide,
-    user_id: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
+#[serde(rename_all = "camelCase")] // frontend uses camelCase, will be renamed to snake_case in the backend
+pub struct GetKlinesInput {
+    pub symbol: String,
+    pub interval: String,
+    // #[serde(rename = "startTime")]  // can also use only this line to rename the field
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    pub limit: Option<usize>,
+    // Buckets without trades come back as flat candles at the previous close
+    pub fill_gaps: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetOpenOrderInput {
-    user_id: String,
-    order_id: String,
-    market: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
+#[serde(rename_all = "camelCase")]
+pub struct ListenKeyInput {
+    pub listen_key: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CancelOrderInput {
-    order_id: String,
-    user_id: String,
-    price: Decimal,
-    side: OrderSide,
-    market: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
+#[serde(rename_all = "camelCase")]
+pub struct ListenKeyResponse {
+    pub listen_key: String,
 }
 
+// The secret key is only ever returned here
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetOpenOrdersInput {
-    user_id: String,
-    market: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
+pub struct ApiKeyResponse {
+    pub user_id: String,
+    pub api_key: String,
+    pub secret_key: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CancelAllOrdersInput {
-    user_id: String,
-    market: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
+pub struct CredentialsInput {
+    pub email: String,
+    pub password: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetDepthInput {
-    pub symbol: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
+pub struct RefreshTokenInput {
+    pub refresh_token: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetTradesInput {
-    pub symbol: String,
+pub struct TokenResponse {
+    pub user_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")] // frontend uses camelCase, will be renamed to snake_case in the backend
-pub struct GetKlinesInput {
-    pub symbol: String,
-    pub interval: String,
-    // #[serde(rename = "startTime")]  // can also use only this line to rename the field
-    pub start_time: String,
+pub struct ChangePasswordInput {
+    pub current_password: String,
+    pub new_password: String,
 }
 
+// The secret is only usable once confirmed with a code from the authenticator app
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum OrderRequests {
-    CreateOrder(CreateOrderInput),
-    GetOpenOrder(GetOpenOrderInput),
-    CancelOrder(CancelOrderInput),
-    GetOpenOrders(GetOpenOrdersInput),
-    CancelAllOrders(CancelAllOrdersInput),
-    GetDepth(GetDepthInput),
+pub struct TwoFactorEnrollResponse {
+    pub secret: String,
+    pub provisioning_uri: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CreateUserInput {
-    pub user_id: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pubsub_id: Option<Uuid>,
+pub struct TwoFactorCodeInput {
+    pub code: String,
 }
 
+// The recovery codes are only ever returned here
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum UserRequests {
-    CreateUser(CreateUserInput),
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
 }
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS markets;
+DROP TABLE IF EXISTS assets;
@@ -0,0 +1,26 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS assets (
+    symbol VARCHAR PRIMARY KEY,
+    precision INTEGER NOT NULL,
+    withdrawal_fee NUMERIC NOT NULL DEFAULT 0,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE IF NOT EXISTS markets (
+    symbol VARCHAR PRIMARY KEY,
+    base_asset VARCHAR NOT NULL REFERENCES assets(symbol),
+    quote_asset VARCHAR NOT NULL REFERENCES assets(symbol),
+    enabled BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+INSERT INTO assets(symbol, precision, withdrawal_fee, enabled) VALUES
+    ('USDC', 6, 1, TRUE),
+    ('USDT', 6, 1, TRUE),
+    ('BTC', 8, 0.0002, TRUE),
+    ('ETH', 8, 0.002, TRUE),
+    ('SOL', 8, 0.01, TRUE)
+ON CONFLICT (symbol) DO NOTHING;
+
+INSERT INTO markets(symbol, base_asset, quote_asset, enabled) VALUES
+    ('SOL_USDC', 'SOL', 'USDC', TRUE)
+ON CONFLICT (symbol) DO NOTHING;
@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- Only the SHA-256 of the secret is stored - it doubles as the HMAC signing key
+CREATE TABLE IF NOT EXISTS api_keys (
+    key_id VARCHAR PRIMARY KEY,
+    user_id VARCHAR NOT NULL,
+    secret_hash VARCHAR NOT NULL,
+    created_at BIGINT NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_user_id_idx ON api_keys (user_id);
@@ -0,0 +1,17 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS users (
+    user_id VARCHAR PRIMARY KEY,
+    email VARCHAR NOT NULL UNIQUE,
+    password_hash VARCHAR NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+-- Refresh tokens are stored as SHA-256 hashes, rotated on every use
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    token_hash VARCHAR PRIMARY KEY,
+    user_id VARCHAR NOT NULL REFERENCES users(user_id),
+    expires_at BIGINT NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_id_idx ON refresh_tokens (user_id);
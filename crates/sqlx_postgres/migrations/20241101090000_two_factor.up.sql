@@ -0,0 +1,13 @@
+-- Add up migration script here
+-- The TOTP secret is pending until the user confirms it with a first code
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_secret VARCHAR;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Recovery codes are stored as SHA-256 hashes and can each be used once
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    code_hash VARCHAR PRIMARY KEY,
+    user_id VARCHAR NOT NULL REFERENCES users(user_id),
+    used BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS recovery_codes_user_id_idx ON recovery_codes (user_id);
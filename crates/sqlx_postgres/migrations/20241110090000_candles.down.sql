@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS trades_market_timestamp_idx;
+DROP TABLE IF EXISTS candles;
@@ -0,0 +1,22 @@
+-- Add up migration script here
+-- Candles of the base intervals (1m, 1h, 1d), updated by db-processor with every trade it inserts.
+-- Longer intervals are rolled up from them when queried
+CREATE TABLE IF NOT EXISTS candles (
+    market VARCHAR NOT NULL,
+    period VARCHAR NOT NULL,
+    open_time BIGINT NOT NULL,
+    open NUMERIC NOT NULL,
+    high NUMERIC NOT NULL,
+    low NUMERIC NOT NULL,
+    close NUMERIC NOT NULL,
+    volume NUMERIC NOT NULL,
+    quote_volume NUMERIC NOT NULL,
+    trades BIGINT NOT NULL,
+    -- Trade ids grow with time, they decide which trade opens and closes a candle
+    first_trade_id BIGINT NOT NULL,
+    last_trade_id BIGINT NOT NULL,
+    PRIMARY KEY (market, period, open_time)
+);
+
+-- Backfills and the engine's replay on start read a market's trades by time
+CREATE INDEX IF NOT EXISTS trades_market_timestamp_idx ON trades (market, timestamp);
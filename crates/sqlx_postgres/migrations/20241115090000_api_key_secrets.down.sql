@@ -0,0 +1,5 @@
+-- Add down migration script here
+-- Encrypted secrets can't be turned back into hashes, the keys stay revoked
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS secret_hash VARCHAR NOT NULL DEFAULT '';
+UPDATE api_keys SET revoked = TRUE;
+ALTER TABLE api_keys DROP COLUMN IF EXISTS secret_encrypted;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- API key secrets are stored encrypted with the router's API_KEY_ENCRYPTION_KEY.
+-- Keys created before only kept the SHA-256 of their secret, which could sign requests by itself,
+-- so they are revoked and have to be created again
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS secret_encrypted VARCHAR;
+UPDATE api_keys SET revoked = TRUE WHERE secret_encrypted IS NULL;
+UPDATE api_keys SET secret_encrypted = '' WHERE secret_encrypted IS NULL;
+ALTER TABLE api_keys ALTER COLUMN secret_encrypted SET NOT NULL;
+ALTER TABLE api_keys DROP COLUMN IF EXISTS secret_hash;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+-- Fails once two markets share a trade id, those trades have to be renumbered first
+ALTER TABLE trades DROP CONSTRAINT IF EXISTS trades_market_trade_id_pkey;
+ALTER TABLE trades ADD CONSTRAINT trades_pkey PRIMARY KEY (trade_id);
@@ -0,0 +1,10 @@
+-- Add up migration script here
+-- The engine counts trade ids per market, so the same id shows up once in every market.
+-- Trades are keyed by both, checked first because this runs on every start
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'trades_market_trade_id_pkey') THEN
+        ALTER TABLE trades DROP CONSTRAINT IF EXISTS trades_pkey;
+        ALTER TABLE trades ADD CONSTRAINT trades_market_trade_id_pkey PRIMARY KEY (market, trade_id);
+    END IF;
+END $$;
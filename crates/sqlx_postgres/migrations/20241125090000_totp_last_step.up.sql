@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- The 30 second step of the last TOTP code the user passed, so a code can't be replayed
+-- within its validity window
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_last_step BIGINT;
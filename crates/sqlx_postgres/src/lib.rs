@@ -33,6 +33,50 @@ impl PostgresDb {
         .execute(&pool)
         .await?;
 
+        // Asset and market registry, seeded with the default listings
+        sqlx::raw_sql(include_str!("../migrations/20241015090000_assets.up.sql"))
+            .execute(&pool)
+            .await?;
+
+        // API keys used to sign router requests
+        sqlx::raw_sql(include_str!("../migrations/20241020090000_api_keys.up.sql"))
+            .execute(&pool)
+            .await?;
+
+        // Registered users and their refresh tokens
+        sqlx::raw_sql(include_str!("../migrations/20241025090000_users.up.sql"))
+            .execute(&pool)
+            .await?;
+
+        // TOTP two factor authentication for registered users
+        sqlx::raw_sql(include_str!("../migrations/20241101090000_two_factor.up.sql"))
+            .execute(&pool)
+            .await?;
+
+        // API key secrets encrypted at rest instead of hashed
+        sqlx::raw_sql(include_str!(
+            "../migrations/20241115090000_api_key_secrets.up.sql"
+        ))
+        .execute(&pool)
+        .await?;
+
+        // Pre-aggregated candles for GET /klines
+        sqlx::raw_sql(include_str!("../migrations/20241110090000_candles.up.sql"))
+            .execute(&pool)
+            .await?;
+
+        // Trade ids are counted per market, so trades are keyed by market and id
+        sqlx::raw_sql(include_str!("../migrations/20241120090000_trade_ids.up.sql"))
+            .execute(&pool)
+            .await?;
+
+        // The last TOTP step each user passed, to reject replayed codes
+        sqlx::raw_sql(include_str!(
+            "../migrations/20241125090000_totp_last_step.up.sql"
+        ))
+        .execute(&pool)
+        .await?;
+
         Ok(Self { pool })
     }
 
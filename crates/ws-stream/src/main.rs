@@ -1,5 +1,6 @@
 use fred::prelude::*;
 use futures_util::StreamExt;
+use protocol::ws_stream::WsResponse;
 use std::io::Error;
 use std::{sync::Arc, thread};
 use tokio::net::{TcpListener, TcpStream};
@@ -12,7 +13,7 @@ pub mod user;
 pub mod ws_manager;
 
 use user::User;
-use ws_manager::WsManager;
+use ws_manager::{WsManager, DEPTH_REFRESH_INTERVAL};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -22,7 +23,7 @@ async fn main() -> Result<(), Error> {
     let try_socket = TcpListener::bind(&addr).await;
     let listener = try_socket.expect("Failed to bind");
 
-    let ws_manager = Arc::new(Mutex::new(WsManager::new().await));
+    let ws_manager = Arc::new(Mutex::new(WsManager::new().await.map_err(Error::other)?));
 
     let ws_manager_clone = ws_manager.clone();
     thread::spawn(move || {
@@ -32,6 +33,11 @@ async fn main() -> Result<(), Error> {
     // thread::spawn can't work with async functions directly since it doesn't understand Futures.
     // By creating a new tokio runtime inside the thread, we can execute the async task within this non-async context.
 
+    // Cancel on disconnect is an engine countdown, refreshed while the connections are open
+    tokio::spawn(refresh_disconnect_countdowns(ws_manager.clone()));
+    // The engine forgets partial book depth streams that are not asked for again
+    tokio::spawn(refresh_depth_subscriptions(ws_manager.clone()));
+
     // Accept new connections in a loop
     while let Ok((stream, _)) = listener.accept().await {
         tokio::spawn(accept_connection(stream, ws_manager.clone()));
@@ -72,8 +78,6 @@ async fn accept_connection(stream: TcpStream, ws_manager: Arc<Mutex<WsManager>>)
                     }
                 } else if msg.is_close() {
                     println!("Closing Connection to user with addr: {}", user_addr);
-                    let mut manager = ws_manager.lock().await;
-                    manager.remove_user(&user_addr.to_string());
                     break;
                 }
             }
@@ -92,13 +96,15 @@ async fn accept_connection(stream: TcpStream, ws_manager: Arc<Mutex<WsManager>>)
                         println!("WebSocket error from {}: {:?}", user_addr, e);
                     }
                 }
-                // Remove user and break the loop on error
-                let mut manager = ws_manager.lock().await;
-                manager.remove_user(&user_addr.to_string());
+                // Break the loop on error, the user is removed below
                 break;
             }
         }
     }
+
+    // Closed, failed or dropped without a close frame - remove the user either way
+    let mut manager = ws_manager.lock().await;
+    manager.disconnect_user(&user_addr.to_string()).await;
 }
 
 async fn process_data(data: WsMessage, user_addr: &str, ws_manager: Arc<Mutex<WsManager>>) {
@@ -111,10 +117,34 @@ async fn process_data(data: WsMessage, user_addr: &str, ws_manager: Arc<Mutex<Ws
         "UNSUBSCRIBE" => {
             manager.unsubscribe(user_addr, data).await;
         }
+        "SET_CANCEL_ON_DISCONNECT" => {
+            manager.set_cancel_on_disconnect(user_addr, data).await;
+        }
         _ => {}
     }
 }
 
+async fn refresh_disconnect_countdowns(ws_manager: Arc<Mutex<WsManager>>) {
+    let refresh_interval = ws_manager.lock().await.disconnect_refresh_interval();
+    let mut interval = tokio::time::interval(refresh_interval);
+
+    loop {
+        interval.tick().await;
+        let manager = ws_manager.lock().await;
+        manager.refresh_disconnect_countdowns().await;
+    }
+}
+
+async fn refresh_depth_subscriptions(ws_manager: Arc<Mutex<WsManager>>) {
+    let mut interval = tokio::time::interval(DEPTH_REFRESH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        let manager = ws_manager.lock().await;
+        manager.refresh_depth_subscriptions().await;
+    }
+}
+
 async fn process_redis_message(ws_manager: Arc<Mutex<WsManager>>) {
     let mut message_stream;
 
@@ -135,8 +165,17 @@ async fn process_redis_message(ws_manager: Arc<Mutex<WsManager>>) {
             }
         };
 
+        // Envelopes are unwrapped here - clients only ever see the stream message itself
+        let ws_response = match protocol::decode::<WsResponse>(&publisher_message) {
+            Ok(ws_response) => ws_response,
+            Err(e) => {
+                println!("Dropping Redis Publisher message - {}", e);
+                continue;
+            }
+        };
+
         // Lock the manager only when you need to send the message
         let mut manager = ws_manager.lock().await;
-        manager.send_to_ws_stream(publisher_message).await;
+        manager.send_to_ws_stream(ws_response).await;
     }
 }
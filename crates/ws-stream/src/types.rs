@@ -1,10 +1,10 @@
+use db_processor::registry::AssetRegistry;
+use protocol::klines::KlineInterval;
+use protocol::orders::{DepthSubscription, DEPTH_LIMITS};
+use protocol::ws_stream::ALL_TICKERS_STREAM;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct WsResponse {
-    pub stream: String,
-    pub data: serde_json::Value, // any kind of JSON-like data - initialise using serde_json::json! macro
-}
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct WsMessage {
@@ -14,69 +14,114 @@ pub struct WsMessage {
 }
 
 impl WsMessage {
-    pub fn parse_subscription(&self) -> Option<(SubscriptionType, SupportedAssetPairs)> {
+    // Markets are validated against the asset registry instead of a fixed list of pairs
+    pub fn parse_subscription(
+        &self,
+        registry: &AssetRegistry,
+    ) -> Option<(SubscriptionType, String)> {
         if self.params.is_empty() {
             return None;
         }
 
+        // A depth step has a decimal point of its own, so split off the type and market only
         let subscription_id = &self.params[0];
-        let parts: Vec<&str> = subscription_id.split('.').collect();
 
-        if parts.len() != 2 {
+        // The only stream that is not per market
+        if subscription_id == ALL_TICKERS_STREAM {
+            return Some((SubscriptionType::all_tickers, String::new()));
+        }
+
+        let parts: Vec<&str> = subscription_id.splitn(3, '.').collect();
+
+        if parts.len() < 2 {
             return None;
         }
 
         let subscription_type_str = parts[0];
-        let asset_pair_str = parts[1];
+        let market_str = parts[1];
+
+        let subscription_type = SubscriptionType::parse(subscription_type_str)?;
+
+        // depth.<market>.<limit> or depth.<market>.<limit>.<step> - partial book depth
+        if let Some(depth_params) = parts.get(2) {
+            if !matches!(subscription_type, SubscriptionType::depth) {
+                return None;
+            }
+
+            let market = registry.market(market_str).ok()?;
+            let subscription = parse_depth_subscription(&market.symbol, depth_params)?;
 
-        let subscription_type = SubscriptionType::from_str(subscription_type_str)?;
-        let asset_pair = SupportedAssetPairs::from_str(asset_pair_str).ok()?;
+            return Some((
+                SubscriptionType::partial_depth(subscription),
+                market.symbol.clone(),
+            ));
+        }
+
+        // Private streams carry a listen key instead of a market, it is resolved by the manager
+        if let SubscriptionType::user = subscription_type {
+            return Some((subscription_type, market_str.to_string()));
+        }
 
-        Some((subscription_type, asset_pair))
+        let market = registry.market(market_str).ok()?;
+
+        Some((subscription_type, market.symbol.clone()))
     }
 }
 
+fn parse_depth_subscription(symbol: &str, depth_params: &str) -> Option<DepthSubscription> {
+    let (limit, step) = match depth_params.split_once('.') {
+        Some((limit, step)) => (limit, Some(Decimal::from_str(step).ok()?)),
+        None => (depth_params, None),
+    };
+
+    let limit = limit.parse::<usize>().ok()?;
+    if !DEPTH_LIMITS.contains(&limit) || step.is_some_and(|step| step <= Decimal::ZERO) {
+        return None;
+    }
+
+    Some(DepthSubscription {
+        symbol: symbol.to_string(),
+        limit,
+        step: step.map(|step| step.normalize()),
+    })
+}
+
 #[derive(Debug, Clone)]
 pub enum SubscriptionType {
     #[allow(non_camel_case_types)]
     depth,
     #[allow(non_camel_case_types)]
+    partial_depth(DepthSubscription),
+    #[allow(non_camel_case_types)]
     trade,
     #[allow(non_camel_case_types)]
     ticker,
+    #[allow(non_camel_case_types)]
+    l3,
+    #[allow(non_camel_case_types)]
+    bookTicker,
+    #[allow(non_camel_case_types)]
+    all_tickers,
+    #[allow(non_camel_case_types)]
+    kline(KlineInterval),
+    #[allow(non_camel_case_types)]
+    user,
 }
 
 impl SubscriptionType {
-    pub fn from_str(s: &str) -> Option<Self> {
+    pub fn parse(s: &str) -> Option<Self> {
         match s {
             "depth" => Some(SubscriptionType::depth),
             "trade" => Some(SubscriptionType::trade),
             "ticker" => Some(SubscriptionType::ticker),
-            _ => None,
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub enum SupportedAssetPairs {
-    #[allow(non_camel_case_types)]
-    BTC_USDT,
-    #[allow(non_camel_case_types)]
-    ETH_USDT,
-    #[allow(non_camel_case_types)]
-    SOL_USDT,
-    #[allow(non_camel_case_types)]
-    SOL_USDC,
-}
-
-impl SupportedAssetPairs {
-    pub fn from_str(asset_pair_str: &str) -> Result<SupportedAssetPairs, &'static str> {
-        match asset_pair_str {
-            "BTC_USDT" => Ok(SupportedAssetPairs::BTC_USDT),
-            "ETH_USDT" => Ok(SupportedAssetPairs::ETH_USDT),
-            "SOL_USDT" => Ok(SupportedAssetPairs::SOL_USDT),
-            "SOL_USDC" => Ok(SupportedAssetPairs::SOL_USDC),
-            _ => Err("Unsupported asset pair"),
+            "l3" => Some(SubscriptionType::l3),
+            "bookTicker" => Some(SubscriptionType::bookTicker),
+            "user" => Some(SubscriptionType::user),
+            // kline_<interval>, the interval is case sensitive - 1m is a minute and 1M a month
+            _ => s
+                .strip_prefix("kline_")
+                .and_then(KlineInterval::parse)
+                .map(SubscriptionType::kline),
         }
     }
 }
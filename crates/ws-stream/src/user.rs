@@ -6,10 +6,17 @@ use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 pub struct User {
     pub id: String,
     pub ws_stream: SplitSink<WebSocketStream<TcpStream>, Message>,
+    pub account_id: Option<String>, // set once the connection subscribed with a valid listen key
+    pub cancel_on_disconnect: bool, // cancel all of the account's orders when the connection closes
 }
 
 impl User {
     pub fn new(id: String, ws_stream: SplitSink<WebSocketStream<TcpStream>, Message>) -> Self {
-        Self { id, ws_stream }
+        Self {
+            id,
+            ws_stream,
+            account_id: None,
+            cancel_on_disconnect: false,
+        }
     }
 }
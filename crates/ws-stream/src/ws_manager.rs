@@ -1,28 +1,59 @@
+use db_processor::registry::AssetRegistry;
+use fred::interfaces::PubsubInterface;
 use futures_util::SinkExt;
-use redis::RedisManager;
+use protocol::orders::{
+    CountdownCancelAll, CountdownSource, DepthSubscription, OrderRequests, MIN_COUNTDOWN_MS,
+};
+use protocol::ws_stream::{
+    kline_stream, listen_key, partial_depth_stream, user_stream, WsResponse, ALL_TICKERS_STREAM,
+};
+use redis::{MessageBus, RedisManager, RedisQueues};
+use sqlx_postgres::PostgresDb;
 use tokio_tungstenite::tungstenite::Message;
 
 use crate::{
-    types::{WsMessage, WsResponse},
+    types::{SubscriptionType, WsMessage},
     user::User,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+// How long the engine waits after the last refresh before it cancels the account's orders,
+// CANCEL_ON_DISCONNECT_MS overrides it
+pub const DEFAULT_DISCONNECT_COUNTDOWN_MS: u64 = 10_000;
+// Well within DEPTH_SUBSCRIPTION_TTL_MS, the engine drops partial book depth streams not refreshed
+pub const DEPTH_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct WsManager {
     pub users: HashMap<String, User>,
     pub subscriptions: HashMap<String, Vec<String>>, // user_id -> [subscription_id]
     pub reverse_subscriptions: HashMap<String, Vec<String>>, // subscription_id -> [user_id]
+    pub depth_streams: HashMap<String, DepthSubscription>, // subscription_id -> registered depth
     pub redis_connection: RedisManager,
+    pub registry: AssetRegistry,
+    pub disconnect_countdown_ms: u64,
 }
 
 impl WsManager {
-    pub async fn new() -> Self {
-        Self {
+    // Postgres is only needed to load the asset registry on start
+    pub async fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let postgres = PostgresDb::new().await?;
+        let pg_pool = postgres.get_pg_connection()?;
+
+        Ok(Self {
             users: HashMap::new(),
             subscriptions: HashMap::new(),
             reverse_subscriptions: HashMap::new(),
-            redis_connection: RedisManager::new().await.unwrap(),
-        }
+            depth_streams: HashMap::new(),
+            redis_connection: RedisManager::new().await?,
+            registry: AssetRegistry::load(&pg_pool).await?,
+            disconnect_countdown_ms: disconnect_countdown_ms()?,
+        })
+    }
+
+    // Several refreshes fit in one countdown, so a slow one doesn't cancel anything
+    pub fn disconnect_refresh_interval(&self) -> Duration {
+        Duration::from_millis(self.disconnect_countdown_ms / 3)
     }
 
     pub fn add_user(&mut self, user: User) {
@@ -38,17 +69,126 @@ impl WsManager {
         }
     }
 
+    // Removes the connection and releases its subscriptions. With cancel on disconnect its
+    // countdown is no longer refreshed, so the engine cancels the account's orders once it runs out
+    pub async fn disconnect_user(&mut self, id: &str) {
+        if let Some(account_id) = self
+            .users
+            .get(id)
+            .filter(|user| user.cancel_on_disconnect)
+            .and_then(|user| user.account_id.as_ref())
+        {
+            println!(
+                "Connection of {} closed, its orders are cancelled within {} ms",
+                account_id, self.disconnect_countdown_ms
+            );
+        }
+
+        let subscriptions = self.subscriptions.get(id).cloned().unwrap_or_default();
+        for subscription_id in subscriptions {
+            self.release_subscription(id, &subscription_id).await;
+        }
+
+        self.remove_user(id);
+    }
+
+    // {"method":"SET_CANCEL_ON_DISCONNECT","params":["true"],"id":1}
+    // Only for connections authenticated with a listen key, the orders of that account are cancelled
+    pub async fn set_cancel_on_disconnect(&mut self, user_id: &str, message: WsMessage) {
+        let enabled = match message.params.first().map(String::as_str) {
+            Some("true") => true,
+            Some("false") => false,
+            _ => {
+                eprintln!("Invalid cancel on disconnect value: {:?}", message.params);
+                return;
+            }
+        };
+
+        let account_id = match self.users.get_mut(user_id) {
+            Some(user) if user.account_id.is_some() => {
+                user.cancel_on_disconnect = enabled;
+                user.account_id.clone().unwrap_or_default()
+            }
+            _ => {
+                eprintln!(
+                    "Cancel on disconnect needs a user stream subscription first, from {}",
+                    user_id
+                );
+                return;
+            }
+        };
+
+        // Another connection of the account may still want its orders cancelled
+        if enabled {
+            self.push_countdown(&account_id, self.disconnect_countdown_ms)
+                .await;
+        } else if !self.countdown_accounts().contains(&account_id) {
+            self.push_countdown(&account_id, 0).await;
+        }
+    }
+
+    // Accounts with at least one connection that cancels on disconnect
+    fn countdown_accounts(&self) -> HashSet<String> {
+        self.users
+            .values()
+            .filter(|user| user.cancel_on_disconnect)
+            .filter_map(|user| user.account_id.clone())
+            .collect()
+    }
+
+    // Keeps the engine's countdown of every such account running while its connections are open
+    pub async fn refresh_disconnect_countdowns(&self) {
+        for account_id in self.countdown_accounts() {
+            self.push_countdown(&account_id, self.disconnect_countdown_ms)
+                .await;
+        }
+    }
+
+    // Its own countdown next to the one of POST /countdownCancelAll, 0 switches it off
+    async fn push_countdown(&self, account_id: &str, countdown_time: u64) {
+        let countdown_request = OrderRequests::CountdownCancelAll(CountdownCancelAll {
+            countdown_time,
+            user_id: account_id.to_string(),
+            source: CountdownSource::Disconnect,
+            pubsub_id: None,
+        });
+
+        if let Err(e) = self
+            .redis_connection
+            .push(RedisQueues::ORDERS, protocol::encode(&countdown_request))
+            .await
+        {
+            eprintln!("Failed to refresh the countdown of {} - {}", account_id, e);
+        }
+    }
+
     // {"method":"SUBSCRIBE","params":["trade.BTC_USDT"],"id":1}
+    // {"method":"SUBSCRIBE","params":["user.<listenKey>"],"id":1} - private order and balance updates
     pub async fn subscribe(&mut self, user_id: &str, message: WsMessage) {
         if message.method == "SUBSCRIBE" {
-            let (subscription_type, asset_pair) = match message.parse_subscription() {
+            let (subscription_type, target) = match message.parse_subscription(&self.registry) {
                 Some(result) => result,
                 None => {
                     eprintln!("Invalid subscription format: {:?}", message.params);
                     return;
                 }
             };
-            let subscription_id = format!("{:?}.{:?}", subscription_type, asset_pair);
+
+            let subscription_id = match subscription_type {
+                SubscriptionType::user => match self.authenticate(user_id, &target).await {
+                    Some(account_id) => user_stream(&account_id),
+                    None => {
+                        eprintln!("Invalid or expired listen key from {}", user_id);
+                        return;
+                    }
+                },
+                SubscriptionType::partial_depth(ref depth) => {
+                    partial_depth_stream(&depth.symbol, depth.limit, depth.step)
+                }
+                SubscriptionType::all_tickers => ALL_TICKERS_STREAM.to_string(),
+                SubscriptionType::kline(interval) => kline_stream(interval, &target),
+                _ => format!("{:?}.{}", subscription_type, target),
+            };
 
             if let Some(subscriptions) = self.subscriptions.get_mut(user_id) {
                 subscriptions.push(subscription_id.clone());
@@ -64,46 +204,120 @@ impl WsManager {
                     .insert(subscription_id.clone(), vec![user_id.to_string()]);
 
                 self.redis_connection
+                    .subscriber
                     .subscribe(subscription_id.as_str())
                     .await
                     .expect("Failed to subscribe in redis");
+
+                // The engine only publishes partial book depth streams someone listens to
+                if let SubscriptionType::partial_depth(depth) = subscription_type {
+                    self.depth_streams
+                        .insert(subscription_id.clone(), depth.clone());
+                    self.register_depth(OrderRequests::SubscribeDepth(depth))
+                        .await;
+                }
             }
         }
     }
 
+    // Resolve a listen key handed out by the router to the account it belongs to
+    async fn authenticate(&mut self, user_id: &str, key: &str) -> Option<String> {
+        let account_id = match self.redis_connection.get(&listen_key(key)).await {
+            Ok(account_id) => account_id?,
+            Err(e) => {
+                eprintln!("Failed to look up listen key - {}", e);
+                return None;
+            }
+        };
+
+        let user = self.users.get_mut(user_id)?;
+        user.account_id = Some(account_id.clone());
+
+        Some(account_id)
+    }
+
     // {"method":"UNSUBSCRIBE","params":["trade.BTC_USDT"],"id":1}
     pub async fn unsubscribe(&mut self, user_id: &str, message: WsMessage) {
         if message.method == "UNSUBSCRIBE" {
-            let (subscription_type, asset_pair) = match message.parse_subscription() {
+            let (subscription_type, target) = match message.parse_subscription(&self.registry) {
                 Some(result) => result,
                 None => {
                     eprintln!("Invalid unsubscription format: {:?}", message.params);
                     return;
                 }
             };
-            let subscription_id = format!("{:?}.{:?}", subscription_type, asset_pair);
 
-            if let Some(subscriptions) = self.subscriptions.get_mut(user_id) {
-                subscriptions.retain(|id| id != &subscription_id);
-            }
+            // The listen key may have expired in the meantime, so go by the account it resolved to
+            let subscription_id = match subscription_type {
+                SubscriptionType::user => {
+                    match self
+                        .users
+                        .get(user_id)
+                        .and_then(|user| user.account_id.clone())
+                    {
+                        Some(account_id) => user_stream(&account_id),
+                        None => return,
+                    }
+                }
+                SubscriptionType::partial_depth(ref depth) => {
+                    partial_depth_stream(&depth.symbol, depth.limit, depth.step)
+                }
+                SubscriptionType::all_tickers => ALL_TICKERS_STREAM.to_string(),
+                SubscriptionType::kline(interval) => kline_stream(interval, &target),
+                _ => format!("{:?}.{}", subscription_type, target),
+            };
 
-            if let Some(users) = self.reverse_subscriptions.get_mut(&subscription_id) {
-                users.retain(|id| id != user_id);
+            self.release_subscription(user_id, &subscription_id).await;
+        }
+    }
 
-                if users.is_empty() {
-                    self.reverse_subscriptions.remove(&subscription_id);
-                    self.redis_connection
-                        .unsubscribe(subscription_id.as_str())
-                        .await
-                        .expect("Failed to unsubscribe in redis");
+    // Drops one subscription of a connection, the last subscriber of a stream leaves it in redis
+    // and in the engine
+    async fn release_subscription(&mut self, user_id: &str, subscription_id: &str) {
+        if let Some(subscriptions) = self.subscriptions.get_mut(user_id) {
+            subscriptions.retain(|id| id != subscription_id);
+        }
+
+        if let Some(users) = self.reverse_subscriptions.get_mut(subscription_id) {
+            users.retain(|id| id != user_id);
+
+            if users.is_empty() {
+                self.reverse_subscriptions.remove(subscription_id);
+                self.redis_connection
+                    .subscriber
+                    .unsubscribe(subscription_id)
+                    .await
+                    .expect("Failed to unsubscribe in redis");
+
+                if let Some(depth) = self.depth_streams.remove(subscription_id) {
+                    self.register_depth(OrderRequests::UnsubscribeDepth(depth))
+                        .await;
                 }
             }
         }
     }
 
+    // Keeps the partial book depth streams registered, also with an engine that restarted since
+    pub async fn refresh_depth_subscriptions(&self) {
+        for depth in self.depth_streams.values() {
+            self.register_depth(OrderRequests::SubscribeDepth(depth.clone()))
+                .await;
+        }
+    }
+
+    async fn register_depth(&self, request: OrderRequests) {
+        if let Err(e) = self
+            .redis_connection
+            .push(RedisQueues::ORDERS, protocol::encode(&request))
+            .await
+        {
+            eprintln!("Failed to register partial book depth - {}", e);
+        }
+    }
+
     // {"data":{"E":1727866324128584,"T":1727866324088922,"U":4977146,"a":[["1.0003","0"]],"b":[],"e":"depth","s":"BTC_USDT","u":4977146},"stream":"depth.BTC_USDT"}
-    pub async fn send_to_ws_stream(&mut self, message: String) {
-        let ws_message: WsResponse = serde_json::from_str(message.as_str()).unwrap();
+    pub async fn send_to_ws_stream(&mut self, ws_message: WsResponse) {
+        let message = serde_json::to_string(&ws_message).unwrap();
 
         if let Some(users) = self.reverse_subscriptions.get(ws_message.stream.as_str()) {
             for user_id in users {
@@ -118,3 +332,20 @@ impl WsManager {
         }
     }
 }
+
+fn disconnect_countdown_ms() -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let countdown_ms = match std::env::var("CANCEL_ON_DISCONNECT_MS") {
+        Ok(value) => value.parse()?,
+        Err(_) => DEFAULT_DISCONNECT_COUNTDOWN_MS,
+    };
+
+    if countdown_ms < MIN_COUNTDOWN_MS {
+        return Err(format!(
+            "CANCEL_ON_DISCONNECT_MS must be at least {} ms, got {}",
+            MIN_COUNTDOWN_MS, countdown_ms
+        )
+        .into());
+    }
+
+    Ok(countdown_ms)
+}